@@ -0,0 +1,110 @@
+/// A topology of FM operators. The operators are numbered from one like on the DX7,
+/// operator `n` is the oscillator `OSC<n>`.
+#[derive(Debug)]
+pub struct Algorithm {
+    pub name: &'static str,
+    pub operators: usize,
+    pub modulations: &'static [(usize, usize)], // pairs of modulator and carrier
+    pub feedback: (usize, usize),               // modulator and carrier of the feedback loop
+    pub carriers: &'static [usize],             // operators that are audible
+}
+
+macro_rules! algorithm {
+    ($name:expr, $operators:expr, [$($modulation:expr),*], $feedback:expr, [$($carrier:expr),*]) => {
+        Algorithm {
+            name: $name,
+            operators: $operators,
+            modulations: &[$($modulation),*],
+            feedback: $feedback,
+            carriers: &[$($carrier),*],
+        }
+    };
+}
+
+/// The algorithms of the four operator Yamaha synthesizers like the DX21 and TX81Z,
+/// followed by the 32 algorithms of the DX7.
+#[rustfmt::skip]
+pub const ALGORITHMS: &[Algorithm] = &[
+    algorithm!("4OP-1", 4, [(4, 3), (3, 2), (2, 1)], (4, 4), [1]),
+    algorithm!("4OP-2", 4, [(3, 2), (4, 2), (2, 1)], (4, 4), [1]),
+    algorithm!("4OP-3", 4, [(3, 2), (2, 1), (4, 1)], (4, 4), [1]),
+    algorithm!("4OP-4", 4, [(4, 3), (3, 1), (2, 1)], (4, 4), [1]),
+    algorithm!("4OP-5", 4, [(2, 1), (4, 3)], (4, 4), [1, 3]),
+    algorithm!("4OP-6", 4, [(4, 1), (4, 2), (4, 3)], (4, 4), [1, 2, 3]),
+    algorithm!("4OP-7", 4, [(4, 3)], (4, 4), [1, 2, 3]),
+    algorithm!("4OP-8", 4, [], (4, 4), [1, 2, 3, 4]),
+    algorithm!("DX7-1", 6, [(2, 1), (6, 5), (5, 4), (4, 3)], (6, 6), [1, 3]),
+    algorithm!("DX7-2", 6, [(2, 1), (6, 5), (5, 4), (4, 3)], (2, 2), [1, 3]),
+    algorithm!("DX7-3", 6, [(3, 2), (2, 1), (6, 5), (5, 4)], (6, 6), [1, 4]),
+    algorithm!("DX7-4", 6, [(3, 2), (2, 1), (6, 5), (5, 4)], (4, 6), [1, 4]),
+    algorithm!("DX7-5", 6, [(2, 1), (4, 3), (6, 5)], (6, 6), [1, 3, 5]),
+    algorithm!("DX7-6", 6, [(2, 1), (4, 3), (6, 5)], (5, 6), [1, 3, 5]),
+    algorithm!("DX7-7", 6, [(2, 1), (4, 3), (5, 3), (6, 5)], (6, 6), [1, 3]),
+    algorithm!("DX7-8", 6, [(2, 1), (4, 3), (5, 3), (6, 5)], (4, 4), [1, 3]),
+    algorithm!("DX7-9", 6, [(2, 1), (4, 3), (5, 3), (6, 5)], (2, 2), [1, 3]),
+    algorithm!("DX7-10", 6, [(3, 2), (2, 1), (5, 4), (6, 4)], (3, 3), [1, 4]),
+    algorithm!("DX7-11", 6, [(3, 2), (2, 1), (5, 4), (6, 4)], (6, 6), [1, 4]),
+    algorithm!("DX7-12", 6, [(2, 1), (4, 3), (5, 3), (6, 3)], (2, 2), [1, 3]),
+    algorithm!("DX7-13", 6, [(2, 1), (4, 3), (5, 3), (6, 3)], (6, 6), [1, 3]),
+    algorithm!("DX7-14", 6, [(2, 1), (4, 3), (5, 4), (6, 4)], (6, 6), [1, 3]),
+    algorithm!("DX7-15", 6, [(2, 1), (4, 3), (5, 4), (6, 4)], (2, 2), [1, 3]),
+    algorithm!("DX7-16", 6, [(2, 1), (3, 1), (5, 1), (4, 3), (6, 5)], (6, 6), [1]),
+    algorithm!("DX7-17", 6, [(2, 1), (3, 1), (5, 1), (4, 3), (6, 5)], (2, 2), [1]),
+    algorithm!("DX7-18", 6, [(2, 1), (3, 1), (4, 1), (5, 4), (6, 5)], (3, 3), [1]),
+    algorithm!("DX7-19", 6, [(3, 2), (2, 1), (6, 4), (6, 5)], (6, 6), [1, 4, 5]),
+    algorithm!("DX7-20", 6, [(3, 1), (3, 2), (5, 4), (6, 4)], (3, 3), [1, 2, 4]),
+    algorithm!("DX7-21", 6, [(3, 1), (3, 2), (6, 4), (6, 5)], (3, 3), [1, 2, 4, 5]),
+    algorithm!("DX7-22", 6, [(2, 1), (6, 3), (6, 4), (6, 5)], (6, 6), [1, 3, 4, 5]),
+    algorithm!("DX7-23", 6, [(3, 2), (6, 4), (6, 5)], (6, 6), [1, 2, 4, 5]),
+    algorithm!("DX7-24", 6, [(6, 3), (6, 4), (6, 5)], (6, 6), [1, 2, 3, 4, 5]),
+    algorithm!("DX7-25", 6, [(6, 4), (6, 5)], (6, 6), [1, 2, 3, 4, 5]),
+    algorithm!("DX7-26", 6, [(3, 2), (5, 4), (6, 4)], (6, 6), [1, 2, 4]),
+    algorithm!("DX7-27", 6, [(3, 2), (5, 4), (6, 4)], (3, 3), [1, 2, 4]),
+    algorithm!("DX7-28", 6, [(2, 1), (5, 4), (4, 3)], (5, 5), [1, 3, 6]),
+    algorithm!("DX7-29", 6, [(4, 3), (6, 5)], (6, 6), [1, 2, 3, 5]),
+    algorithm!("DX7-30", 6, [(5, 4), (4, 3)], (5, 5), [1, 2, 3, 6]),
+    algorithm!("DX7-31", 6, [(6, 5)], (6, 6), [1, 2, 3, 4, 5]),
+    algorithm!("DX7-32", 6, [], (6, 6), [1, 2, 3, 4, 5, 6]),
+];
+
+/// Returns the algorithm with the given name.
+pub fn find_algorithm(name: &str) -> Option<&'static Algorithm> {
+    ALGORITHMS.iter().find(|algorithm| algorithm.name == name)
+}
+
+#[test]
+fn test_algorithms() {
+    assert_eq!(ALGORITHMS.len(), 8 + 32);
+    for algorithm in ALGORITHMS {
+        let valid = |op: usize| op > 0 && op <= algorithm.operators;
+        assert!(!algorithm.carriers.is_empty(), "{}", algorithm.name);
+        assert!(algorithm.carriers.iter().all(|op| valid(*op)));
+        let (modulator, carrier) = algorithm.feedback;
+        assert!(valid(modulator) && valid(carrier), "{}", algorithm.name);
+        for &(modulator, carrier) in algorithm.modulations {
+            assert!(valid(modulator) && valid(carrier), "{}", algorithm.name);
+            // modulators are never audible and only modulate lower operators
+            assert!(modulator > carrier, "{}", algorithm.name);
+            assert!(
+                !algorithm.carriers.contains(&modulator),
+                "{}",
+                algorithm.name
+            );
+        }
+        // every operator is either audible or modulates another one
+        for op in 1..algorithm.operators + 1 {
+            assert!(
+                algorithm.carriers.contains(&op)
+                    || algorithm
+                        .modulations
+                        .iter()
+                        .any(|&(modulator, _)| modulator == op),
+                "{}: {}",
+                algorithm.name,
+                op
+            );
+        }
+    }
+    assert_eq!(find_algorithm("DX7-32").unwrap().carriers.len(), 6);
+    assert!(find_algorithm("DX7-33").is_none());
+}
@@ -0,0 +1,367 @@
+extern crate rand;
+
+use std::collections::VecDeque;
+
+use dsp::{Clock, Transport};
+use event::{ControlEvent, Controllable};
+use types::*;
+
+pub const MAX_OCTAVES: usize = 4;
+
+/// The order in which the held notes are played.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum ArpeggiatorMode {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+/// Turns the held notes into a sequence of `NoteOn` and `NoteOff` events.
+/// The events are queued and must be fetched with `next_event` after each tick.
+/// If the arpeggiator is disabled all note events are passed through unchanged.
+/// The steps follow the tempo of the shared `Clock`, also while the transport is stopped.
+pub struct Arpeggiator {
+    enabled: bool,
+    mode: ArpeggiatorMode,
+    octaves: usize,
+    gate: Float,  // note length relative to the step length
+    swing: Float, // delay of every second step relative to half the step length
+    latch: bool,
+    division: Float,            // step length in beats
+    held: Vec<(u8, Float)>,     // held keys and velocities in the order they were played
+    pattern: Vec<(u8, Float)>,  // arpeggiated keys, differs from `held` only if latched
+    sequence: Vec<(u8, Float)>, // the ordered pattern in all octaves
+    position: Float,            // beats since the pattern was started
+    grid: Float,                // position of the next step without swing
+    next_step: Float,
+    note_off: Float,
+    step: usize,
+    playing: Option<u8>,
+    events: VecDeque<ControlEvent>,
+}
+impl Arpeggiator {
+    /// Advances the arpeggiator by the beats that passed in the current clock tick.
+    pub fn tick(&mut self, clock: &Clock) {
+        if self.enabled {
+            // align the steps with the beginning of the song
+            if clock.transport() == Some(Transport::Start) && !self.pattern.is_empty() {
+                self.release();
+                self.restart();
+            }
+            self.advance(clock.beats());
+        }
+    }
+
+    /// Returns the next queued note event.
+    pub fn next_event(&mut self) -> Option<ControlEvent> {
+        self.events.pop_front()
+    }
+
+    fn advance(&mut self, beats: Float) {
+        if self.pattern.is_empty() {
+            return;
+        }
+        self.position += beats;
+        if self.playing.is_some() && self.position >= self.note_off {
+            self.release();
+        }
+        if self.position >= self.next_step {
+            self.play_step();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.position = 0.0;
+        self.grid = 0.0;
+        self.next_step = 0.0;
+        self.step = 0;
+    }
+
+    fn play_step(&mut self) {
+        self.release();
+        let start = self.next_step;
+        if let Some((key, velocity)) = self.next_note() {
+            self.events.push_back(ControlEvent::NoteOn { key, velocity });
+            self.playing = Some(key);
+        }
+        self.step += 1;
+        self.grid += self.division;
+        self.next_step = if self.step % 2 == 1 {
+            self.grid + self.swing * self.division / 2.0
+        } else {
+            self.grid
+        };
+        self.note_off = start + self.gate * (self.next_step - start);
+    }
+
+    fn release(&mut self) {
+        if let Some(key) = self.playing.take() {
+            self.events.push_back(ControlEvent::NoteOff { key, velocity: 0.0 });
+        }
+    }
+
+    /// Orders the pattern and repeats it in the higher octaves. The steps only index
+    /// into the sequence, so it is rebuilt when the pattern, mode or octaves change.
+    fn update_sequence(&mut self) {
+        self.sequence.clear();
+        self.sequence.extend_from_slice(&self.pattern);
+        if self.mode != ArpeggiatorMode::AsPlayed {
+            self.sequence.sort_unstable_by_key(|&(key, _)| key);
+        }
+        let len = self.sequence.len();
+        for octave in 1..self.octaves {
+            for idx in 0..len {
+                let (key, velocity) = self.sequence[idx];
+                let transposed = key as usize + 12 * octave;
+                if transposed < 128 {
+                    self.sequence.push((transposed as u8, velocity));
+                }
+            }
+        }
+    }
+
+    /// Returns the key and velocity for the current step.
+    fn next_note(&self) -> Option<(u8, Float)> {
+        let sequence = &self.sequence;
+        if sequence.is_empty() {
+            return None;
+        }
+        let len = sequence.len();
+        let idx = match self.mode {
+            ArpeggiatorMode::Up | ArpeggiatorMode::AsPlayed => self.step % len,
+            ArpeggiatorMode::Down => len - 1 - self.step % len,
+            ArpeggiatorMode::UpDown => {
+                if len < 2 {
+                    0
+                } else {
+                    // don't repeat the highest and lowest note
+                    let period = 2 * len - 2;
+                    let idx = self.step % period;
+                    if idx < len {
+                        idx
+                    } else {
+                        period - idx
+                    }
+                }
+            }
+            ArpeggiatorMode::Random => rand::random::<usize>() % len,
+        };
+        Some(sequence[idx])
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.enabled {
+            return;
+        }
+        self.enabled = enabled;
+        if enabled {
+            // the held keys were passed through and have to be stopped
+            for &(key, _) in &self.held {
+                self.events.push_back(ControlEvent::NoteOff { key, velocity: 0.0 });
+            }
+            self.pattern = self.held.clone();
+            self.restart();
+        } else {
+            self.release();
+            self.pattern = self.held.clone();
+        }
+    }
+
+    fn set_latch(&mut self, latch: bool) {
+        self.latch = latch;
+        if !latch {
+            let held = &self.held;
+            self.pattern
+                .retain(|&(key, _)| held.iter().any(|&(held_key, _)| held_key == key));
+            if self.pattern.is_empty() {
+                self.release();
+            }
+        }
+    }
+}
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Arpeggiator {
+            enabled: false,
+            mode: ArpeggiatorMode::Up,
+            octaves: 1,
+            gate: 0.5,
+            swing: 0.0,
+            latch: false,
+            division: 0.25,
+            held: Vec::new(),
+            pattern: Vec::new(),
+            sequence: Vec::new(),
+            position: 0.0,
+            grid: 0.0,
+            next_step: 0.0,
+            note_off: 0.0,
+            step: 0,
+            playing: None,
+            events: VecDeque::new(),
+        }
+    }
+}
+impl Controllable for Arpeggiator {
+    fn handle(&mut self, msg: &ControlEvent) {
+        match *msg {
+            ControlEvent::NoteOn { key, velocity } => {
+                if !self.enabled {
+                    self.events.push_back(msg.clone());
+                }
+                let latched = self.latch && self.enabled;
+                if latched && self.held.is_empty() {
+                    // a new chord replaces the latched one
+                    self.pattern.clear();
+                }
+                let was_empty = self.pattern.is_empty();
+                self.held.retain(|&(held_key, _)| held_key != key);
+                self.held.push((key, velocity));
+                self.pattern.retain(|&(pattern_key, _)| pattern_key != key);
+                self.pattern.push((key, velocity));
+                if was_empty && self.enabled {
+                    self.restart();
+                }
+                self.update_sequence();
+            }
+            ControlEvent::NoteOff { key, .. } => {
+                if !self.enabled {
+                    self.events.push_back(msg.clone());
+                }
+                self.held.retain(|&(held_key, _)| held_key != key);
+                if !(self.latch && self.enabled) {
+                    self.pattern.retain(|&(pattern_key, _)| pattern_key != key);
+                }
+                if self.pattern.is_empty() {
+                    self.release();
+                }
+                self.update_sequence();
+            }
+            ControlEvent::Arpeggiator {
+                enabled,
+                mode,
+                octaves,
+                division,
+                gate,
+                swing,
+                latch,
+            } => {
+                if let Some(some_mode) = mode {
+                    self.mode = some_mode;
+                }
+                if let Some(some_octaves) = octaves {
+                    self.octaves = some_octaves.max(1).min(MAX_OCTAVES);
+                }
+                if let Some(some_division) = division {
+                    if some_division > 0.0 {
+                        self.division = some_division;
+                    }
+                }
+                if let Some(some_gate) = gate {
+                    self.gate = some_gate.max(0.01).min(1.0);
+                }
+                if let Some(some_swing) = swing {
+                    self.swing = some_swing.max(0.0).min(1.0);
+                }
+                if let Some(some_latch) = latch {
+                    self.set_latch(some_latch);
+                }
+                if let Some(some_enabled) = enabled {
+                    self.set_enabled(some_enabled);
+                }
+                self.update_sequence();
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+fn arpeggiator_event(enabled: bool, mode: ArpeggiatorMode) -> ControlEvent {
+    ControlEvent::Arpeggiator {
+        enabled: Some(enabled),
+        mode: Some(mode),
+        octaves: None,
+        division: None,
+        gate: None,
+        swing: None,
+        latch: None,
+    }
+}
+
+#[test]
+fn test_arpeggiator_steps() {
+    const SAMPLE_RATE: usize = 48_000;
+    let mut arp = Arpeggiator::default();
+    let mut clock = Clock::new(SAMPLE_RATE);
+    arp.handle(&arpeggiator_event(true, ArpeggiatorMode::Up));
+    clock.handle(&ControlEvent::Tempo(120.0));
+    for key in &[64, 60] {
+        arp.handle(&ControlEvent::NoteOn {
+            key: *key,
+            velocity: 1.0,
+        });
+    }
+    // 1/16 steps at 120 bpm last 6000 samples, the gate is half of it
+    let mut events = Vec::new();
+    for idx in 0..12_000 {
+        clock.tick();
+        arp.tick(&clock);
+        while let Some(event) = arp.next_event() {
+            events.push((idx, event));
+        }
+    }
+    let expected = [(0, 60, true), (3000, 60, false), (6000, 64, true), (9000, 64, false)];
+    assert_eq!(events.len(), expected.len());
+    for (&(idx, ref event), &(expected_idx, expected_key, on)) in events.iter().zip(&expected) {
+        assert!((idx as isize - expected_idx as isize).abs() <= 1);
+        match *event {
+            ControlEvent::NoteOn { key, .. } => assert!(on && key == expected_key),
+            ControlEvent::NoteOff { key, .. } => assert!(!on && key == expected_key),
+            _ => panic!("unexpected event: {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn test_arpeggiator_latch() {
+    let mut arp = Arpeggiator::default();
+    let mut clock = Clock::new(48_000);
+    arp.handle(&arpeggiator_event(true, ArpeggiatorMode::Down));
+    arp.handle(&ControlEvent::Arpeggiator {
+        enabled: None,
+        mode: None,
+        octaves: Some(2),
+        division: None,
+        gate: Some(1.0),
+        swing: None,
+        latch: Some(true),
+    });
+    arp.handle(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    arp.handle(&ControlEvent::NoteOff {
+        key: 60,
+        velocity: 0.0,
+    });
+    assert_eq!(arp.sequence, vec![(60, 1.0), (72, 1.0)]);
+    // one beat at 120 bpm, the transport start realigns the steps
+    let mut keys = Vec::new();
+    for idx in 0..24_000 {
+        if idx == 100 {
+            clock.handle(&ControlEvent::Transport(Transport::Start));
+        }
+        clock.tick();
+        arp.tick(&clock);
+        while let Some(event) = arp.next_event() {
+            if let ControlEvent::NoteOn { key, .. } = event {
+                keys.push(key);
+            }
+        }
+    }
+    // the released key is still played because it is latched
+    assert_eq!(keys, vec![72, 72, 60, 72, 60]);
+}
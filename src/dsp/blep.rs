@@ -0,0 +1,153 @@
+extern crate rustfft;
+
+use std::sync::Arc;
+
+use self::rustfft::num_complex::Complex;
+use self::rustfft::num_traits::Zero;
+use self::rustfft::FFTplanner;
+
+use types::*;
+
+/// Zero crossings of the windowed sinc on each side, the corrections of a
+/// discontinuity last for this many samples.
+pub const BLEP_LEN: usize = 16;
+/// Table entries per sample.
+const BLEP_OVERSAMPLING: usize = 32;
+/// Cutoff of the windowed sinc relative to the nyquist frequency.
+const CUTOFF: Float = 0.9;
+const INVERSE: bool = true;
+
+/// Returns the residual of a minimum phase band-limited step, which is the difference
+/// between the band-limited and the naive unit step, see Eli Brandt's "Hard Sync
+/// Without Aliasing". The minimum phase step has no pre-ringing, so that a
+/// discontinuity can be corrected after it occured.
+pub fn minblep_table() -> Arc<Vec<Float>> {
+    let sinc_len = 2 * BLEP_LEN * BLEP_OVERSAMPLING + 1;
+    let size = 4 * sinc_len.next_power_of_two();
+    let center = (sinc_len / 2) as Float;
+    let mut signal = vec![Complex::zero(); size];
+    for (idx, sample) in signal.iter_mut().take(sinc_len).enumerate() {
+        let x = CUTOFF * (idx as Float - center) / BLEP_OVERSAMPLING as Float;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            Float::sin(PI * x) / (PI * x)
+        };
+        let phase = 2.0 * PI * idx as Float / (sinc_len - 1) as Float;
+        let blackman = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+        *sample = Complex::new(sinc * blackman, 0.0);
+    }
+    let mut planner = FFTplanner::new(!INVERSE);
+    let fft = planner.plan_fft(size);
+    let mut inverse_planner = FFTplanner::new(INVERSE);
+    let ifft = inverse_planner.plan_fft(size);
+    let mut spectrum = vec![Complex::zero(); size];
+
+    // real cepstrum of the windowed sinc
+    fft.process(&mut signal, &mut spectrum);
+    for bin in &mut spectrum {
+        *bin = Complex::new(bin.norm().max(1e-9).ln(), 0.0);
+    }
+    let mut cepstrum = vec![Complex::zero(); size];
+    ifft.process(&mut spectrum, &mut cepstrum);
+    // folding the cepstrum onto the positive quefrencies makes the signal minimum phase
+    for (idx, value) in cepstrum.iter_mut().enumerate() {
+        let scale = match idx {
+            0 => 1.0,
+            idx if idx < size / 2 => 2.0,
+            idx if idx == size / 2 => 1.0,
+            _ => 0.0,
+        };
+        *value *= scale / size as Float;
+    }
+    fft.process(&mut cepstrum, &mut spectrum);
+    for bin in &mut spectrum {
+        *bin = bin.exp();
+    }
+    ifft.process(&mut spectrum, &mut signal);
+
+    // the integrated impulse is the band-limited step, the ringing of the residual is
+    // faded out at the end of the table
+    let len = BLEP_LEN * BLEP_OVERSAMPLING + 1;
+    let fade_start = 3 * len / 4;
+    let total = signal.iter().fold(0.0, |acc, sample| acc + sample.re);
+    let mut step = 0.0;
+    Arc::new(
+        signal
+            .iter()
+            .take(len)
+            .enumerate()
+            .map(|(idx, sample)| {
+                step += sample.re;
+                let fade = if idx > fade_start {
+                    let phase = PI * (idx - fade_start) as Float / (len - 1 - fade_start) as Float;
+                    0.5 * (1.0 + phase.cos())
+                } else {
+                    1.0
+                };
+                (step / total - 1.0) * fade
+            }).collect(),
+    )
+}
+
+/// Accumulates the corrections of discontinuities in a signal.
+pub struct MinBlep {
+    table: Arc<Vec<Float>>,
+    residual: [Float; BLEP_LEN],
+    pos: usize,
+}
+impl MinBlep {
+    pub fn new(table: Arc<Vec<Float>>) -> Self {
+        MinBlep {
+            table,
+            residual: [0.0; BLEP_LEN],
+            pos: 0,
+        }
+    }
+
+    /// Adds the correction of a step of size `delta` that occured `offset` samples
+    /// before the next sample, the offset is in the range `[0.0, 1.0]`.
+    pub fn add(&mut self, offset: Float, delta: Float) {
+        let last = self.table.len() - 1;
+        for idx in 0..BLEP_LEN {
+            let position = (idx as Float + offset) * BLEP_OVERSAMPLING as Float;
+            let table_idx = position as usize;
+            if table_idx >= last {
+                break;
+            }
+            let fraction = position - table_idx as Float;
+            let (current, next) = (self.table[table_idx], self.table[table_idx + 1]);
+            self.residual[(self.pos + idx) % BLEP_LEN] +=
+                delta * (current + (next - current) * fraction);
+        }
+    }
+
+    /// Returns the correction for the next sample.
+    pub fn tick(&mut self) -> Float {
+        let correction = self.residual[self.pos];
+        self.residual[self.pos] = 0.0;
+        self.pos = (self.pos + 1) % BLEP_LEN;
+        correction
+    }
+}
+
+#[test]
+fn test_minblep() {
+    let table = minblep_table();
+    assert_eq!(table.len(), BLEP_LEN * BLEP_OVERSAMPLING + 1);
+    // the band-limited step starts at zero and settles at one
+    assert_relative_eq!(table[0], -1.0, epsilon = 0.01);
+    assert_relative_eq!(table[table.len() - 1], 0.0, epsilon = 1e-9);
+
+    // the corrected step rises over several samples
+    let mut blep = MinBlep::new(table);
+    blep.add(0.3, 1.0);
+    let step = (0..2 * BLEP_LEN)
+        .map(|_| 1.0 + blep.tick())
+        .collect::<Vec<_>>();
+    let largest_jump = step
+        .windows(2)
+        .fold(0.0, |acc: Float, pair| acc.max((pair[1] - pair[0]).abs()));
+    assert!(largest_jump < 0.8, "{}", largest_jump);
+    assert_relative_eq!(step[step.len() - 1], 1.0);
+}
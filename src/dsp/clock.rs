@@ -0,0 +1,223 @@
+use event::{ControlEvent, Controllable};
+use types::*;
+
+/// Resolution of the MIDI clock in pulses per quarter note.
+const MIDI_CLOCK_PPQN: Float = 24.0;
+/// Time without clock pulses after which the internal tempo is used again.
+const MIDI_CLOCK_TIMEOUT: Time = 0.5;
+/// Weight of a new pulse interval in the smoothed pulse interval.
+const INTERVAL_SMOOTHING: Float = 0.05;
+/// Time constant for pulling the song position towards the MIDI clock.
+const PHASE_CORRECTION_TIME: Time = 0.1;
+
+/// Changes of the transport state.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum Transport {
+    /// Starts playback at the beginning of the song.
+    Start,
+    Stop,
+    /// Resumes playback at the current song position.
+    Continue,
+    /// Moves the song position to the given beat.
+    SongPosition(Float),
+}
+
+/// The shared tempo and transport source for all tempo-aware components.
+///
+/// The clock runs at the internal tempo unless MIDI clock pulses are received,
+/// in which case the tempo is derived from the smoothed pulse intervals and the
+/// song position follows the pulses. Components query the clock once per tick:
+/// `beats` is the free-running tempo, which also advances while the transport
+/// is stopped, `position` is the song position, which only advances while the
+/// transport is running.
+pub struct Clock {
+    sample_rate: usize,
+    tempo: Float,                  // internal tempo in beats per minute
+    pulse_interval: Option<Float>, // smoothed time between MIDI clock pulses in ms
+    last_pulse: Option<u32>,       // timestamp of the last MIDI clock pulse
+    idle: usize,                   // ticks since the last MIDI clock pulse
+    running: bool,
+    position: Float,       // song position in beats
+    pulse_position: Float, // song position according to the MIDI clock
+    drift: Float,          // phase error to the MIDI clock that still has to be corrected
+    beats: Float,          // beats advanced in the current tick
+    pending: Option<Transport>,
+    transport: Option<Transport>,
+}
+impl Clock {
+    pub fn new(sample_rate: usize) -> Self {
+        Clock {
+            sample_rate,
+            tempo: 120.0,
+            pulse_interval: None,
+            last_pulse: None,
+            idle: 0,
+            running: false,
+            position: 0.0,
+            pulse_position: 0.0,
+            drift: 0.0,
+            beats: 0.0,
+            pending: None,
+            transport: None,
+        }
+    }
+
+    /// Advances the clock by one sample.
+    pub fn tick(&mut self) {
+        self.transport = self.pending.take();
+        if self.last_pulse.is_some() {
+            self.idle += 1;
+            if self.idle as Time > MIDI_CLOCK_TIMEOUT * self.sample_rate as Time {
+                // the clock source has gone away
+                self.pulse_interval = None;
+                self.last_pulse = None;
+                self.drift = 0.0;
+            }
+        }
+        let mut beats = self.tempo() / (60.0 * self.sample_rate as Float);
+        if self.running && self.drift != 0.0 {
+            let ticks = PHASE_CORRECTION_TIME * self.sample_rate as Time;
+            let correction = (self.drift / Float::from(ticks)).max(-beats);
+            self.drift -= correction;
+            beats += correction;
+        }
+        self.beats = beats;
+        if self.running {
+            self.position += beats;
+        }
+    }
+
+    /// Returns the current tempo in beats per minute.
+    pub fn tempo(&self) -> Float {
+        match self.pulse_interval {
+            Some(interval) => 60_000.0 / (MIDI_CLOCK_PPQN * interval),
+            None => self.tempo,
+        }
+    }
+
+    /// Returns the number of beats that passed in the current tick.
+    pub fn beats(&self) -> Float {
+        self.beats
+    }
+
+    /// Returns the song position in beats.
+    pub fn position(&self) -> Float {
+        self.position
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns the transport change that happened in the current tick.
+    pub fn transport(&self) -> Option<Transport> {
+        self.transport
+    }
+
+    fn pulse(&mut self, timestamp: u32) {
+        if let Some(last) = self.last_pulse {
+            let interval = Float::from(timestamp.wrapping_sub(last));
+            if interval > 0.0 {
+                self.pulse_interval = Some(match self.pulse_interval {
+                    // follow sudden tempo changes immediately
+                    Some(smoothed) if interval < 2.0 * smoothed && interval > 0.5 * smoothed => {
+                        smoothed + INTERVAL_SMOOTHING * (interval - smoothed)
+                    }
+                    _ => interval,
+                });
+            }
+        }
+        self.last_pulse = Some(timestamp);
+        self.idle = 0;
+        if self.running {
+            self.pulse_position += MIDI_CLOCK_PPQN.recip();
+            self.drift = self.pulse_position - self.position;
+        }
+    }
+
+    fn locate(&mut self, position: Float) {
+        self.position = position.max(0.0);
+        // the next pulse marks the new position
+        self.pulse_position = self.position - MIDI_CLOCK_PPQN.recip();
+        self.drift = 0.0;
+    }
+
+    fn change_transport(&mut self, transport: Transport) {
+        match transport {
+            Transport::Start => {
+                self.locate(0.0);
+                self.running = true;
+            }
+            Transport::Stop => self.running = false,
+            Transport::Continue => self.running = true,
+            Transport::SongPosition(position) => self.locate(position),
+        }
+        self.pending = Some(transport);
+    }
+}
+impl Controllable for Clock {
+    fn handle(&mut self, msg: &ControlEvent) {
+        match *msg {
+            ControlEvent::Tempo(bpm) => {
+                if bpm > 0.0 {
+                    self.tempo = bpm;
+                }
+            }
+            ControlEvent::ClockTick(timestamp) => self.pulse(timestamp),
+            ControlEvent::Transport(transport) => self.change_transport(transport),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn test_clock_internal_tempo() {
+    let mut clock = Clock::new(48_000);
+    clock.handle(&ControlEvent::Tempo(90.0));
+    clock.tick();
+    assert_relative_eq!(clock.beats(), 1.5 / 48_000.0);
+    // the song position only advances while the transport is running
+    assert_relative_eq!(clock.position(), 0.0);
+    clock.handle(&ControlEvent::Transport(Transport::Start));
+    clock.tick();
+    assert_eq!(clock.transport(), Some(Transport::Start));
+    for _ in 1..48_000 {
+        clock.tick();
+    }
+    assert_eq!(clock.transport(), None);
+    assert_relative_eq!(clock.position(), 1.5, epsilon = 1e-9);
+    clock.handle(&ControlEvent::Transport(Transport::SongPosition(8.0)));
+    clock.handle(&ControlEvent::Transport(Transport::Stop));
+    clock.tick();
+    assert!(!clock.running());
+    assert_relative_eq!(clock.position(), 8.0);
+}
+
+#[test]
+fn test_clock_midi_sync() {
+    const SAMPLE_RATE: usize = 48_000;
+    let mut clock = Clock::new(SAMPLE_RATE);
+    clock.handle(&ControlEvent::Transport(Transport::Start));
+    // 100 bpm are 25ms between pulses, the pulses have a jitter of up to 4ms
+    // and are delivered in bursts every 20ms
+    let mut pulse = 0;
+    for period in 0..480 {
+        while pulse * 25 < (period + 1) * 20 {
+            let jitter = [0, 3, 1, 4, 2][pulse as usize % 5];
+            clock.handle(&ControlEvent::ClockTick(1000 + pulse * 25 + jitter));
+            pulse += 1;
+        }
+        for _ in 0..(SAMPLE_RATE / 50) {
+            clock.tick();
+        }
+    }
+    assert!(clock.pulse_interval.is_some());
+    assert_relative_eq!(clock.tempo(), 100.0, epsilon = 1.0);
+    assert_relative_eq!(clock.position(), 16.0, epsilon = 0.1);
+    // the internal tempo is used again when the pulses stop
+    for _ in 0..SAMPLE_RATE {
+        clock.tick();
+    }
+    assert!(clock.pulse_interval.is_none());
+    assert_relative_eq!(clock.tempo(), 120.0);
+}
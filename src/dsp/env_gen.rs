@@ -3,6 +3,19 @@ use std::default::Default;
 use event::{ControlEvent, Controllable};
 use types::*;
 
+/// Shortest duration of a stage, faster changes of the level would click.
+const MIN_STAGE_TIME: Time = 0.001;
+const MAX_STAGE_TIME: Time = 60.0;
+/// Fade time of segment envelopes whose last breakpoint is above zero.
+const FADE_OUT_TIME: Time = 0.005;
+/// Steepness of the exponential and logarithmic curves with an amount of `1.0`.
+const MAX_CURVE_STEEPNESS: Float = 16.0;
+pub const MAX_VELOCITY_RANGE: Float = 60.0; // dB
+/// Scale of the attack and decay times of the hardest notes with a velocity time of `1.0`.
+const MIN_VELOCITY_TIME_SCALE: Time = 0.25;
+/// Lowest key of the rate scaling, the times of lower keys are unchanged.
+const RATE_SCALING_KEY: u8 = 21;
+
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum ADSRState {
     Attack,
@@ -20,6 +33,316 @@ impl ADSRState {
             ADSRState::Release | ADSRState::Off => ADSRState::Off,
         }
     }
+
+    /// Returns true while the note of the envelope is held.
+    fn gated(self) -> bool {
+        match self {
+            ADSRState::Attack | ADSRState::Decay | ADSRState::Sustain => true,
+            ADSRState::Release | ADSRState::Off => false,
+        }
+    }
+}
+
+/// The shape of the level change of an envelope stage. The amount of the exponential
+/// and logarithmic curves is in the range `[0.0, 1.0]`, an amount of `0.0` is linear.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum EnvelopeCurve {
+    Linear,
+    /// Changes fast at the start of the stage and slowly approaches the target level
+    /// like a charging capacitor.
+    Exponential(Float),
+    /// Changes slowly at the start of the stage and fast at its end.
+    Logarithmic(Float),
+}
+impl EnvelopeCurve {
+    /// Maps the progress of a stage in the range `[0.0, 1.0]` to the covered fraction
+    /// of the level change.
+    fn shape(self, progress: Float) -> Float {
+        match self {
+            EnvelopeCurve::Linear => progress,
+            EnvelopeCurve::Exponential(amount) => {
+                let steepness = MAX_CURVE_STEEPNESS * amount;
+                if steepness < 1.0E-3 {
+                    progress
+                } else {
+                    (1.0 - Float::exp(-steepness * progress)) / (1.0 - Float::exp(-steepness))
+                }
+            }
+            EnvelopeCurve::Logarithmic(amount) => {
+                1.0 - EnvelopeCurve::Exponential(amount).shape(1.0 - progress)
+            }
+        }
+    }
+
+    fn clamped(self) -> Self {
+        match self {
+            EnvelopeCurve::Linear => EnvelopeCurve::Linear,
+            EnvelopeCurve::Exponential(amount) => {
+                EnvelopeCurve::Exponential(amount.max(0.0).min(1.0))
+            }
+            EnvelopeCurve::Logarithmic(amount) => {
+                EnvelopeCurve::Logarithmic(amount.max(0.0).min(1.0))
+            }
+        }
+    }
+}
+
+/// How an envelope reacts to a new note while it is still running.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum RetriggerMode {
+    /// Restarts the attack at zero.
+    Reset,
+    /// Restarts the attack at the current level.
+    Continue,
+    /// Restarts the attack at the current level only if the previous note was released,
+    /// a held envelope keeps its stage and velocity.
+    Legato,
+}
+
+/// Maps the velocity of a note in the range `[0.0, 1.0]` to the envelope level.
+#[derive(Debug, Clone, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum VelocityCurve {
+    /// The level rises linearly from the bottom of the dynamic range.
+    Linear,
+    /// The level in dB rises linearly over the dynamic range.
+    Exponential,
+    /// Interpolates the position in the dynamic range from the table entries, which are
+    /// in the range `[0.0, 1.0]` and evenly spread over the velocities.
+    Table(Vec<Float>),
+}
+impl VelocityCurve {
+    /// Returns the level for a note, the range is the dynamic range in dB.
+    fn gain(&self, velocity: Float, range: Float) -> Float {
+        let velocity = velocity.max(0.0).min(1.0);
+        let in_range =
+            |position: Float| Float::from_db((1.0 - position.max(0.0).min(1.0)) * -range);
+        match *self {
+            VelocityCurve::Linear => {
+                let floor = Float::from_db(-range);
+                floor + (1.0 - floor) * velocity
+            }
+            VelocityCurve::Exponential => in_range(velocity),
+            VelocityCurve::Table(ref table) => match table.len() {
+                0 => in_range(velocity),
+                1 => in_range(table[0]),
+                len => {
+                    let position = velocity * (len - 1) as Float;
+                    let idx = (position as usize).min(len - 2);
+                    let fraction = position - idx as Float;
+                    in_range(table[idx] + (table[idx + 1] - table[idx]) * fraction)
+                }
+            },
+        }
+    }
+}
+
+/// A breakpoint of a multi-segment envelope. The segment that ends at the breakpoint
+/// moves from the previous level to `level` in `time` seconds with the given curve.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct Breakpoint {
+    pub time: Time,
+    pub level: Float, // in the range `[0.0, 1.0]`
+    pub curve: EnvelopeCurve,
+}
+
+/// An envelope with an arbitrary number of segments. While the note is held the
+/// envelope stops at the sustain point, or repeats the segments after the loop start
+/// up to the loop end. Releasing the note continues with the segment after the sustain
+/// point or the loop end, every segment starts at the current level. After the last
+/// breakpoint the envelope fades out.
+pub struct SegmentEnvelope {
+    sample_rate: usize,
+    breakpoints: Vec<Breakpoint>,
+    sustain: Option<usize>,
+    loop_points: Option<(usize, usize)>,
+    state: ADSRState,
+    gated: bool,
+    segment: usize, // index of the breakpoint that ends the current segment
+    stage_ticks: usize,
+    ticks_left: usize,
+    level: Float,
+    start_level: Float,
+    time_scale: Time,    // scales the segments while the note is held
+    release_scale: Time, // scales the segments after the note was released
+}
+impl SegmentEnvelope {
+    pub fn new(sample_rate: usize) -> Self {
+        SegmentEnvelope {
+            sample_rate,
+            breakpoints: Vec::new(),
+            sustain: None,
+            loop_points: None,
+            state: ADSRState::Off,
+            gated: false,
+            segment: 0,
+            stage_ticks: 0,
+            ticks_left: 0,
+            level: 0.0,
+            start_level: 0.0,
+            time_scale: 1.0,
+            release_scale: 1.0,
+        }
+    }
+
+    /// Replaces the breakpoints, out of range values are clamped and invalid sustain
+    /// and loop points are dropped. A running envelope keeps its position.
+    pub fn set(
+        &mut self,
+        breakpoints: &[Breakpoint],
+        sustain: Option<usize>,
+        loop_points: Option<(usize, usize)>,
+    ) {
+        self.breakpoints = breakpoints
+            .iter()
+            .map(|breakpoint| Breakpoint {
+                time: breakpoint.time.max(MIN_STAGE_TIME).min(MAX_STAGE_TIME),
+                level: breakpoint.level.max(0.0).min(1.0),
+                curve: breakpoint.curve.clamped(),
+            }).collect();
+        let len = self.breakpoints.len();
+        self.sustain = sustain.filter(|&sustain| sustain < len);
+        self.loop_points = loop_points.filter(|&(start, end)| start <= end && end < len);
+        if self.segment >= len && self.state != ADSRState::Off {
+            self.fade_out();
+        }
+    }
+
+    pub fn tick(&mut self) -> Float {
+        match self.state {
+            ADSRState::Off | ADSRState::Sustain => self.level,
+            _ => {
+                let (level, curve) = self.target();
+                if self.ticks_left == 0 {
+                    self.level = level;
+                    self.next_segment();
+                    self.tick()
+                } else {
+                    let progress =
+                        (self.stage_ticks - self.ticks_left) as Float / self.stage_ticks as Float;
+                    self.level =
+                        self.start_level + (level - self.start_level) * curve.shape(progress);
+                    self.ticks_left -= 1;
+                    self.level
+                }
+            }
+        }
+    }
+
+    /// Returns `Sustain` while the envelope holds the sustain point, `Attack` for the
+    /// other segments while the note is held and `Release` after it was released.
+    pub fn state(&self) -> ADSRState {
+        self.state
+    }
+
+    pub fn level(&self) -> Float {
+        self.level
+    }
+
+    pub fn reset(&mut self) {
+        self.state = ADSRState::Off;
+        self.gated = false;
+        self.ticks_left = 0;
+        self.level = 0.0;
+    }
+
+    /// Starts the first segment, the segments are scaled by `time_scale` until the note
+    /// is released and by `release_scale` afterwards.
+    pub fn note_on(&mut self, time_scale: Time, release_scale: Time) {
+        if !self.breakpoints.is_empty() {
+            self.gated = true;
+            self.time_scale = time_scale;
+            self.release_scale = release_scale;
+            self.start_segment(0);
+        }
+    }
+
+    pub fn note_off(&mut self) {
+        self.gated = false;
+        if self.state == ADSRState::Off {
+            return;
+        }
+        let release_point = self
+            .sustain
+            .or_else(|| self.loop_points.map(|(_, end)| end));
+        match release_point {
+            Some(point) if self.segment <= point => {
+                if point + 1 < self.breakpoints.len() {
+                    self.start_segment(point + 1)
+                } else {
+                    self.finish()
+                }
+            }
+            _ => self.state = ADSRState::Release,
+        }
+    }
+
+    /// Continues after the current segment has reached its breakpoint.
+    fn next_segment(&mut self) {
+        let reached = self.segment;
+        if self.gated && self.sustain == Some(reached) {
+            self.state = ADSRState::Sustain;
+            return;
+        }
+        if let Some((start, end)) = self.loop_points {
+            if self.gated && end == reached {
+                if start == end {
+                    self.state = ADSRState::Sustain;
+                } else {
+                    self.start_segment(start + 1);
+                }
+                return;
+            }
+        }
+        if reached + 1 < self.breakpoints.len() {
+            self.start_segment(reached + 1)
+        } else {
+            self.finish()
+        }
+    }
+
+    /// Returns the level and curve of the current segment, the segment after the last
+    /// breakpoint fades out.
+    fn target(&self) -> (Float, EnvelopeCurve) {
+        self.breakpoints
+            .get(self.segment)
+            .map_or((0.0, EnvelopeCurve::Linear), |breakpoint| {
+                (breakpoint.level, breakpoint.curve)
+            })
+    }
+
+    /// Stops after the last breakpoint, a level above zero is faded out first, so that
+    /// the voice doesn't stop with a click.
+    fn finish(&mut self) {
+        if self.level > 0.0 && self.segment < self.breakpoints.len() {
+            self.fade_out();
+        } else {
+            self.state = ADSRState::Off;
+            self.level = 0.0;
+        }
+    }
+
+    fn fade_out(&mut self) {
+        self.segment = self.breakpoints.len();
+        self.state = ADSRState::Release;
+        self.stage_ticks = ::std::cmp::max(1, (FADE_OUT_TIME * self.sample_rate as Time) as usize);
+        self.ticks_left = self.stage_ticks;
+        self.start_level = self.level;
+    }
+
+    fn start_segment(&mut self, segment: usize) {
+        self.segment = segment;
+        let (state, scale) = if self.gated {
+            (ADSRState::Attack, self.time_scale)
+        } else {
+            (ADSRState::Release, self.release_scale)
+        };
+        self.state = state;
+        // every segment lasts at least a tick, so that loops always advance
+        let time = self.breakpoints[segment].time * scale;
+        self.stage_ticks = ::std::cmp::max(1, (time * self.sample_rate as Time) as usize);
+        self.ticks_left = self.stage_ticks;
+        self.start_level = self.level;
+    }
 }
 
 pub struct ADSR {
@@ -28,12 +351,25 @@ pub struct ADSR {
     decay: Time,
     sustain: Float,
     release: Time,
+    attack_curve: EnvelopeCurve,
+    decay_curve: EnvelopeCurve,
+    release_curve: EnvelopeCurve,
+    retrigger: RetriggerMode,
     state: ADSRState,
+    stage_ticks: usize,
     ticks_left: usize,
-    gain: Float,
     velocity: Float,
+    velocity_curve: VelocityCurve,
+    velocity_range: Float,       // dB
+    velocity_sensitivity: Float, // scales the velocity range
+    velocity_time: Float,        // how much harder notes shorten the attack and decay
+    rate_scaling: Float,         // how much higher keys shorten the times
+    time_scale: Time,            // of the attack and decay of the current note
+    release_scale: Time,         // of the release of the current note
     level: Float,
+    start_level: Float,
     target_level: Float,
+    segments: Option<SegmentEnvelope>, // replaces the stages if set
     id: String,
 }
 impl ADSR {
@@ -50,16 +386,36 @@ impl ADSR {
     }
 
     pub fn tick(&mut self) -> Float {
-        self.velocity * match self.state {
+        let level = match self.segments {
+            Some(ref mut segments) => segments.tick(),
+            None => self.next_level(),
+        };
+        self.velocity * level
+    }
+
+    fn next_level(&mut self) -> Float {
+        match self.state {
             ADSRState::Off => 0.0,
-            ADSRState::Sustain => self.level,
+            ADSRState::Sustain => {
+                self.level = self.sustain;
+                self.level
+            }
             _ => {
                 if self.ticks_left == 0 {
+                    self.level = self.target_level;
                     let next_state = self.state.progress();
                     self.state_change(next_state);
-                    self.tick()
+                    self.next_level()
                 } else {
-                    self.level = self.target_level * self.gain + (1.0 - self.gain) * self.level;
+                    let curve = match self.state {
+                        ADSRState::Attack => self.attack_curve,
+                        ADSRState::Decay => self.decay_curve,
+                        _ => self.release_curve,
+                    };
+                    let progress =
+                        (self.stage_ticks - self.ticks_left) as Float / self.stage_ticks as Float;
+                    self.level = self.start_level
+                        + (self.target_level - self.start_level) * curve.shape(progress);
                     self.ticks_left -= 1;
                     self.level
                 }
@@ -68,49 +424,75 @@ impl ADSR {
     }
 
     pub fn state(&self) -> ADSRState {
-        self.state
+        self.segments
+            .as_ref()
+            .map_or(self.state, |segments| segments.state())
     }
 
-    fn state_change(&mut self, state: ADSRState) {
-        if state == self.state {
-            return;
+    /// Returns the current output level of the envelope including velocity.
+    pub fn level(&self) -> Float {
+        self.velocity * self
+            .segments
+            .as_ref()
+            .map_or(self.level, |segments| segments.level())
+    }
+
+    /// Silences the envelope immediately.
+    pub fn reset(&mut self) {
+        self.state = ADSRState::Off;
+        self.ticks_left = 0;
+        self.level = 0.0;
+        if let Some(ref mut segments) = self.segments {
+            segments.reset();
         }
+    }
 
+    /// Starts a stage at the current level, a running stage is restarted.
+    fn state_change(&mut self, state: ADSRState) {
         self.state = state;
-        match state {
-            ADSRState::Attack => {
-                let (time, level) = self.attack;
-                self.ticks_left = (time * self.sample_rate as Time) as usize;
-                self.gain = 4.0 / self.ticks_left as Float;
-                self.target_level = level;
-            }
-            ADSRState::Decay => {
-                let time = self.decay;
-                self.ticks_left = (time * self.sample_rate as Time) as usize;
-                self.gain = 4.0 / self.ticks_left as Float;
-                self.target_level = self.sustain;
-            }
-            ADSRState::Release => {
-                let time = self.release;
-                self.ticks_left = (time * self.sample_rate as Time) as usize;
-                self.gain = 8.0 / self.ticks_left as Float;
-                self.target_level = 0.0
-            }
-            _ => {}
-        }
+        let (time, target_level) = match state {
+            ADSRState::Attack => (self.attack.0 * self.time_scale, self.attack.1),
+            ADSRState::Decay => (self.decay * self.time_scale, self.sustain),
+            ADSRState::Release => (self.release * self.release_scale, 0.0),
+            _ => return,
+        };
+        self.stage_ticks = (time * self.sample_rate as Time) as usize;
+        self.ticks_left = self.stage_ticks;
+        self.start_level = self.level;
+        self.target_level = target_level;
     }
 }
 impl Controllable for ADSR {
     fn handle(&mut self, msg: &ControlEvent) {
         match *msg {
-            ControlEvent::NoteOn { velocity, .. } => {
-                self.state_change(ADSRState::Attack);
-                // TODO: Retrigger option?
-                // self.level = 0.0;
-                // TODO: make velocity sensitivity controllable
-                self.velocity = Float::from_db((1.0 - velocity) * -30.0);
+            ControlEvent::NoteOn { key, velocity } => {
+                match self.retrigger {
+                    RetriggerMode::Legato if self.state().gated() => return,
+                    RetriggerMode::Reset => self.reset(),
+                    _ => (),
+                }
+                // the sensitivity scales the level in dB
+                self.velocity = self
+                    .velocity_curve
+                    .gain(velocity, self.velocity_range)
+                    .powf(self.velocity_sensitivity);
+                let octaves = Time::from(key.saturating_sub(RATE_SCALING_KEY)) / 12.0;
+                self.release_scale = Time::powf(2.0, -self.rate_scaling as Time * octaves);
+                self.time_scale = self.release_scale
+                    * MIN_VELOCITY_TIME_SCALE.powf((self.velocity_time * velocity) as Time);
+                match self.segments {
+                    Some(ref mut segments) => segments.note_on(self.time_scale, self.release_scale),
+                    None => self.state_change(ADSRState::Attack),
+                }
+            }
+            ControlEvent::NoteOff { .. } => {
+                if self.state().gated() {
+                    match self.segments {
+                        Some(ref mut segments) => segments.note_off(),
+                        None => self.state_change(ADSRState::Release),
+                    }
+                }
             }
-            ControlEvent::NoteOff { .. } => self.state_change(ADSRState::Release),
             ControlEvent::ADSR {
                 ref id,
                 attack,
@@ -118,13 +500,87 @@ impl Controllable for ADSR {
                 sustain,
                 release,
             } => {
-                // check path
-                // TODO: make sure that all values are non-zero!
                 if *id == self.id {
-                    self.attack.0 = attack;
-                    self.decay = decay;
-                    self.sustain = sustain;
-                    self.release = release;
+                    // zero times would skip the stages and click
+                    let clamp_time = |time: Time| time.max(MIN_STAGE_TIME).min(MAX_STAGE_TIME);
+                    self.attack.0 = clamp_time(attack);
+                    self.decay = clamp_time(decay);
+                    self.sustain = sustain.max(0.0).min(1.0);
+                    self.release = clamp_time(release);
+                }
+            }
+            ControlEvent::ADSRCurve {
+                ref id,
+                attack,
+                decay,
+                release,
+            } => {
+                if *id == self.id {
+                    if let Some(curve) = attack {
+                        self.attack_curve = curve.clamped();
+                    }
+                    if let Some(curve) = decay {
+                        self.decay_curve = curve.clamped();
+                    }
+                    if let Some(curve) = release {
+                        self.release_curve = curve.clamped();
+                    }
+                }
+            }
+            ControlEvent::ADSRRetrigger { ref id, mode } => {
+                if *id == self.id {
+                    self.retrigger = mode;
+                }
+            }
+            ControlEvent::VelocityResponse { ref curve, range } => {
+                if let Some(ref curve) = *curve {
+                    self.velocity_curve = curve.clone();
+                }
+                if let Some(range) = range {
+                    self.velocity_range = range.max(0.0).min(MAX_VELOCITY_RANGE);
+                }
+            }
+            ControlEvent::ADSRVelocity {
+                ref id,
+                sensitivity,
+                time,
+            } => {
+                if *id == self.id {
+                    if let Some(sensitivity) = sensitivity {
+                        self.velocity_sensitivity = sensitivity.max(0.0).min(1.0);
+                    }
+                    if let Some(time) = time {
+                        self.velocity_time = time.max(0.0).min(1.0);
+                    }
+                }
+            }
+            ControlEvent::ADSRRateScaling { ref id, rate } => {
+                if *id == self.id {
+                    self.rate_scaling = rate.max(0.0).min(1.0);
+                }
+            }
+            ControlEvent::SegmentEnvelope {
+                ref id,
+                ref breakpoints,
+                sustain,
+                loop_points,
+            } => {
+                if *id != self.id {
+                    return;
+                }
+                if breakpoints.is_empty() {
+                    // switching back to the stages silences the envelope
+                    if self.segments.take().is_some() {
+                        self.reset();
+                    }
+                } else {
+                    if self.segments.is_none() {
+                        self.reset();
+                        self.segments = Some(SegmentEnvelope::new(self.sample_rate));
+                    }
+                    if let Some(ref mut segments) = self.segments {
+                        segments.set(breakpoints, sustain, loop_points);
+                    }
                 }
             }
             _ => (),
@@ -138,13 +594,26 @@ impl Default for ADSR {
             decay: 0.2,
             sustain: Float::from_db(-12.0),
             release: 0.6,
+            attack_curve: EnvelopeCurve::Exponential(0.25),
+            decay_curve: EnvelopeCurve::Exponential(0.25),
+            release_curve: EnvelopeCurve::Exponential(0.5),
+            retrigger: RetriggerMode::Continue,
             sample_rate: 48_000,
             state: ADSRState::Off,
+            stage_ticks: 0,
             ticks_left: 0,
-            gain: 0.0,
             level: 0.0,
             velocity: 0.0,
+            velocity_curve: VelocityCurve::Exponential,
+            velocity_range: 30.0,
+            velocity_sensitivity: 1.0,
+            velocity_time: 0.0,
+            rate_scaling: 0.0,
+            time_scale: 1.0,
+            release_scale: 1.0,
+            start_level: 0.0,
             target_level: 1.0,
+            segments: None,
             id: "".to_owned(),
         }
     }
@@ -298,3 +767,397 @@ fn test_long_envelope() {
     assert_eq!(adsr.state(), ADSRState::Off);
     assert_relative_eq!(level, 0.0);
 }
+
+#[test]
+fn test_envelope_curves() {
+    let sample_rate = 48_000;
+    let halfway_level = |curve: EnvelopeCurve| {
+        let mut adsr = ADSR::new(sample_rate);
+        adsr.handle(&ControlEvent::ADSR {
+            id: "".to_owned(),
+            attack: 0.1,
+            decay: 0.1,
+            sustain: 0.5,
+            release: 0.1,
+        });
+        adsr.handle(&ControlEvent::ADSRCurve {
+            id: "".to_owned(),
+            attack: Some(curve),
+            decay: None,
+            release: None,
+        });
+        adsr.handle(&ControlEvent::NoteOn {
+            key: 0,
+            velocity: 1.0,
+        });
+        let mut level = 0.0;
+        for _ in 0..(sample_rate / 20 + 1) {
+            level = adsr.tick();
+        }
+        level / adsr.attack.1
+    };
+    assert_relative_eq!(halfway_level(EnvelopeCurve::Linear), 0.5, epsilon = 1e-9);
+    let exponential = halfway_level(EnvelopeCurve::Exponential(0.5));
+    let steeper = halfway_level(EnvelopeCurve::Exponential(1.0));
+    assert!(0.5 < exponential && exponential < steeper && steeper < 1.0);
+    let logarithmic = halfway_level(EnvelopeCurve::Logarithmic(0.5));
+    assert_relative_eq!(logarithmic, 1.0 - exponential, epsilon = 1e-3);
+    // an amount of zero and out of range amounts
+    assert_relative_eq!(
+        halfway_level(EnvelopeCurve::Exponential(0.0)),
+        0.5,
+        epsilon = 1e-9
+    );
+    assert_relative_eq!(
+        halfway_level(EnvelopeCurve::Logarithmic(-2.0)),
+        0.5,
+        epsilon = 1e-9
+    );
+    assert_relative_eq!(halfway_level(EnvelopeCurve::Exponential(3.0)), steeper);
+    for curve in &[
+        EnvelopeCurve::Linear,
+        EnvelopeCurve::Exponential(1.0),
+        EnvelopeCurve::Logarithmic(1.0),
+    ] {
+        assert_relative_eq!(curve.shape(0.0), 0.0);
+        assert_relative_eq!(curve.shape(1.0), 1.0);
+    }
+}
+
+#[test]
+fn test_retrigger() {
+    let sample_rate = 48_000;
+    let held_envelope = |mode: RetriggerMode| {
+        let mut adsr = ADSR::new(sample_rate);
+        adsr.handle(&ControlEvent::ADSRRetrigger {
+            id: "".to_owned(),
+            mode,
+        });
+        adsr.handle(&ControlEvent::NoteOn {
+            key: 0,
+            velocity: 1.0,
+        });
+        for _ in 0..sample_rate {
+            adsr.tick();
+        }
+        assert_eq!(adsr.state(), ADSRState::Sustain);
+        adsr.handle(&ControlEvent::NoteOn {
+            key: 1,
+            velocity: 1.0,
+        });
+        adsr
+    };
+    let mut adsr = held_envelope(RetriggerMode::Continue);
+    assert_eq!(adsr.state(), ADSRState::Attack);
+    assert_relative_eq!(adsr.tick(), adsr.sustain);
+    let mut adsr = held_envelope(RetriggerMode::Reset);
+    assert_eq!(adsr.state(), ADSRState::Attack);
+    assert_relative_eq!(adsr.tick(), 0.0);
+    // a legato envelope keeps its stage until the note is released
+    let mut adsr = held_envelope(RetriggerMode::Legato);
+    assert_eq!(adsr.state(), ADSRState::Sustain);
+    assert_relative_eq!(adsr.tick(), adsr.sustain);
+    adsr.handle(&ControlEvent::NoteOff {
+        key: 1,
+        velocity: 0.0,
+    });
+    let released = adsr.tick();
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 2,
+        velocity: 1.0,
+    });
+    assert_eq!(adsr.state(), ADSRState::Attack);
+    assert!(adsr.tick() <= released);
+}
+
+#[test]
+fn test_parameter_validation() {
+    let mut adsr = ADSR::new(48_000);
+    adsr.handle(&ControlEvent::ADSR {
+        id: "".to_owned(),
+        attack: 0.0,
+        decay: -1.0,
+        sustain: 2.0,
+        release: Time::NAN,
+    });
+    assert_eq!(adsr.attack.0, MIN_STAGE_TIME);
+    assert_eq!(adsr.decay, MIN_STAGE_TIME);
+    assert_eq!(adsr.sustain, 1.0);
+    assert_eq!(adsr.release, MIN_STAGE_TIME);
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    let level = adsr.tick();
+    assert!(level.is_finite() && level >= 0.0);
+    adsr.handle(&ControlEvent::ADSR {
+        id: "".to_owned(),
+        attack: 1000.0,
+        decay: 0.1,
+        sustain: -0.5,
+        release: 0.1,
+    });
+    assert_eq!(adsr.attack.0, MAX_STAGE_TIME);
+    assert_eq!(adsr.sustain, 0.0);
+    // a release without a held note is ignored
+    adsr.reset();
+    adsr.handle(&ControlEvent::NoteOff {
+        key: 0,
+        velocity: 0.0,
+    });
+    assert_eq!(adsr.state(), ADSRState::Off);
+}
+
+#[cfg(test)]
+fn linear_breakpoints(points: &[(Time, Float)]) -> Vec<Breakpoint> {
+    points
+        .iter()
+        .map(|&(time, level)| Breakpoint {
+            time,
+            level,
+            curve: EnvelopeCurve::Linear,
+        }).collect()
+}
+
+#[test]
+fn test_segment_envelope() {
+    // a tick per millisecond
+    let mut adsr = ADSR::new(1000);
+    adsr.handle(&ControlEvent::SegmentEnvelope {
+        id: "".to_owned(),
+        breakpoints: linear_breakpoints(&[(0.01, 1.0), (0.01, 0.5), (0.02, 0.0)]),
+        sustain: Some(1),
+        loop_points: None,
+    });
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    for idx in 0..10 {
+        assert_relative_eq!(adsr.tick(), idx as Float / 10.0, epsilon = 1e-9);
+    }
+    assert_relative_eq!(adsr.tick(), 1.0);
+    for _ in 0..10 {
+        adsr.tick();
+    }
+    assert_eq!(adsr.state(), ADSRState::Sustain);
+    for _ in 0..100 {
+        assert_relative_eq!(adsr.tick(), 0.5);
+    }
+    adsr.handle(&ControlEvent::NoteOff {
+        key: 0,
+        velocity: 0.0,
+    });
+    assert_eq!(adsr.state(), ADSRState::Release);
+    let mut last_level = adsr.tick();
+    for _ in 0..19 {
+        let level = adsr.tick();
+        assert!(level < last_level);
+        last_level = level;
+    }
+    assert_relative_eq!(adsr.tick(), 0.0);
+    assert_eq!(adsr.state(), ADSRState::Off);
+
+    // an empty list of breakpoints restores the stages
+    adsr.handle(&ControlEvent::SegmentEnvelope {
+        id: "".to_owned(),
+        breakpoints: Vec::new(),
+        sustain: None,
+        loop_points: None,
+    });
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    for _ in 0..1000 {
+        adsr.tick();
+    }
+    assert_relative_eq!(adsr.tick(), adsr.sustain);
+}
+
+#[test]
+fn test_segment_loop() {
+    let mut adsr = ADSR::new(1000);
+    let breakpoints = linear_breakpoints(&[(0.01, 1.0), (0.01, 0.2), (0.01, 1.0), (0.05, 0.0)]);
+    adsr.handle(&ControlEvent::SegmentEnvelope {
+        id: "".to_owned(),
+        breakpoints: breakpoints.clone(),
+        sustain: None,
+        loop_points: Some((0, 2)),
+    });
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    for _ in 0..20 {
+        adsr.tick();
+    }
+    // the level moves between the breakpoints of the loop while the note is held
+    let levels = (0..1000).map(|_| adsr.tick()).collect::<Vec<_>>();
+    assert_eq!(adsr.state(), ADSRState::Attack);
+    let min = levels.iter().cloned().fold(1.0, Float::min);
+    let max = levels.iter().cloned().fold(0.0, Float::max);
+    assert_relative_eq!(min, 0.2, epsilon = 1e-9);
+    assert_relative_eq!(max, 1.0, epsilon = 1e-9);
+    adsr.handle(&ControlEvent::NoteOff {
+        key: 0,
+        velocity: 0.0,
+    });
+    for _ in 0..51 {
+        adsr.tick();
+    }
+    assert_eq!(adsr.state(), ADSRState::Off);
+
+    // invalid loop points are dropped and the envelope runs through
+    adsr.handle(&ControlEvent::SegmentEnvelope {
+        id: "".to_owned(),
+        breakpoints,
+        sustain: Some(4),
+        loop_points: Some((2, 1)),
+    });
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    for _ in 0..81 {
+        adsr.tick();
+    }
+    assert_eq!(adsr.state(), ADSRState::Off);
+}
+
+#[test]
+fn test_segment_fade_out() {
+    let mut adsr = ADSR::new(1000);
+    adsr.handle(&ControlEvent::SegmentEnvelope {
+        id: "".to_owned(),
+        breakpoints: linear_breakpoints(&[(0.01, 1.0), (0.01, 0.5)]),
+        sustain: None,
+        loop_points: None,
+    });
+    adsr.handle(&ControlEvent::NoteOn {
+        key: 0,
+        velocity: 1.0,
+    });
+    for _ in 0..20 {
+        adsr.tick();
+    }
+    assert_relative_eq!(adsr.tick(), 0.5);
+    // the envelope fades out after the last breakpoint instead of stopping at its level
+    let mut last_level = 0.5;
+    for _ in 0..5 {
+        let level = adsr.tick();
+        assert!(level < last_level && last_level - level <= 0.1 + 1e-9);
+        last_level = level;
+    }
+    assert_relative_eq!(adsr.tick(), 0.0);
+    assert_eq!(adsr.state(), ADSRState::Off);
+    assert_relative_eq!(adsr.level(), 0.0);
+}
+
+#[test]
+fn test_velocity_response() {
+    let mut adsr = ADSR::new(48_000);
+    let gain = |adsr: &mut ADSR, velocity: Float| {
+        adsr.handle(&ControlEvent::NoteOn { key: 0, velocity });
+        adsr.velocity
+    };
+    assert_relative_eq!(gain(&mut adsr, 1.0), 1.0);
+    assert_relative_eq!(gain(&mut adsr, 0.0), Float::from_db(-30.0));
+    adsr.handle(&ControlEvent::VelocityResponse {
+        curve: Some(VelocityCurve::Linear),
+        range: Some(40.0),
+    });
+    let floor = Float::from_db(-40.0);
+    assert_relative_eq!(gain(&mut adsr, 0.5), floor + 0.5 * (1.0 - floor));
+    // the table reaches the top of the dynamic range at half the velocity
+    adsr.handle(&ControlEvent::VelocityResponse {
+        curve: Some(VelocityCurve::Table(vec![0.0, 1.0, 1.0])),
+        range: Some(100.0),
+    });
+    assert_relative_eq!(
+        gain(&mut adsr, 0.25),
+        Float::from_db(-0.5 * MAX_VELOCITY_RANGE)
+    );
+    assert_relative_eq!(gain(&mut adsr, 0.75), 1.0);
+    // an insensitive envelope ignores the velocity
+    adsr.handle(&ControlEvent::ADSRVelocity {
+        id: "".to_owned(),
+        sensitivity: Some(0.5),
+        time: None,
+    });
+    assert_relative_eq!(
+        gain(&mut adsr, 0.0),
+        Float::from_db(-0.5 * MAX_VELOCITY_RANGE)
+    );
+    adsr.handle(&ControlEvent::ADSRVelocity {
+        id: "".to_owned(),
+        sensitivity: Some(-1.0),
+        time: None,
+    });
+    assert_relative_eq!(gain(&mut adsr, 0.0), 1.0);
+
+    // hard notes shorten the attack
+    adsr.handle(&ControlEvent::ADSRVelocity {
+        id: "".to_owned(),
+        sensitivity: None,
+        time: Some(1.0),
+    });
+    let attack_ticks = |adsr: &mut ADSR, velocity: Float| {
+        adsr.reset();
+        adsr.handle(&ControlEvent::NoteOn { key: 0, velocity });
+        let mut ticks = 0;
+        while adsr.state() == ADSRState::Attack {
+            adsr.tick();
+            ticks += 1;
+        }
+        ticks
+    };
+    let soft = attack_ticks(&mut adsr, 0.0);
+    let hard = attack_ticks(&mut adsr, 1.0);
+    assert_eq!(soft, (adsr.attack.0 * 48_000.0) as usize + 1);
+    assert_eq!(
+        hard,
+        (adsr.attack.0 * MIN_VELOCITY_TIME_SCALE * 48_000.0) as usize + 1
+    );
+}
+
+#[test]
+fn test_rate_scaling() {
+    let mut adsr = ADSR::new(48_000);
+    adsr.handle(&ControlEvent::ADSRRateScaling {
+        id: "".to_owned(),
+        rate: 1.0,
+    });
+    let stage_ticks = |adsr: &mut ADSR, key: u8| {
+        adsr.reset();
+        adsr.handle(&ControlEvent::NoteOn { key, velocity: 1.0 });
+        let mut attack = 0;
+        while adsr.state() == ADSRState::Attack {
+            adsr.tick();
+            attack += 1;
+        }
+        adsr.handle(&ControlEvent::NoteOff { key, velocity: 1.0 });
+        let mut release = 0;
+        while adsr.state() == ADSRState::Release {
+            adsr.tick();
+            release += 1;
+        }
+        (attack, release)
+    };
+    let (low_attack, low_release) = stage_ticks(&mut adsr, RATE_SCALING_KEY);
+    assert_eq!(low_attack, (adsr.attack.0 * 48_000.0) as usize + 1);
+    // the times halve every octave above the lowest key
+    let (high_attack, high_release) = stage_ticks(&mut adsr, RATE_SCALING_KEY + 24);
+    assert!((high_attack as isize - low_attack as isize / 4).abs() <= 1);
+    assert!((high_release as isize - low_release as isize / 4).abs() <= 1);
+    // lower keys and a rate scaling of zero leave the times unchanged
+    assert_eq!(stage_ticks(&mut adsr, 0), (low_attack, low_release));
+    adsr.handle(&ControlEvent::ADSRRateScaling {
+        id: "".to_owned(),
+        rate: 0.0,
+    });
+    assert_eq!(
+        stage_ticks(&mut adsr, RATE_SCALING_KEY + 24),
+        (low_attack, low_release)
+    );
+}
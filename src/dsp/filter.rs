@@ -2,7 +2,7 @@ use dsp::ControllableLink;
 use event::ControlEvent;
 use types::{Float, Stereo, PI};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
 pub enum FilterType {
     LP,
     HP,
@@ -1,43 +1,105 @@
+use std::sync::mpsc;
+
 use types::{SharedMut, Stereo, Wrap};
 
 use dsp::{
-    ControllableLink, Filter, SignalFlow, SignalLink, SignalSink, SignalSource, SoftLimiter,
-    VoiceManager,
+    Arpeggiator, Clock, ControllableLink, Filter, Sequencer, SignalFlow, SignalLink, SignalSink,
+    SignalSource, SoftLimiter, VoiceManager,
 };
 use event::{ControlEvent, Controllable};
+use patch::Patch;
 use rb::{Producer, RbProducer};
 
 pub struct Flow {
+    clock: Clock,
+    arpeggiator: Arpeggiator,
+    sequencer: Sequencer,
     source: VoiceManager,
     links: Vec<SharedMut<ControllableLink>>,
     sink: BufferSink,
+    patch: Patch,
+    saved_patches: mpsc::Sender<(String, Patch)>, // are written by another thread
 }
 impl Flow {
-    pub fn new(source: VoiceManager, sink: BufferSink, sample_rate: usize) -> Self {
+    pub fn new(
+        source: VoiceManager,
+        sink: BufferSink,
+        sample_rate: usize,
+        defaults: Patch,
+        saved_patches: mpsc::Sender<(String, Patch)>,
+    ) -> Self {
         Flow {
+            clock: Clock::new(sample_rate),
+            arpeggiator: Arpeggiator::default(),
+            sequencer: Sequencer::new(defaults),
             source,
             links: vec![
                 SharedMut::wrap(Filter::new(sample_rate)),
                 SharedMut::wrap(SoftLimiter {}),
             ],
             sink,
+            patch: Patch::default(),
+            saved_patches,
+        }
+    }
+
+    /// Passes a parameter event to the voices and the signal chain.
+    fn dispatch(&mut self, msg: &ControlEvent) {
+        self.source.handle(msg);
+        for link in &self.links {
+            link.borrow_mut().handle(msg)
+        }
+    }
+
+    fn save_patch(&mut self, name: &str) {
+        self.patch.patterns = self.sequencer.patterns().to_vec();
+        // the synth keeps playing if the patch can't be saved
+        self.saved_patches
+            .send((name.to_owned(), self.patch.clone()))
+            .ok();
+    }
+
+    fn load_patch(&mut self, patch: &Patch) {
+        self.sequencer.set_patterns(&patch.patterns);
+        self.patch = Patch::default();
+        for msg in patch.parameters() {
+            self.handle(msg);
         }
     }
 }
 impl Controllable for Flow {
     fn handle(&mut self, msg: &ControlEvent) {
+        self.patch.update(msg);
         match *msg {
-            _ => {
-                self.source.handle(msg);
-                for link in &self.links {
-                    link.borrow_mut().handle(msg)
-                }
+            // note events are passed to the voices by the arpeggiator
+            ControlEvent::NoteOn { .. }
+            | ControlEvent::NoteOff { .. }
+            | ControlEvent::Arpeggiator { .. } => self.arpeggiator.handle(msg),
+            ControlEvent::Tempo(_) | ControlEvent::ClockTick(_) | ControlEvent::Transport(_) => {
+                self.clock.handle(msg)
             }
+            ControlEvent::Sequencer { .. }
+            | ControlEvent::SequencerPattern { .. }
+            | ControlEvent::SequencerStep { .. }
+            | ControlEvent::ParameterLock { .. } => self.sequencer.handle(msg),
+            ControlEvent::SavePatch(ref name) => self.save_patch(name),
+            ControlEvent::LoadPatch { ref patch, .. } => self.load_patch(patch),
+            _ => self.dispatch(msg),
         }
     }
 }
 impl SignalFlow for Flow {
     fn tick(&mut self) {
+        self.clock.tick();
+        self.arpeggiator.tick(&self.clock);
+        while let Some(event) = self.arpeggiator.next_event() {
+            self.source.handle(&event);
+        }
+        // the sequencer events don't change the stored parameters
+        self.sequencer.tick(&self.clock, &self.patch);
+        while let Some(event) = self.sequencer.next_event() {
+            self.dispatch(&event);
+        }
         let mut sample = self.source.tick();
         for link in &self.links {
             sample = link.borrow_mut().tick(sample);
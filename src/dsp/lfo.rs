@@ -0,0 +1,47 @@
+use types::*;
+
+/// A free running low frequency sine oscillator for parameter modulation.
+pub struct Lfo {
+    sample_rate: usize,
+    phase_incr: Float,
+    phase: Float,
+}
+impl Lfo {
+    pub fn new(sample_rate: usize) -> Self {
+        let mut lfo = Lfo {
+            sample_rate,
+            phase_incr: 0.0,
+            phase: 0.0,
+        };
+        lfo.set_freq(1.0);
+        lfo
+    }
+
+    /// Sets the frequency in Hz.
+    pub fn set_freq(&mut self, freq: Float) {
+        self.phase_incr = freq.max(0.0) / self.sample_rate as Float;
+    }
+
+    /// Restarts the period.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Returns the next value in the range `[-1.0, 1.0]`.
+    pub fn tick(&mut self) -> Float {
+        let value = Float::sin(2.0 * PI * self.phase);
+        self.phase = (self.phase + self.phase_incr).fract();
+        value
+    }
+}
+
+#[test]
+fn test_lfo() {
+    let mut lfo = Lfo::new(1000);
+    lfo.set_freq(4.0);
+    let values = (0..250).map(|_| lfo.tick()).collect::<Vec<_>>();
+    assert_relative_eq!(values[0], 0.0);
+    assert_relative_eq!(values[62], 1.0, epsilon = 1e-3);
+    assert_relative_eq!(values[187], -1.0, epsilon = 1e-3);
+    assert_relative_eq!(lfo.tick(), 0.0, epsilon = 1e-9);
+}
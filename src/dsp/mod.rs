@@ -1,19 +1,39 @@
 use event::ControlEvent;
 use types::Stereo;
 
+mod algorithm;
+mod arpeggiator;
+mod blep;
+mod clock;
 mod dynamics;
 mod env_gen;
 mod filter;
 mod flow;
+mod lfo;
+mod noise;
+mod resynthesis;
+mod sequencer;
+mod spectrum;
 mod voice;
 mod wavetable;
 
+pub use self::algorithm::{find_algorithm, ALGORITHMS};
+pub use self::arpeggiator::{Arpeggiator, ArpeggiatorMode, MAX_OCTAVES};
+pub use self::clock::{Clock, Transport};
 pub use self::dynamics::{HardLimiter, SoftLimiter};
-pub use self::env_gen::{ADSRState, ADSR};
+pub use self::env_gen::{
+    ADSRState, Breakpoint, EnvelopeCurve, RetriggerMode, VelocityCurve, ADSR, MAX_VELOCITY_RANGE,
+};
 pub use self::filter::{Filter, FilterType};
 pub use self::flow::{BufferSink, Flow};
-pub use self::voice::VoiceManager;
-pub use self::wavetable::{generate_wavetables, Waveform, Wavetable, WavetableOsc};
+pub use self::resynthesis::resynthesize_wavetable;
+pub use self::sequencer::{Pattern, Sequencer, PATTERN_CNT};
+pub use self::spectrum::{SpectrumQueue, SpectrumRenderer};
+pub use self::voice::{VoiceAllocation, VoiceManager, FEEDBACK_SCALE, MAX_OSC_CNT};
+pub use self::wavetable::{
+    generate_wavetables, load_wavetable, Interpolation, KeyScalingCurve, Waveform, Wavetable,
+    WavetableCache, WavetableFrames, WavetableOsc, LOWEST_FREQ,
+};
 
 pub trait SignalSource {
     fn tick(&mut self) -> Stereo;
@@ -0,0 +1,151 @@
+extern crate rand;
+
+use self::rand::{Rng, XorShiftRng};
+
+use dsp::wavetable::Waveform;
+use types::*;
+
+/// Quality of the bandpass of the colored noise.
+const TONE_Q: Float = 2.0;
+const MIN_TONE_FREQ: Float = 20.0;
+const MAX_TONE_FREQ: Float = 20_000.0;
+
+/// Generates white, pink, brown and bandpassed noise.
+///
+/// Pink noise is filtered with Paul Kellet's refined method, brown noise is leaky
+/// integrated white noise. The colored noise is filtered by the bandpass of a
+/// state-variable filter, which stays stable while the tone is modulated. Its center
+/// frequency is set by the tone and its gain compensates the bandwidth, so that all
+/// colors have a similar loudness.
+pub struct Noise {
+    sample_rate: usize,
+    rng: XorShiftRng,
+    pink: [Float; 7],
+    brown: Float,
+    coeffs: [Float; 3],
+    gain: Float,
+    ic_s: [Float; 2], // states of the integrators
+}
+impl Noise {
+    pub fn new(sample_rate: usize) -> Self {
+        let mut noise = Noise {
+            sample_rate,
+            rng: rand::weak_rng(),
+            pink: [0.0; 7],
+            brown: 0.0,
+            coeffs: [0.0; 3],
+            gain: 1.0,
+            ic_s: [0.0; 2],
+        };
+        noise.set_tone(0.5);
+        noise
+    }
+
+    /// Sets the center frequency of the colored noise, the tone in the range
+    /// `[0.0, 1.0]` is mapped exponentially to 20 Hz to 20 kHz.
+    pub fn set_tone(&mut self, tone: Float) {
+        let nyquist = self.sample_rate as Float / 2.0;
+        let tone = tone.max(0.0).min(1.0);
+        let freq = MIN_TONE_FREQ * Float::powf(MAX_TONE_FREQ / MIN_TONE_FREQ, tone);
+        let freq = freq.min(0.9 * nyquist);
+        let g = Float::tan(PI * freq / self.sample_rate as Float);
+        let a1 = 1.0 / (1.0 + g * (g + TONE_Q.recip()));
+        self.coeffs = [a1, g * a1, g * g * a1];
+        // the noise bandwidth of the bandpass is `PI / 2 * freq / TONE_Q`,
+        // the level is lowered to that of pink noise because of the higher crest factor
+        self.gain = 0.6 * Float::sqrt(nyquist * TONE_Q / (PI / 2.0 * freq));
+    }
+
+    /// Returns the next sample of the noise with the color of the waveform,
+    /// waveforms that aren't noise are silent.
+    pub fn tick(&mut self, waveform: Waveform) -> Float {
+        let white = self.rng.gen::<Float>() * 2.0 - 1.0;
+        match waveform {
+            Waveform::WhiteNoise => white,
+            Waveform::PinkNoise => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.055_517_9;
+                b[1] = 0.99332 * b[1] + white * 0.075_075_9;
+                b[2] = 0.969 * b[2] + white * 0.153_852;
+                b[3] = 0.8665 * b[3] + white * 0.310_485_6;
+                b[4] = 0.55 * b[4] + white * 0.532_952_2;
+                b[5] = -0.7616 * b[5] - white * 0.016_898;
+                let pink = b.iter().sum::<Float>() + white * 0.5362;
+                b[6] = white * 0.115_926;
+                pink * 0.2
+            }
+            Waveform::BrownNoise => {
+                self.brown = (self.brown + 0.02 * white) / 1.02;
+                self.brown * 6.0
+            }
+            Waveform::ColoredNoise => {
+                let [a1, a2, a3] = self.coeffs;
+                // the gain is applied before the filter, the stored energy would be
+                // amplified by a changing gain otherwise
+                let v3 = self.gain * white - self.ic_s[1];
+                let v1 = a1 * self.ic_s[0] + a2 * v3;
+                let v2 = self.ic_s[1] + a2 * self.ic_s[0] + a3 * v3;
+                self.ic_s = [2.0 * v1 - self.ic_s[0], 2.0 * v2 - self.ic_s[1]];
+                // the bandpass has a peak gain of `TONE_Q`
+                v1 / TONE_Q
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[test]
+fn test_noise() {
+    const SAMPLE_RATE: usize = 48_000;
+    let rms = |noise: &mut Noise, waveform| {
+        let sum = (0..SAMPLE_RATE).fold(0.0, |acc, _| {
+            let sample = noise.tick(waveform);
+            assert!(sample.abs() < 4.0);
+            acc + sample * sample
+        });
+        Float::sqrt(sum / SAMPLE_RATE as Float)
+    };
+    let mut noise = Noise::new(SAMPLE_RATE);
+    // the level of the narrow low bands fluctuates, a fixed seed keeps the test reproducible
+    noise.rng = XorShiftRng::new_unseeded();
+    let white = rms(&mut noise, Waveform::WhiteNoise);
+    assert_relative_eq!(white, Float::sqrt(1.0 / 3.0), epsilon = 0.01);
+    for &waveform in &[Waveform::PinkNoise, Waveform::BrownNoise] {
+        let level = rms(&mut noise, waveform);
+        assert!(
+            level > 0.5 * white && level < 2.0 * white,
+            "{:?}: {}",
+            waveform,
+            level
+        );
+    }
+    // the level stays bounded when the tone jumps, the band at 20 kHz is quieter
+    // because it is compressed towards the Nyquist frequency
+    for &tone in &[1.0, 0.0, 0.5, 0.1, 0.8] {
+        noise.set_tone(tone);
+        let level = rms(&mut noise, Waveform::ColoredNoise);
+        let min_level = if tone < 1.0 { 0.5 * white } else { 0.2 * white };
+        assert!(
+            level > min_level && level < 2.0 * white,
+            "tone {}: {}",
+            tone,
+            level
+        );
+    }
+    assert_eq!(rms(&mut noise, Waveform::Saw), 0.0);
+
+    // brown noise has most of its energy in the low frequencies
+    let mut last = 0.0;
+    let mut roughness = |noise: &mut Noise, waveform| {
+        (0..SAMPLE_RATE).fold(0.0, |acc, _| {
+            let sample = noise.tick(waveform);
+            let delta = sample - last;
+            last = sample;
+            acc + delta * delta
+        })
+    };
+    let white = roughness(&mut noise, Waveform::WhiteNoise);
+    let pink = roughness(&mut noise, Waveform::PinkNoise);
+    let brown = roughness(&mut noise, Waveform::BrownNoise);
+    assert!(white > pink && pink > brown);
+}
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use dsp::wavetable::{build_user_wavetables, Wavetable, MAX_FRAMES};
+use errors::RunError;
+use io::{read_wav, write_wav};
+use types::*;
+
+/// Samples of each extracted cycle.
+pub const FRAME_SIZE: usize = 2048;
+const MIN_PITCH: Float = 40.0;
+const MAX_PITCH: Float = 2000.0;
+/// Threshold of the normalized difference function below which a period is accepted.
+const PITCH_THRESHOLD: Float = 0.15;
+
+/// Turns a recording into a wavetable with the given number of frames, see
+/// `extract_cycles`. The extracted cycles are written into `output` if it is given,
+/// so that they can be loaded with `load_wavetable` later on.
+pub fn resynthesize_wavetable<P: AsRef<Path>>(
+    path: P,
+    frame_cnt: usize,
+    output: Option<P>,
+    fundamental_freq: Float,
+    sample_rate: usize,
+) -> Result<Vec<Vec<Wavetable>>, RunError> {
+    let wav = try!(read_wav(path));
+    let cycles = try!(extract_cycles(
+        &wav.samples,
+        wav.sample_rate as usize,
+        frame_cnt
+    ));
+    if let Some(output) = output {
+        try!(write_wav(output, &cycles, wav.sample_rate, Some(FRAME_SIZE)));
+    }
+    Ok(build_user_wavetables(
+        &cycles,
+        FRAME_SIZE,
+        fundamental_freq,
+        sample_rate,
+    ))
+}
+
+/// Extracts single cycles at regular intervals of the recording.
+///
+/// The period is detected at each position and the cycle starts at the next rising
+/// zero crossing. Each cycle is resampled to `FRAME_SIZE` samples and a linear ramp
+/// is subtracted, so that its end matches its start. The frames are returned one
+/// after another.
+pub fn extract_cycles(
+    samples: &[Float],
+    sample_rate: usize,
+    frame_cnt: usize,
+) -> Result<Vec<Float>, RunError> {
+    let max_period = (sample_rate as Float / MIN_PITCH).ceil() as usize;
+    // pitch detection window, search for the zero crossing and the cycle itself
+    let needed = 4 * max_period + 2;
+    if frame_cnt == 0 || frame_cnt > MAX_FRAMES {
+        return Err(RunError::WavError(format!(
+            "the frame count must be in the range [1, {}]",
+            MAX_FRAMES
+        )));
+    }
+    if samples.len() < needed {
+        return Err(RunError::WavError(
+            "the recording is too short for resynthesis".to_owned(),
+        ));
+    }
+    let spacing = if frame_cnt > 1 {
+        (samples.len() - needed) as Float / (frame_cnt - 1) as Float
+    } else {
+        0.0
+    };
+    let mut cycles = Vec::with_capacity(frame_cnt * FRAME_SIZE);
+    let mut last_period = None;
+    for frame in 0..frame_cnt {
+        let offset = (frame as Float * spacing) as usize;
+        let period = match detect_period(&samples[offset..], sample_rate).or(last_period) {
+            Some(period) => period,
+            None => continue,
+        };
+        last_period = Some(period);
+        let start = zero_crossing(samples, offset + 2 * max_period, period);
+        let first = interpolate(samples, start);
+        let drift = interpolate(samples, start + period) - first;
+        for idx in 0..FRAME_SIZE {
+            let fraction = idx as Float / FRAME_SIZE as Float;
+            cycles.push(interpolate(samples, start + fraction * period) - drift * fraction);
+        }
+    }
+    if last_period.is_none() {
+        return Err(RunError::WavError("no pitch detected".to_owned()));
+    }
+    // silent frames at the beginning get the first detected cycle
+    let missing = frame_cnt - cycles.len() / FRAME_SIZE;
+    let first_cycle = cycles[..FRAME_SIZE].to_vec();
+    for _ in 0..missing {
+        cycles.splice(0..0, first_cycle.iter().cloned());
+    }
+    Ok(cycles)
+}
+
+/// Detects the period in samples at the beginning of the signal with the cumulative
+/// mean normalized difference function of the YIN algorithm.
+fn detect_period(samples: &[Float], sample_rate: usize) -> Option<Float> {
+    let min_period = (sample_rate as Float / MAX_PITCH) as usize;
+    let max_period = (sample_rate as Float / MIN_PITCH).ceil() as usize;
+    let window = max_period;
+    if samples[..window].iter().all(|sample| sample.abs() < 1e-4) {
+        return None;
+    }
+    let mut differences = vec![1.0; max_period + 2];
+    let mut sum = 0.0;
+    for period in 1..max_period + 2 {
+        let difference = (0..window).fold(0.0, |acc, idx| {
+            let delta = samples[idx] - samples[idx + period];
+            acc + delta * delta
+        });
+        sum += difference;
+        differences[period] = if sum > 0.0 {
+            difference * period as Float / sum
+        } else {
+            1.0
+        };
+    }
+    let mut best = None;
+    for period in min_period.max(1)..max_period + 1 {
+        let difference = differences[period];
+        if difference < PITCH_THRESHOLD && difference <= differences[period + 1] {
+            best = Some(period);
+            break;
+        }
+        if best.map_or(true, |best| difference < differences[best]) {
+            best = Some(period);
+        }
+    }
+    best.map(|period| {
+        // parabolic interpolation of the minimum
+        let (prev, current, next) = (
+            differences[period - 1],
+            differences[period],
+            differences[period + 1],
+        );
+        let denominator = prev - 2.0 * current + next;
+        if denominator.abs() > 1e-12 {
+            period as Float + 0.5 * (prev - next) / denominator
+        } else {
+            period as Float
+        }
+    })
+}
+
+/// Returns the position of the first rising zero crossing within a period after `offset`.
+fn zero_crossing(samples: &[Float], offset: usize, period: Float) -> Float {
+    for idx in offset..offset + period as usize {
+        let (current, next) = (samples[idx], samples[idx + 1]);
+        if current <= 0.0 && next > 0.0 {
+            return idx as Float + current / (current - next);
+        }
+    }
+    offset as Float
+}
+
+fn interpolate(samples: &[Float], position: Float) -> Float {
+    let idx = position as usize;
+    let fraction = position - idx as Float;
+    let next = samples.get(idx + 1).cloned().unwrap_or(0.0);
+    samples[idx] + (next - samples[idx]) * fraction
+}
+
+#[test]
+fn test_extract_cycles() {
+    const SAMPLE_RATE: usize = 44_100;
+    const FREQ: Float = 220.0;
+    // the level of the second harmonic rises from 0.0 to 0.4
+    let len = SAMPLE_RATE;
+    let samples = (0..len)
+        .map(|idx| {
+            let phase = 2.0 * PI * FREQ * idx as Float / SAMPLE_RATE as Float;
+            let level = 0.4 * idx as Float / len as Float;
+            0.5 * (phase.sin() + level * (2.0 * phase).sin())
+        }).collect::<Vec<_>>();
+    let period = detect_period(&samples, SAMPLE_RATE).unwrap();
+    assert_relative_eq!(period, SAMPLE_RATE as Float / FREQ, epsilon = 0.05);
+
+    let cycles = extract_cycles(&samples, SAMPLE_RATE, 4).unwrap();
+    assert_eq!(cycles.len(), 4 * FRAME_SIZE);
+    let mut last_level = -1.0;
+    for cycle in cycles.chunks(FRAME_SIZE) {
+        // the cycles start at the rising zero crossing of the fundamental
+        assert_relative_eq!(cycle[0], 0.0, epsilon = 0.01);
+        assert_relative_eq!(cycle[FRAME_SIZE / 4], 0.5, epsilon = 0.02);
+        let level = 2.0 * cycle[FRAME_SIZE / 8] - Float::sin(PI / 4.0);
+        assert!(level > last_level);
+        last_level = level;
+    }
+    assert!(last_level > 0.35);
+}
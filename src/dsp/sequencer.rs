@@ -0,0 +1,467 @@
+extern crate rand;
+
+use std::collections::VecDeque;
+
+use dsp::{Clock, Transport};
+use event::{ControlEvent, Controllable};
+use patch::Patch;
+use types::*;
+
+pub const MAX_STEPS: usize = 64;
+pub const PATTERN_CNT: usize = 16;
+
+/// A single step of a sequencer pattern.
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
+pub struct Step {
+    /// The key to play, `None` for a rest.
+    pub note: Option<u8>,
+    pub velocity: Float,
+    /// Note length relative to the step length.
+    pub gate: Float,
+    /// Continues the note of the previous step instead of retriggering it.
+    pub tie: bool,
+    /// Chance that the step is played, in the range `[0.0, 1.0]`.
+    pub probability: Float,
+    /// Parameter changes that are only active while the step is played.
+    pub locks: Vec<ControlEvent>,
+}
+impl Default for Step {
+    fn default() -> Self {
+        Step {
+            note: None,
+            velocity: 1.0,
+            gate: 0.5,
+            tie: false,
+            probability: 1.0,
+            locks: Vec::new(),
+        }
+    }
+}
+
+/// A sequence of up to `MAX_STEPS` steps.
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
+pub struct Pattern {
+    steps: Vec<Step>,
+    length: usize,
+    /// The pattern that is played after this one, `None` to loop.
+    next: Option<usize>,
+}
+impl Default for Pattern {
+    fn default() -> Self {
+        Pattern {
+            steps: vec![Step::default(); MAX_STEPS],
+            length: 16,
+            next: None,
+        }
+    }
+}
+
+/// A pattern based step sequencer that generates note and parameter events.
+/// The events are queued and must be fetched with `next_event` after each tick.
+/// The sequencer plays while the transport of the shared `Clock` is running.
+pub struct Sequencer {
+    patterns: Vec<Pattern>,
+    playing: bool,
+    pattern: usize,
+    queued: Option<usize>, // pattern that is played when the current one is finished
+    division: Float,       // step length in beats
+    step: usize,
+    position: Float, // song position in beats
+    next_step: Float,
+    note_off: Option<Float>,
+    sounding: Option<u8>,
+    locked: Vec<ControlEvent>, // the locks of the current step
+    defaults: Patch,           // restores the locked parameters that are not stored in the patch
+    events: VecDeque<ControlEvent>,
+}
+impl Sequencer {
+    /// Creates a sequencer that restores the locked parameters that are not stored in
+    /// the patch to the values of `defaults`.
+    pub fn new(defaults: Patch) -> Self {
+        Sequencer {
+            patterns: vec![Pattern::default(); PATTERN_CNT],
+            playing: false,
+            pattern: 0,
+            queued: None,
+            division: 0.25,
+            step: 0,
+            position: 0.0,
+            next_step: 0.0,
+            note_off: None,
+            sounding: None,
+            locked: Vec::new(),
+            defaults,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    /// Replaces all patterns, e.g. when a patch is loaded.
+    pub fn set_patterns(&mut self, patterns: &[Pattern]) {
+        self.patterns = vec![Pattern::default(); PATTERN_CNT];
+        for (old, new) in self.patterns.iter_mut().zip(patterns.iter()) {
+            *old = new.clone();
+            old.steps.resize(MAX_STEPS, Step::default());
+        }
+    }
+
+    /// Follows the song position of the clock by one tick.
+    /// The patch is needed to restore the locked parameters.
+    pub fn tick(&mut self, clock: &Clock, patch: &Patch) {
+        match clock.transport() {
+            Some(Transport::Stop) => self.stop(patch),
+            // the clock position already includes the current tick
+            Some(_) if clock.running() => self.locate(clock.position() - clock.beats()),
+            _ => (),
+        }
+        if self.playing {
+            self.advance(clock.position(), patch);
+        }
+    }
+
+    /// Returns the next queued event.
+    pub fn next_event(&mut self) -> Option<ControlEvent> {
+        self.events.pop_front()
+    }
+
+    fn advance(&mut self, position: Float, patch: &Patch) {
+        self.position = position;
+        if let Some(note_off) = self.note_off {
+            if self.position >= note_off {
+                self.release();
+            }
+        }
+        if self.position >= self.next_step {
+            self.restore(patch);
+            self.play_step();
+        }
+    }
+
+    /// Continues playing at the step at or after the given song position.
+    fn locate(&mut self, position: Float) {
+        self.release();
+        self.playing = true;
+        if let Some(pattern) = self.queued.take() {
+            self.pattern = pattern;
+        }
+        let step = (position / self.division).ceil();
+        self.position = position;
+        self.next_step = step * self.division;
+        self.step = step as usize % self.patterns[self.pattern].length;
+    }
+
+    fn stop(&mut self, patch: &Patch) {
+        self.playing = false;
+        self.release();
+        self.restore(patch);
+    }
+
+    fn play_step(&mut self) {
+        let start = self.next_step;
+        self.next_step += self.division;
+        let (tie, next_tie) = {
+            let pattern = &self.patterns[self.pattern];
+            let step = &pattern.steps[self.step];
+            let next = &pattern.steps[(self.step + 1) % pattern.length];
+            (step.tie && self.sounding.is_some(), next.tie && next.note.is_some())
+        };
+        if !tie {
+            self.release();
+            let step = &self.patterns[self.pattern].steps[self.step];
+            if let Some(key) = step.note {
+                if step.probability >= 1.0 || rand::random::<Float>() < step.probability {
+                    for lock in &step.locks {
+                        self.locked.push(lock.clone());
+                        self.events.push_back(lock.clone());
+                    }
+                    self.events.push_back(ControlEvent::NoteOn {
+                        key,
+                        velocity: step.velocity,
+                    });
+                    self.sounding = Some(key);
+                }
+            }
+        }
+        let gate = self.patterns[self.pattern].steps[self.step].gate;
+        self.note_off = if next_tie {
+            None
+        } else {
+            Some(start + gate * self.division)
+        };
+        self.next_position();
+    }
+
+    fn next_position(&mut self) {
+        self.step += 1;
+        if self.step >= self.patterns[self.pattern].length {
+            self.step = 0;
+            let next = self.patterns[self.pattern].next;
+            self.pattern = self.queued.take().or(next).unwrap_or(self.pattern);
+        }
+    }
+
+    fn release(&mut self) {
+        self.note_off = None;
+        if let Some(key) = self.sounding.take() {
+            self.events.push_back(ControlEvent::NoteOff { key, velocity: 0.0 });
+        }
+    }
+
+    /// Sets the locked fields back to the values stored in the patch or to their
+    /// default values.
+    fn restore(&mut self, patch: &Patch) {
+        for lock in self.locked.drain(..) {
+            let (stored, default) = match lock.parameter() {
+                Some(parameter) => (
+                    patch.get(parameter.name, parameter.id),
+                    self.defaults.get(parameter.name, parameter.id),
+                ),
+                None => continue,
+            };
+            let mut event = match (stored, default) {
+                (Some(stored), Some(mut default)) => {
+                    default.merge(&stored, stored.fields());
+                    default
+                }
+                (Some(event), None) | (None, Some(event)) => event,
+                (None, None) => continue,
+            };
+            event.retain_fields(lock.fields());
+            if event.fields() != 0 {
+                self.events.push_back(event);
+            }
+        }
+    }
+
+    fn pattern_mut(&mut self, pattern: usize) -> Option<&mut Pattern> {
+        self.patterns.get_mut(pattern)
+    }
+}
+impl Controllable for Sequencer {
+    fn handle(&mut self, msg: &ControlEvent) {
+        match *msg {
+            ControlEvent::Sequencer { pattern, division } => {
+                if let Some(some_pattern) = pattern {
+                    if some_pattern < PATTERN_CNT {
+                        self.queued = Some(some_pattern);
+                    }
+                }
+                if let Some(some_division) = division {
+                    if some_division > 0.0 {
+                        self.division = some_division;
+                    }
+                }
+            }
+            ControlEvent::SequencerPattern {
+                pattern,
+                length,
+                next,
+            } => {
+                if let Some(pattern) = self.pattern_mut(pattern) {
+                    if let Some(some_length) = length {
+                        pattern.length = some_length.max(1).min(MAX_STEPS);
+                    }
+                    if let Some(some_next) = next {
+                        pattern.next = some_next.and_then(|next| {
+                            if next < PATTERN_CNT {
+                                Some(next)
+                            } else {
+                                None
+                            }
+                        });
+                    }
+                }
+                // the current step might be outside of the shortened pattern
+                if self.step >= self.patterns[self.pattern].length {
+                    self.step = 0;
+                }
+            }
+            ControlEvent::SequencerStep {
+                pattern,
+                step,
+                note,
+                velocity,
+                gate,
+                tie,
+                probability,
+            } => {
+                if let Some(step) = self.pattern_mut(pattern).and_then(|p| p.steps.get_mut(step)) {
+                    step.note = note.and_then(|key| if key < 128 { Some(key) } else { None });
+                    step.velocity = velocity.max(0.0).min(1.0);
+                    step.gate = gate.max(0.01).min(1.0);
+                    step.tie = tie;
+                    step.probability = probability.max(0.0).min(1.0);
+                }
+            }
+            ControlEvent::ParameterLock {
+                pattern,
+                step,
+                ref lock,
+            } => {
+                if let Some(step) = self.pattern_mut(pattern).and_then(|p| p.steps.get_mut(step)) {
+                    match *lock {
+                        Some(ref lock) => {
+                            // a parameter can only be locked once per step
+                            if let Some(parameter) = lock.parameter() {
+                                let locked = step
+                                    .locks
+                                    .iter()
+                                    .position(|old| old.parameter() == Some(parameter));
+                                match locked {
+                                    Some(idx) => step.locks[idx].merge(lock, lock.fields()),
+                                    None => step.locks.push((**lock).clone()),
+                                }
+                            }
+                        }
+                        None => step.locks.clear(),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+fn set_step(sequencer: &mut Sequencer, step: usize, note: Option<u8>, tie: bool) {
+    sequencer.handle(&ControlEvent::SequencerStep {
+        pattern: 0,
+        step,
+        note,
+        velocity: 1.0,
+        gate: 0.5,
+        tie,
+        probability: 1.0,
+    });
+}
+
+#[cfg(test)]
+fn run(sequencer: &mut Sequencer, patch: &Patch, ticks: usize) -> Vec<(usize, ControlEvent)> {
+    let mut clock = Clock::new(48_000);
+    clock.handle(&ControlEvent::Transport(Transport::Start));
+    let mut events = Vec::new();
+    for idx in 0..ticks {
+        clock.tick();
+        sequencer.tick(&clock, patch);
+        while let Some(event) = sequencer.next_event() {
+            events.push((idx, event));
+        }
+    }
+    events
+}
+
+#[test]
+fn test_sequencer_steps() {
+    let mut sequencer = Sequencer::new(Patch::default());
+    let patch = Patch::default();
+    sequencer.handle(&ControlEvent::SequencerPattern {
+        pattern: 0,
+        length: Some(4),
+        next: None,
+    });
+    set_step(&mut sequencer, 0, Some(60), false);
+    set_step(&mut sequencer, 1, Some(60), true);
+    set_step(&mut sequencer, 3, Some(67), false);
+    // 1/16 steps at 120 bpm last 6000 samples
+    let events = run(&mut sequencer, &patch, 4 * 6000 - 2);
+    // the tied second step extends the first note
+    let expected = [(0, 60, true), (9000, 60, false), (18000, 67, true), (21000, 67, false)];
+    assert_eq!(events.len(), expected.len());
+    for (&(idx, ref event), &(expected_idx, expected_key, on)) in events.iter().zip(&expected) {
+        assert!((idx as isize - expected_idx as isize).abs() <= 1);
+        match *event {
+            ControlEvent::NoteOn { key, .. } => assert!(on && key == expected_key),
+            ControlEvent::NoteOff { key, .. } => assert!(!on && key == expected_key),
+            _ => panic!("unexpected event: {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn test_sequencer_parameter_lock() {
+    use dsp::FilterType;
+
+    let mut sequencer = Sequencer::new(Patch::default());
+    let mut patch = Patch::default();
+    // the locked frequency is restored from the combined event
+    let base = ControlEvent::Filter {
+        filter_type: Some(FilterType::LP),
+        freq: Some(1000.0),
+        q: Some(2.0),
+    };
+    patch.update(&base);
+    sequencer.handle(&ControlEvent::SequencerPattern {
+        pattern: 0,
+        length: Some(2),
+        next: None,
+    });
+    set_step(&mut sequencer, 0, Some(60), false);
+    sequencer.handle(&ControlEvent::ParameterLock {
+        pattern: 0,
+        step: 0,
+        lock: Some(Box::new(ControlEvent::Filter {
+            filter_type: None,
+            freq: Some(200.0),
+            q: None,
+        })),
+    });
+    let filters = run(&mut sequencer, &patch, 6001)
+        .into_iter()
+        .filter_map(|(idx, event)| match event {
+            ControlEvent::Filter { .. } => Some((idx, event)),
+            _ => None,
+        }).collect::<Vec<_>>();
+    assert_eq!(filters.len(), 2);
+    assert_eq!(filters[0].0, 0);
+    match filters[1].1 {
+        ControlEvent::Filter {
+            filter_type,
+            freq,
+            q,
+        } => {
+            // only the locked field is restored
+            assert_eq!((filter_type, q), (None, None));
+            assert_relative_eq!(freq.unwrap(), 1000.0);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_sequencer_lock_default() {
+    let mut defaults = Patch::default();
+    defaults.update(&ControlEvent::Filter {
+        filter_type: None,
+        freq: Some(20_000.0),
+        q: Some(1.0),
+    });
+    let mut sequencer = Sequencer::new(defaults);
+    sequencer.handle(&ControlEvent::SequencerPattern {
+        pattern: 0,
+        length: Some(2),
+        next: None,
+    });
+    set_step(&mut sequencer, 0, Some(60), false);
+    sequencer.handle(&ControlEvent::ParameterLock {
+        pattern: 0,
+        step: 0,
+        lock: Some(Box::new(ControlEvent::Filter {
+            filter_type: None,
+            freq: None,
+            q: Some(3.0),
+        })),
+    });
+    // the resonance was never set, so it returns to its default
+    let qs = run(&mut sequencer, &Patch::default(), 6001)
+        .into_iter()
+        .filter_map(|(_, event)| match event {
+            ControlEvent::Filter { q, .. } => q,
+            _ => None,
+        }).collect::<Vec<_>>();
+    assert_eq!(qs.len(), 2);
+    assert_relative_eq!(qs[0], 3.0);
+    assert_relative_eq!(qs[1], 1.0);
+}
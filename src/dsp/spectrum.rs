@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dsp::wavetable::{render_spectrum, Waveform, WavetableFrames};
+use event::ControlEvent;
+use types::*;
+
+/// Partials that are rendered at most.
+pub const MAX_PARTIALS: usize = 256;
+
+/// Interval in which retired frames are checked if they can be dropped.
+const RETIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Request {
+    id: String,
+    amplitudes: Vec<Float>,
+    phases: Vec<Float>,
+}
+
+enum Message {
+    Render(Request),
+    /// An installed spectrum and the frames that it replaced in the wavetable bank.
+    Retire(RenderedSpectrum, Option<WavetableFrames>),
+}
+
+/// A rendered spectrum with the events that install it, which are built on the
+/// renderer thread so that the audio thread doesn't allocate them.
+pub struct RenderedSpectrum {
+    pub id: String,   // of the oscillator
+    pub name: String, // of the wavetable, `SPECTRUM-<id>`
+    pub frames: WavetableFrames,
+    pub add: ControlEvent,    // `AddWavetable` with the frames
+    pub select: ControlEvent, // `Waveform` of the oscillator, the index is set on install
+}
+
+/// Queues spectra for a `SpectrumRenderer`. Spectra are queued by the threads that
+/// receive them, so that the audio thread doesn't allocate the requests.
+#[derive(Clone)]
+pub struct SpectrumQueue {
+    requests: mpsc::Sender<Message>,
+}
+impl SpectrumQueue {
+    /// Queues the spectrum with the amplitudes and phases of the partials. Phases are
+    /// given in periods, missing phases are zero.
+    pub fn render(&self, id: &str, amplitudes: &[Float], phases: &[Float]) {
+        let len = ::std::cmp::min(amplitudes.len(), MAX_PARTIALS);
+        let request = Request {
+            id: id.to_owned(),
+            amplitudes: amplitudes[..len].to_vec(),
+            phases: phases.iter().take(len).cloned().collect(),
+        };
+        // the thread only stops if the renderer is dropped
+        self.requests.send(Message::Render(request)).ok();
+    }
+}
+
+/// Renders harmonic spectra into band-limited wavetables on a background thread,
+/// so that editing a spectrum doesn't interrupt the audio thread.
+///
+/// Requests that arrive faster than they are rendered are coalesced per id and only
+/// the latest spectrum is rendered. Installed spectra and the frames that they
+/// replaced are sent back and dropped on the renderer thread, once no oscillator
+/// fades them out anymore. The thread finishes when the renderer is dropped.
+pub struct SpectrumRenderer {
+    queue: SpectrumQueue,
+    results: mpsc::Receiver<RenderedSpectrum>,
+}
+impl SpectrumRenderer {
+    pub fn new(fundamental_freq: Float, sample_rate: usize) -> Self {
+        let (tx_request, rx_request) = mpsc::channel();
+        let (tx_result, rx_result) = mpsc::channel();
+        thread::Builder::new()
+            .name("spectrum".to_owned())
+            .spawn(move || {
+                let mut retired: Vec<WavetableFrames> = Vec::new();
+                loop {
+                    let mut pending = HashMap::new();
+                    let mut next = rx_request.recv_timeout(RETIRE_INTERVAL);
+                    loop {
+                        match next {
+                            Ok(Message::Render(request)) => {
+                                pending.insert(request.id.clone(), request);
+                            }
+                            Ok(Message::Retire(spectrum, replaced)) => {
+                                drop(spectrum);
+                                retired.extend(replaced);
+                            }
+                            Err(mpsc::RecvTimeoutError::Timeout) => break,
+                            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                        }
+                        next = rx_request
+                            .try_recv()
+                            .map_err(|_| mpsc::RecvTimeoutError::Timeout);
+                    }
+                    // the oscillators hold the frames until they faded out
+                    retired.retain(|frames| Arc::strong_count(frames) > 1);
+                    for (id, request) in pending {
+                        let frames = Arc::new(render_spectrum(
+                            &request.amplitudes,
+                            &request.phases,
+                            fundamental_freq,
+                            sample_rate,
+                        ));
+                        let name = format!("SPECTRUM-{}", id);
+                        let spectrum = RenderedSpectrum {
+                            add: ControlEvent::AddWavetable {
+                                name: name.clone(),
+                                frames: frames.clone(),
+                            },
+                            select: ControlEvent::Waveform {
+                                id: id.clone(),
+                                waveform: Waveform::User(0),
+                            },
+                            id,
+                            name,
+                            frames,
+                        };
+                        if tx_result.send(spectrum).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }).unwrap();
+        SpectrumRenderer {
+            queue: SpectrumQueue {
+                requests: tx_request,
+            },
+            results: rx_result,
+        }
+    }
+
+    /// Returns a queue for requests from other threads.
+    pub fn queue(&self) -> SpectrumQueue {
+        self.queue.clone()
+    }
+
+    /// Returns a rendered spectrum if one is ready, never blocks.
+    pub fn try_recv(&self) -> Option<RenderedSpectrum> {
+        self.results.try_recv().ok()
+    }
+
+    /// Returns an installed spectrum and the frames that it replaced to the renderer
+    /// thread, which drops them.
+    pub fn retire(&self, spectrum: RenderedSpectrum, replaced: Option<WavetableFrames>) {
+        self.queue
+            .requests
+            .send(Message::Retire(spectrum, replaced))
+            .ok();
+    }
+}
+
+#[test]
+fn test_spectrum_renderer() {
+    let renderer = SpectrumRenderer::new(20.0, 48_000);
+    renderer.queue().render("OSC1", &[1.0, 0.0, 0.5], &[0.25]);
+    let spectrum = renderer.results.recv().unwrap();
+    assert_eq!(spectrum.id, "OSC1");
+    assert_eq!(spectrum.name, "SPECTRUM-OSC1");
+    assert_eq!(spectrum.frames.len(), 1);
+    match spectrum.add {
+        ControlEvent::AddWavetable {
+            ref name,
+            ref frames,
+        } => {
+            assert_eq!(name, "SPECTRUM-OSC1");
+            assert!(Arc::ptr_eq(frames, &spectrum.frames));
+        }
+        _ => panic!("unexpected event"),
+    }
+}
+
+#[test]
+fn test_retired_frames_dropped_after_fade() {
+    let renderer = SpectrumRenderer::new(20.0, 48_000);
+    renderer.queue().render("OSC1", &[1.0], &[]);
+    let spectrum = renderer.results.recv().unwrap();
+    let fading = spectrum.frames.clone();
+    renderer.retire(spectrum, Some(fading.clone()));
+    thread::sleep(RETIRE_INTERVAL * 3);
+    // still held by the fading oscillator
+    assert_eq!(Arc::strong_count(&fading), 2);
+    let weak = Arc::downgrade(&fading);
+    drop(fading);
+    thread::sleep(RETIRE_INTERVAL * 3);
+    assert!(weak.upgrade().is_none());
+}
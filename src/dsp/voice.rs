@@ -1,37 +1,57 @@
 extern crate itertools;
 
 use self::itertools::Zip;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Ordering;
+use std::sync::Arc;
 
 use dsp;
+use dsp::algorithm::{find_algorithm, Algorithm};
+use dsp::blep::{minblep_table, MinBlep};
 use dsp::env_gen::*;
+use dsp::spectrum::{RenderedSpectrum, SpectrumRenderer};
 use dsp::wavetable::*;
 use event::{ControlEvent, Controllable};
 use io::PitchConvert;
-use std::rc::Rc;
 use types::*;
 
 use dsp::SignalSource;
 
-const OSC_CNT: usize = 4;
+/// Number of oscillators of each voice, a patch can use fewer of them as FM operators.
+pub const MAX_OSC_CNT: usize = 8;
+const DEFAULT_OSC_CNT: usize = 4;
+/// Scales the self modulation, because feedback modulation easily creates noise.
+pub const FEEDBACK_SCALE: Float = 0.1;
+/// Modulation index of the modulators of an algorithm.
+const ALGORITHM_MOD_INDEX: Float = 1.0;
+/// Length of the fade-out that is applied to a stolen voice.
+const STEAL_FADE_TIME: Time = 0.005;
 
 pub struct Voice {
-    fm_mod: Vec<Float>, // contains the mod indices
-    levels: Vec<Float>, // oscillator levels
+    osc_cnt: usize,                     // number of oscillators that are played
+    fm_mod: Vec<Float>,                 // contains the mod indices
+    ring_mod: Vec<Float>,               // ring modulation levels, same layout as `fm_mod`
+    sync: Vec<Option<usize>>,           // index of the oscillator that each oscillator is synced to
+    bleps: Vec<MinBlep>,                // corrections of the sync discontinuities
+    last_samples: [Float; MAX_OSC_CNT], // samples of the last tick, used for the feedback
+    levels: Vec<Float>,                 // oscillator levels
     pan: Vec<Stereo>,
     volume_envelopes: Vec<ADSR>,
     oscillators: Vec<WavetableOsc>,
+    steal_fade: usize,          // length of the fade-out in ticks
+    fade_ticks: usize,          // remaining ticks of a running fade-out
+    pending: Vec<ControlEvent>, // note events that are deferred until the fade-out is done
 }
 impl Voice {
     fn new(
         sample_rate: usize,
-        wavetables: &Rc<HashMap<Waveform, Vec<Wavetable>>>,
-        pitch_convert_handle: &Rc<PitchConvert>,
+        wavetables: &SharedMut<WavetableBank>,
+        pitch_convert_handle: &SharedMut<PitchConvert>,
+        blep_table: &Arc<Vec<Float>>,
     ) -> Self {
-        let mut levels = Vec::with_capacity(OSC_CNT);
-        let mut oscillators = Vec::with_capacity(OSC_CNT);
-        let mut volume_envelopes = Vec::with_capacity(OSC_CNT);
-        for idx in 0..OSC_CNT {
+        let mut levels = Vec::with_capacity(MAX_OSC_CNT);
+        let mut oscillators = Vec::with_capacity(MAX_OSC_CNT);
+        let mut volume_envelopes = Vec::with_capacity(MAX_OSC_CNT);
+        for idx in 0..MAX_OSC_CNT {
             levels.push(if idx == 0 { MINUS_THREE_DB } else { 0.0 });
             oscillators.push(WavetableOsc::with_id(
                 format!("OSC{}", idx + 1),
@@ -42,47 +62,176 @@ impl Voice {
             volume_envelopes.push(ADSR::with_id(sample_rate, format!("ADSR-OSC{}", idx + 1)));
         }
         Voice {
+            osc_cnt: DEFAULT_OSC_CNT,
             // use offset instead of nested vector
-            fm_mod: vec![0.0; OSC_CNT * OSC_CNT],
+            fm_mod: vec![0.0; MAX_OSC_CNT * MAX_OSC_CNT],
+            ring_mod: vec![0.0; MAX_OSC_CNT * MAX_OSC_CNT],
+            sync: vec![None; MAX_OSC_CNT],
+            bleps: (0..MAX_OSC_CNT)
+                .map(|_| MinBlep::new(blep_table.clone()))
+                .collect(),
+            last_samples: [0.0; MAX_OSC_CNT],
             levels,
-            pan: vec![Stereo(MINUS_THREE_DB, MINUS_THREE_DB); OSC_CNT],
+            pan: vec![Stereo(MINUS_THREE_DB, MINUS_THREE_DB); MAX_OSC_CNT],
             volume_envelopes,
             oscillators,
+            steal_fade: ::std::cmp::max(1, (STEAL_FADE_TIME * sample_rate as Time) as usize),
+            fade_ticks: 0,
+            pending: Vec::with_capacity(2), // the stolen note and its note off
         }
     }
     fn running(&self) -> bool {
-        self.volume_envelopes
+        self.fade_ticks > 0 || self
+            .volume_envelopes
             .iter()
+            .take(self.osc_cnt)
             .all(|envelope| envelope.state() != ADSRState::Off)
     }
+    /// Returns the summed envelope level of all oscillators.
+    fn loudness(&self) -> Float {
+        self.volume_envelopes
+            .iter()
+            .zip(self.levels.iter())
+            .take(self.osc_cnt)
+            .fold(0.0, |acc, (envelope, level)| acc + envelope.level() * level)
+    }
+    /// Fades the voice out and plays the given note afterwards.
+    /// A steal request for an already fading voice replaces the pending note.
+    fn steal(&mut self, msg: &ControlEvent) {
+        if self.fade_ticks == 0 {
+            self.fade_ticks = self.steal_fade;
+        }
+        self.pending.clear();
+        self.pending.push(msg.clone());
+    }
+    fn retrigger(&mut self) {
+        for envelope in &mut self.volume_envelopes {
+            envelope.reset();
+        }
+        // the buffer is put back, so it keeps its capacity for the next steal
+        let mut pending = ::std::mem::replace(&mut self.pending, Vec::new());
+        for msg in pending.drain(..) {
+            self.handle(&msg);
+        }
+        self.pending = pending;
+    }
     fn tick(&mut self) -> Stereo {
-        let mut samples = [0.0; OSC_CNT];
+        let osc_cnt = self.osc_cnt;
+        let mut samples = [0.0; MAX_OSC_CNT];
         let mut frame = Stereo::default();
         // tick each oscillator + apply env
-        for (_idx, (sample, oscillator, envelope, level, pan)) in Zip::new((
+        for (sample, oscillator, blep, envelope) in Zip::new((
             &mut samples,
             &mut self.oscillators,
+            &mut self.bleps,
             &mut self.volume_envelopes,
-            &self.levels,
-            &self.pan,
-        )).take(OSC_CNT)
-        .enumerate()
+        )).take(osc_cnt)
+        {
+            *sample = (oscillator.tick() + blep.tick()) * envelope.tick();
+        }
+        self.sync_oscillators();
+        // all oscillators are modulated by the unmodulated samples
+        let unmodulated = samples;
+        for (idx, (sample, level, pan)) in Zip::new((&mut samples, &self.levels, &self.pan))
+            .take(osc_cnt)
+            .enumerate()
         {
-            *sample = oscillator.tick() * envelope.tick();
+            *sample = Zip::new((&unmodulated, self.ring_mod.iter().skip(idx * MAX_OSC_CNT)))
+                .take(osc_cnt)
+                .fold(*sample, |acc, (modulator, mod_level)| {
+                    acc * (1.0 - mod_level + mod_level * modulator)
+                });
             frame += Stereo(*sample, *sample) * *level * *pan;
         }
-        for (idx, oscillator) in self.oscillators.iter_mut().enumerate() {
-            let phase = Zip::new((&mut samples, self.fm_mod.iter().skip(idx * OSC_CNT)))
-                .take(OSC_CNT)
-                .fold(0.0, |acc, (sample, mod_index)| acc + *sample * mod_index);
+        for (idx, oscillator) in self.oscillators.iter_mut().take(osc_cnt).enumerate() {
+            let phase = Zip::new((
+                &samples,
+                &self.last_samples,
+                self.fm_mod.iter().skip(idx * MAX_OSC_CNT),
+            )).take(osc_cnt)
+            .enumerate()
+            .fold(0.0, |acc, (modulator, (sample, last_sample, mod_index))| {
+                // the feedback is averaged over two samples like in the DX7,
+                // otherwise it starts to oscillate at high levels
+                let sample = if modulator == idx {
+                    (sample + last_sample) / 2.0
+                } else {
+                    *sample
+                };
+                acc + sample * mod_index
+            });
             oscillator.set_phase(phase);
         }
-        frame
+        self.last_samples = samples;
+        if self.fade_ticks > 0 {
+            let gain = self.fade_ticks as Float / self.steal_fade as Float;
+            self.fade_ticks -= 1;
+            if self.fade_ticks == 0 {
+                self.retrigger();
+            }
+            frame * gain
+        } else {
+            frame
+        }
+    }
+    /// Restarts the periods of the oscillators whose sync source started a new period
+    /// and corrects the discontinuities with band-limited steps.
+    fn sync_oscillators(&mut self) {
+        for idx in 0..self.osc_cnt {
+            // oscillators that are not played don't start new periods
+            let wrapped = self.sync[idx]
+                .filter(|&source| source < self.osc_cnt)
+                .and_then(|source| self.oscillators[source].wrapped());
+            let offset = match wrapped {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let delta = self.oscillators[idx].sync(offset);
+            self.bleps[idx].add(offset, delta);
+        }
+    }
+    /// Replaces the modulation matrix and the oscillator levels with the topology
+    /// of an algorithm, the other modulations are turned off.
+    fn set_algorithm(&mut self, algorithm: &Algorithm) {
+        self.osc_cnt = algorithm.operators;
+        for level in self.fm_mod.iter_mut().chain(self.ring_mod.iter_mut()) {
+            *level = 0.0;
+        }
+        for &(modulator, carrier) in algorithm.modulations {
+            self.fm_mod[(carrier - 1) * MAX_OSC_CNT + modulator - 1] = ALGORITHM_MOD_INDEX;
+        }
+        let (modulator, carrier) = algorithm.feedback;
+        self.fm_mod[(carrier - 1) * MAX_OSC_CNT + modulator - 1] =
+            ALGORITHM_MOD_INDEX * FEEDBACK_SCALE;
+        // the carriers share the level of a single oscillator
+        let carrier_level = MINUS_THREE_DB / algorithm.carriers.len() as Float;
+        for (idx, level) in self.levels.iter_mut().enumerate() {
+            *level = if algorithm.carriers.contains(&(idx + 1)) {
+                carrier_level
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+/// Returns the index of the oscillator with the given id.
+fn osc_index(id: &str) -> Option<usize> {
+    if !id.starts_with("OSC") {
+        return None;
+    }
+    match id[3..].parse::<usize>() {
+        Ok(number) if number > 0 && number <= MAX_OSC_CNT => Some(number - 1),
+        _ => None,
     }
 }
+
 impl Controllable for Voice {
     fn handle(&mut self, msg: &ControlEvent) {
         match *msg {
+            ControlEvent::NoteOn { .. } | ControlEvent::NoteOff { .. } if self.fade_ticks > 0 => {
+                self.pending.push(msg.clone())
+            }
             ControlEvent::Volume(ref volume) => {
                 for (old_vol, new_vol) in self.levels.iter_mut().zip(volume.iter()) {
                     *old_vol = if *new_vol < -60.0 {
@@ -110,26 +259,51 @@ impl Controllable for Voice {
                 }
             }
             ControlEvent::FM { ref id, ref levels } => {
-                let offset = match id.as_ref() {
-                    "OSC1" => 0,
-                    "OSC2" => 1,
-                    "OSC3" => 2,
-                    "OSC4" => 3,
-                    _ => self.fm_mod.len(), // offset is larger than the length of the vector -> no modification
-                } * OSC_CNT;
-                for (idx, (old_level, new_level)) in self
-                    .fm_mod
-                    .iter_mut()
-                    .skip(offset)
-                    .take(OSC_CNT)
-                    .zip(levels.iter())
-                    .enumerate()
-                {
-                    *old_level = if idx == offset {
-                        *new_level * 0.1 // feedback modulation easily creates feedback
-                    } else {
-                        *new_level
-                    };
+                if let Some(osc) = osc_index(id) {
+                    for (idx, (old_level, new_level)) in self
+                        .fm_mod
+                        .iter_mut()
+                        .skip(osc * MAX_OSC_CNT)
+                        .take(MAX_OSC_CNT)
+                        .zip(levels.iter())
+                        .enumerate()
+                    {
+                        *old_level = if idx == osc {
+                            *new_level * FEEDBACK_SCALE
+                        } else {
+                            *new_level
+                        };
+                    }
+                }
+            }
+            ControlEvent::RingMod { ref id, ref levels } => {
+                if let Some(idx) = osc_index(id) {
+                    for (old_level, new_level) in self
+                        .ring_mod
+                        .iter_mut()
+                        .skip(idx * MAX_OSC_CNT)
+                        .take(MAX_OSC_CNT)
+                        .zip(levels.iter())
+                    {
+                        *old_level = new_level.max(0.0).min(1.0);
+                    }
+                }
+            }
+            ControlEvent::Sync { ref id, ref source } => {
+                if let Some(idx) = osc_index(id) {
+                    // an oscillator can't be synced to itself
+                    self.sync[idx] = source
+                        .as_ref()
+                        .and_then(|source| osc_index(source))
+                        .filter(|&source| source != idx);
+                }
+            }
+            ControlEvent::OscillatorCount(count) => {
+                self.osc_cnt = count.max(1).min(MAX_OSC_CNT);
+            }
+            ControlEvent::FMAlgorithm(ref name) => {
+                if let Some(algorithm) = find_algorithm(name) {
+                    self.set_algorithm(algorithm);
                 }
             }
             _ => {
@@ -144,39 +318,208 @@ impl Controllable for Voice {
     }
 }
 
+/// Strategies to choose a voice for a new note.
+/// If no voice is free, a voice whose key was already released is stolen before a held one.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum VoiceAllocation {
+    /// Steals the voice that plays the oldest note.
+    Oldest,
+    /// Steals the voice with the lowest envelope level.
+    Quietest,
+    /// Steals the voice that plays the lowest key.
+    LowestNote,
+    /// Steals the voice that plays the highest key.
+    HighestNote,
+    /// Retriggers a voice that already plays the same key, otherwise like `Oldest`.
+    SameNote,
+    /// Cycles through the voices.
+    RoundRobin,
+}
+
+/// The note that is played by a voice.
+#[derive(Debug, Clone, Copy)]
+struct Note {
+    key: u8,
+    held: bool,
+    started: usize, // value of the note counter when the note was started
+}
+
 pub struct VoiceManager {
+    pitch_convert: SharedMut<PitchConvert>,
+    wavetables: SharedMut<WavetableBank>,
+    user_wavetables: Vec<String>, // names of the `Waveform::User` wavetables
+    spectrum_renderer: SpectrumRenderer,
+    spectrum_selected: Vec<String>, // oscillators that play their rendered spectrum
     voices: Vec<Voice>,
-    note_queue: VecDeque<(u8, usize)>,
+    notes: Vec<Option<Note>>,
+    allocation: VoiceAllocation,
+    note_count: usize,
+    next_voice: usize,
 }
 impl VoiceManager {
-    pub fn new(max_voices: usize, sample_rate: usize) -> Self {
-        let wavetables = Rc::new(dsp::generate_wavetables(20.0, sample_rate));
-        let pitch_convert = Rc::new(PitchConvert::default());
+    pub fn new(
+        max_voices: usize,
+        sample_rate: usize,
+        cache: &WavetableCache,
+        spectrum_renderer: SpectrumRenderer,
+    ) -> Self {
+        let mut wavetables = dsp::generate_wavetables(LOWEST_FREQ, sample_rate, cache);
+        // leave room for the spectra, which are added on the audio thread
+        wavetables.reserve(MAX_OSC_CNT);
+        let wavetables = SharedMut::wrap(wavetables);
+        let pitch_convert = SharedMut::wrap(PitchConvert::default());
+        let blep_table = minblep_table();
         let mut voices = Vec::with_capacity(max_voices);
         for _ in 0..max_voices {
             voices.push(Voice::new(
                 sample_rate,
                 &wavetables,
                 &pitch_convert,
+                &blep_table,
             ));
         }
         VoiceManager {
+            pitch_convert,
+            wavetables,
+            user_wavetables: Vec::with_capacity(MAX_OSC_CNT),
+            spectrum_renderer,
+            spectrum_selected: Vec::new(),
             voices,
-            note_queue: VecDeque::with_capacity(max_voices),
+            notes: vec![None; max_voices],
+            allocation: VoiceAllocation::Oldest,
+            note_count: 0,
+            next_voice: 0,
         }
     }
 
-    fn free_voice(&self) -> Option<usize> {
-        for (idx, voice) in self.voices.iter().enumerate() {
-            if !voice.running() {
-                return Some(idx);
+    /// Registers a wavetable for all oscillators and returns its index.
+    fn add_wavetable(&mut self, name: &str, frames: &WavetableFrames) -> usize {
+        let idx = match self.user_wavetables.iter().position(|other| other == name) {
+            Some(idx) => idx,
+            None => {
+                self.user_wavetables.push(name.to_owned());
+                self.user_wavetables.reserve(MAX_OSC_CNT);
+                self.wavetables.borrow_mut().reserve(MAX_OSC_CNT);
+                self.user_wavetables.len() - 1
+            }
+        };
+        self.wavetables
+            .borrow_mut()
+            .insert(Waveform::User(idx), frames.clone());
+        // oscillators that play a replaced wavetable have to update their frames
+        let msg = ControlEvent::AddWavetable {
+            name: name.to_owned(),
+            frames: frames.clone(),
+        };
+        for voice in &mut self.voices {
+            voice.handle(&msg)
+        }
+        idx
+    }
+
+    /// Registers a rendered spectrum and selects it if the oscillator didn't switch to
+    /// another waveform in the meantime. The spectrum and the frames that it replaced are
+    /// returned to the renderer, so that they aren't freed on the audio thread.
+    fn add_spectrum(&mut self, mut spectrum: RenderedSpectrum) {
+        let idx = match self
+            .user_wavetables
+            .iter()
+            .position(|other| *other == spectrum.name)
+        {
+            Some(idx) => idx,
+            None => {
+                let name = ::std::mem::take(&mut spectrum.name);
+                self.user_wavetables.push(name);
+                self.user_wavetables.len() - 1
+            }
+        };
+        let replaced = self
+            .wavetables
+            .borrow_mut()
+            .insert(Waveform::User(idx), spectrum.frames.clone());
+        // oscillators that play a replaced wavetable have to update their frames
+        for voice in &mut self.voices {
+            voice.handle(&spectrum.add)
+        }
+        if self.spectrum_selected.contains(&spectrum.id) {
+            if let ControlEvent::Waveform {
+                ref mut waveform, ..
+            } = spectrum.select
+            {
+                *waveform = Waveform::User(idx);
+            }
+            for voice in &mut self.voices {
+                voice.handle(&spectrum.select)
+            }
+        }
+        self.spectrum_renderer.retire(spectrum, replaced);
+    }
+
+    /// Returns the index of the voice that should play the given key and
+    /// whether the voice has to be stolen.
+    fn allocate(&self, key: u8) -> (usize, bool) {
+        if self.allocation == VoiceAllocation::SameNote {
+            let same_note = (0..self.voices.len()).find(|&idx| {
+                self.voices[idx].running() && self.notes[idx].map_or(false, |note| note.key == key)
+            });
+            if let Some(idx) = same_note {
+                return (idx, false);
             }
         }
-        None
+        match self.free_voice() {
+            Some(idx) => (idx, false),
+            None => (self.steal_voice(), true),
+        }
+    }
+
+    fn free_voice(&self) -> Option<usize> {
+        let len = self.voices.len();
+        let start = match self.allocation {
+            VoiceAllocation::RoundRobin => self.next_voice,
+            _ => 0,
+        };
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|&idx| !self.voices[idx].running())
+    }
+
+    fn steal_voice(&self) -> usize {
+        self.steal_candidate(true)
+            .or_else(|| self.steal_candidate(false))
+            .unwrap_or(0)
+    }
+
+    fn steal_candidate(&self, released_only: bool) -> Option<usize> {
+        let len = self.voices.len();
+        let candidates = self
+            .notes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, note)| note.map(|note| (idx, note)))
+            .filter(|&(_, note)| !(released_only && note.held));
+        match self.allocation {
+            VoiceAllocation::Oldest | VoiceAllocation::SameNote => {
+                candidates.min_by_key(|&(_, note)| note.started)
+            }
+            VoiceAllocation::Quietest => candidates.min_by(|&(a, _), &(b, _)| {
+                self.voices[a]
+                    .loudness()
+                    .partial_cmp(&self.voices[b].loudness())
+                    .unwrap_or(Ordering::Equal)
+            }),
+            VoiceAllocation::LowestNote => candidates.min_by_key(|&(_, note)| note.key),
+            VoiceAllocation::HighestNote => candidates.max_by_key(|&(_, note)| note.key),
+            VoiceAllocation::RoundRobin => {
+                candidates.min_by_key(|&(idx, _)| (idx + len - self.next_voice) % len)
+            }
+        }.map(|(idx, _)| idx)
     }
 }
 impl SignalSource for VoiceManager {
     fn tick(&mut self) -> Stereo {
+        if let Some(spectrum) = self.spectrum_renderer.try_recv() {
+            self.add_spectrum(spectrum);
+        }
         let mut out = Stereo::default();
         for voice in &mut self.voices {
             if voice.running() {
@@ -190,19 +533,83 @@ impl Controllable for VoiceManager {
     fn handle(&mut self, msg: &ControlEvent) {
         match *msg {
             ControlEvent::NoteOn { key, .. } => {
-                if let Some(idx) = self.free_voice() {
-                    self.note_queue.push_back((key, idx));
-                    self.voices[idx].handle(msg)
+                if self.voices.is_empty() {
+                    return;
+                }
+                let (idx, steal) = self.allocate(key);
+                if steal {
+                    self.voices[idx].steal(msg)
                 } else {
-                    let (_, old_idx) = self.note_queue.pop_front().unwrap_or((0, 0));
-                    self.voices[old_idx].handle(msg);
-                    self.note_queue.push_back((key, old_idx))
+                    self.voices[idx].handle(msg)
                 }
+                self.notes[idx] = Some(Note {
+                    key,
+                    held: true,
+                    started: self.note_count,
+                });
+                self.note_count = self.note_count.wrapping_add(1);
+                self.next_voice = (idx + 1) % self.voices.len();
             }
             ControlEvent::NoteOff { key, .. } => {
-                for &(played_key, idx) in &self.note_queue {
-                    if played_key == key {
-                        self.voices[idx].handle(msg)
+                for (voice, note) in self.voices.iter_mut().zip(self.notes.iter_mut()) {
+                    if let Some(ref mut note) = *note {
+                        if note.key == key && note.held {
+                            note.held = false;
+                            voice.handle(msg)
+                        }
+                    }
+                }
+            }
+            ControlEvent::VoiceAllocation(allocation) => self.allocation = allocation,
+            ControlEvent::Tuning(ref table) => {
+                // swap the table that is shared by all oscillators before they retune
+                self.pitch_convert.borrow_mut().set_table(table);
+                for voice in &mut self.voices {
+                    voice.handle(msg)
+                }
+            }
+            ControlEvent::KeyTuning(ref freqs) => {
+                self.pitch_convert.borrow_mut().set_key_freqs(freqs);
+                for voice in &mut self.voices {
+                    voice.handle(msg)
+                }
+            }
+            ControlEvent::AddWavetable {
+                ref name,
+                ref frames,
+            } => {
+                self.add_wavetable(name, frames);
+            }
+            // the spectrum is rendered from the queue of the receiving thread
+            ControlEvent::Spectrum { ref id, .. } => {
+                if !self.spectrum_selected.contains(id) {
+                    self.spectrum_selected.push(id.clone());
+                }
+            }
+            ControlEvent::Waveform { ref id, .. } => {
+                self.spectrum_selected.retain(|other| other != id);
+                for voice in &mut self.voices {
+                    voice.handle(msg)
+                }
+            }
+            // unknown algorithms, e.g. of a patch from a newer version, are ignored
+            ControlEvent::FMAlgorithm(ref name) => {
+                if find_algorithm(name).is_some() {
+                    for voice in &mut self.voices {
+                        voice.handle(msg)
+                    }
+                }
+            }
+            // unknown wavetables, e.g. of a patch from another machine, are ignored
+            ControlEvent::UserWaveform { ref id, ref name } => {
+                if let Some(idx) = self.user_wavetables.iter().position(|other| other == name) {
+                    self.spectrum_selected.retain(|other| other != id);
+                    let msg = ControlEvent::Waveform {
+                        id: id.clone(),
+                        waveform: Waveform::User(idx),
+                    };
+                    for voice in &mut self.voices {
+                        voice.handle(&msg)
                     }
                 }
             }
@@ -214,3 +621,213 @@ impl Controllable for VoiceManager {
         }
     }
 }
+
+#[cfg(test)]
+fn voice_manager(max_voices: usize) -> VoiceManager {
+    VoiceManager::new(
+        max_voices,
+        48_000,
+        &WavetableCache::new(None, false),
+        SpectrumRenderer::new(LOWEST_FREQ, 48_000),
+    )
+}
+
+#[test]
+fn test_note_off_after_steal() {
+    let mut manager = voice_manager(2);
+    for key in &[60, 62, 64] {
+        manager.handle(&ControlEvent::NoteOn {
+            key: *key,
+            velocity: 1.0,
+        });
+    }
+    // the oldest note was stolen
+    assert_eq!(manager.notes[0].map(|note| note.key), Some(64));
+    assert_eq!(manager.voices[0].fade_ticks, manager.voices[0].steal_fade);
+    for _ in 0..manager.voices[0].steal_fade {
+        manager.tick();
+    }
+    assert!(manager.voices[0].pending.is_empty());
+    assert!(manager.voices[0].pending.capacity() > 0);
+    assert_eq!(
+        manager.voices[0].volume_envelopes[0].state(),
+        ADSRState::Attack
+    );
+    // releasing the stolen key must not release the new note
+    manager.handle(&ControlEvent::NoteOff {
+        key: 60,
+        velocity: 0.0,
+    });
+    assert_eq!(
+        manager.voices[0].volume_envelopes[0].state(),
+        ADSRState::Attack
+    );
+    manager.handle(&ControlEvent::NoteOff {
+        key: 64,
+        velocity: 0.0,
+    });
+    assert_eq!(
+        manager.voices[0].volume_envelopes[0].state(),
+        ADSRState::Release
+    );
+    assert_eq!(manager.notes.len(), 2);
+}
+
+#[test]
+fn test_voice_allocation() {
+    let play = |allocation: VoiceAllocation, keys: &[u8], released: &[u8], key: u8| {
+        let mut manager = voice_manager(3);
+        manager.handle(&ControlEvent::VoiceAllocation(allocation));
+        for key in keys {
+            manager.handle(&ControlEvent::NoteOn {
+                key: *key,
+                velocity: 1.0,
+            });
+        }
+        for key in released {
+            manager.handle(&ControlEvent::NoteOff {
+                key: *key,
+                velocity: 0.0,
+            });
+        }
+        manager.allocate(key)
+    };
+    assert_eq!(play(VoiceAllocation::Oldest, &[62, 60, 64], &[], 65), (0, true));
+    assert_eq!(play(VoiceAllocation::Oldest, &[62, 60, 64], &[64], 65), (2, true));
+    assert_eq!(play(VoiceAllocation::LowestNote, &[62, 60, 64], &[], 65), (1, true));
+    assert_eq!(play(VoiceAllocation::HighestNote, &[62, 60, 64], &[], 65), (2, true));
+    assert_eq!(play(VoiceAllocation::SameNote, &[62, 60], &[60], 60), (1, false));
+    assert_eq!(play(VoiceAllocation::Oldest, &[62, 60], &[60], 60), (2, false));
+    assert_eq!(play(VoiceAllocation::RoundRobin, &[62, 60, 64], &[60], 65), (1, true));
+    assert_eq!(play(VoiceAllocation::RoundRobin, &[62, 60, 64], &[], 65), (0, true));
+}
+
+#[test]
+fn test_fm_feedback() {
+    let mut manager = voice_manager(1);
+    manager.handle(&ControlEvent::FM {
+        id: "OSC2".to_owned(),
+        levels: vec![0.5, 1.0, 0.0, 0.0],
+    });
+    // the level of the oscillator itself is its scaled feedback
+    assert_eq!(
+        &manager.voices[0].fm_mod[MAX_OSC_CNT..MAX_OSC_CNT + 4],
+        &[0.5, 0.1, 0.0, 0.0]
+    );
+    manager.handle(&ControlEvent::FM {
+        id: "OSC1".to_owned(),
+        levels: vec![10.0],
+    });
+    manager.handle(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    for _ in 0..4800 {
+        let Stereo(left, right) = manager.tick();
+        assert!(left.is_finite() && right.is_finite());
+    }
+}
+
+#[test]
+fn test_sync_and_ring_mod() {
+    let mut manager = voice_manager(1);
+    let sync = |source: Option<&str>| ControlEvent::Sync {
+        id: "OSC2".to_owned(),
+        source: source.map(str::to_owned),
+    };
+    manager.handle(&sync(Some("OSC1")));
+    assert_eq!(manager.voices[0].sync[1], Some(0));
+    // an oscillator can't be synced to itself
+    manager.handle(&sync(Some("OSC2")));
+    assert_eq!(manager.voices[0].sync[1], None);
+    manager.handle(&sync(Some("OSC3")));
+    manager.handle(&sync(None));
+    assert_eq!(manager.voices[0].sync[1], None);
+
+    // an oscillator that ring modulates itself is squared
+    manager.handle(&ControlEvent::RingMod {
+        id: "OSC1".to_owned(),
+        levels: vec![1.0, 0.0, 0.0, 0.0],
+    });
+    manager.handle(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    let mut peak: Float = 0.0;
+    for _ in 0..4800 {
+        let Stereo(left, right) = manager.tick();
+        assert!(left >= 0.0 && right >= 0.0);
+        peak = peak.max(left);
+    }
+    assert!(peak > 0.1);
+}
+
+#[test]
+fn test_fm_algorithm() {
+    let mut manager = voice_manager(1);
+    manager.handle(&ControlEvent::FMAlgorithm("DX7-5".to_owned()));
+    {
+        let voice = &manager.voices[0];
+        assert_eq!(voice.osc_cnt, 6);
+        // operator 2 modulates operator 1, operator 6 has feedback
+        assert_eq!(voice.fm_mod[1], ALGORITHM_MOD_INDEX);
+        assert_eq!(voice.fm_mod[2 * MAX_OSC_CNT + 3], ALGORITHM_MOD_INDEX);
+        assert_eq!(voice.fm_mod[5 * MAX_OSC_CNT + 5], ALGORITHM_MOD_INDEX * FEEDBACK_SCALE);
+        assert_eq!(voice.fm_mod.iter().filter(|level| **level > 0.0).count(), 4);
+        let carrier_level = MINUS_THREE_DB / 3.0;
+        assert_eq!(
+            &voice.levels[..],
+            &[carrier_level, 0.0, carrier_level, 0.0, carrier_level, 0.0, 0.0, 0.0]
+        );
+    }
+    // unknown algorithms are ignored
+    manager.handle(&ControlEvent::FMAlgorithm("DX7-33".to_owned()));
+    assert_eq!(manager.voices[0].osc_cnt, 6);
+    manager.handle(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    for _ in 0..4800 {
+        let Stereo(left, right) = manager.tick();
+        assert!(left.is_finite() && right.is_finite());
+    }
+    manager.handle(&ControlEvent::OscillatorCount(12));
+    assert_eq!(manager.voices[0].osc_cnt, MAX_OSC_CNT);
+    manager.handle(&ControlEvent::OscillatorCount(2));
+    assert_eq!(manager.voices[0].osc_cnt, 2);
+    // only the envelopes of the played oscillators keep the voice running
+    manager.voices[0].volume_envelopes[MAX_OSC_CNT - 1].reset();
+    assert!(manager.voices[0].running());
+    manager.voices[0].volume_envelopes[0].reset();
+    assert!(!manager.voices[0].running());
+}
+
+#[test]
+fn test_add_spectrum() {
+    let mut manager = voice_manager(1);
+    let queue = manager.spectrum_renderer.queue();
+    manager.handle(&ControlEvent::Spectrum {
+        id: "OSC1".to_owned(),
+        amplitudes: vec![1.0],
+        phases: vec![],
+    });
+    let mut render = |amplitudes: &[Float]| {
+        queue.render("OSC1", amplitudes, &[]);
+        let spectrum = loop {
+            if let Some(spectrum) = manager.spectrum_renderer.try_recv() {
+                break spectrum;
+            }
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        };
+        let frames = spectrum.frames.clone();
+        manager.add_spectrum(spectrum);
+        assert_eq!(manager.user_wavetables, vec!["SPECTRUM-OSC1"]);
+        assert!(Arc::ptr_eq(
+            &manager.wavetables.borrow()[&Waveform::User(0)],
+            &frames
+        ));
+    };
+    render(&[1.0]);
+    // a new spectrum replaces the wavetable of the oscillator
+    render(&[0.0, 1.0]);
+}
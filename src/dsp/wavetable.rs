@@ -3,28 +3,55 @@ extern crate num;
 extern crate rand;
 extern crate rustfft;
 
-use self::bincode::rustc_serialize::{decode_from, encode_into};
+use self::bincode::rustc_serialize::{decode, encode};
 use self::bincode::SizeLimit;
 use self::rustfft::algorithm::Radix4;
 use self::rustfft::num_complex::Complex;
 use self::rustfft::num_traits::Zero;
-use self::rustfft::FFT;
+use self::rustfft::{FFTplanner, FFT};
+use std::cmp;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::rc::Rc;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use dsp::env_gen::ADSR;
+use dsp::lfo::Lfo;
+use dsp::noise::Noise;
+use errors::RunError;
 use event::{ControlEvent, Controllable};
-use io::PitchConvert;
+use io::{read_wav, PitchConvert};
 use types::*;
 
 const OVERSAMPLING: usize = 2;
 const INVERSE: bool = true;
 const SCALE: bool = true;
+/// Narrowest pulse width, narrower pulses get too quiet.
+const MIN_PULSE_WIDTH: Float = 0.01;
+/// Samples on each side of the interpolated position for `Interpolation::Sinc`.
+const SINC_HALF_TAPS: isize = 8;
+/// Frame size of wavetable files without a `clm ` chunk.
+const DEFAULT_FRAME_SIZE: usize = 2048;
+pub const MAX_FRAMES: usize = 256;
+/// Length of the crossfade when the tables of the oscillator are replaced.
+const WAVEFORM_FADE_TIME: Time = 0.005;
+
+/// The lowest fundamental frequency the wavetables are band-limited for.
+pub const LOWEST_FREQ: Float = 20.0;
+/// Range of the level change of the key scaling in dB.
+const MIN_KEY_SCALING_LEVEL: Float = -96.0;
+const MAX_KEY_SCALING_LEVEL: Float = 12.0;
+
+/// The band-limited wavetables of each frame of a waveform.
+pub type WavetableFrames = Arc<Vec<Vec<Wavetable>>>;
+/// All waveforms that can be selected by the oscillators.
+pub type WavetableBank = HashMap<Waveform, WavetableFrames>;
 
 /// Stores a period of a band-limited signal together with
 /// the maximum frequency before aliasing occurs.
-#[derive(RustcDecodable, RustcEncodable)]
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
 pub struct Wavetable {
     /// The band-limited signal
     table: Vec<Float>,
@@ -35,22 +62,95 @@ pub struct Wavetable {
     max_phase_incr: Float,
 }
 impl Wavetable {
-    /// Returns a linear interpolated sample from the wavetable for the given phase.
+    /// Returns an interpolated sample from the wavetable for the given phase.
     /// The phase is mapped to a table index.
-    fn sample(&self, phasor: Float) -> Float {
+    fn sample(&self, phasor: Float, interpolation: Interpolation) -> Float {
         let table_len = self.table.len();
         let idx = if phasor < 0.0 { phasor + 1.0 } else { phasor } * table_len as Float;
-        // linear interpolation
-        let (i, j) = (
-            idx.floor() as usize % table_len,
-            idx.ceil() as usize % table_len,
-        );
-        self.table[i] + (self.table[j] - self.table[i]) * (idx - i as Float)
+        let i = idx.floor() as usize % table_len;
+        let fraction = idx - idx.floor();
+        // the sample at `offset` relative to `i`
+        let at =
+            |offset: isize| self.table[((i + table_len) as isize + offset) as usize % table_len];
+        match interpolation {
+            Interpolation::Linear => at(0) + (at(1) - at(0)) * fraction,
+            Interpolation::Cubic => {
+                // 4-point, 3rd-order Hermite
+                let (y0, y1, y2, y3) = (at(-1), at(0), at(1), at(2));
+                let c1 = 0.5 * (y2 - y0);
+                let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+                let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+                ((c3 * fraction + c2) * fraction + c1) * fraction + y1
+            }
+            Interpolation::Sinc => {
+                // `sin(PI * (fraction - k))` only differs in its sign for the taps
+                let sine = Float::sin(PI * fraction);
+                // the cosine of the window is rotated from tap to tap
+                let (step_sin, step_cos) = Float::sin_cos(PI / SINC_HALF_TAPS as Float);
+                let angle =
+                    PI * (fraction + (SINC_HALF_TAPS - 1) as Float) / SINC_HALF_TAPS as Float;
+                let (mut window_sin, mut window_cos) = angle.sin_cos();
+                let (mut sum, mut weights) = (0.0, 0.0);
+                for k in 1 - SINC_HALF_TAPS..SINC_HALF_TAPS + 1 {
+                    let x = fraction - k as Float;
+                    let sinc = if x.abs() < 1e-9 {
+                        1.0
+                    } else if k % 2 == 0 {
+                        sine / (PI * x)
+                    } else {
+                        -sine / (PI * x)
+                    };
+                    // Blackman window, `cos(2a) = 2cos(a)^2 - 1`
+                    let window = 0.34 + window_cos * (0.5 + 0.16 * window_cos);
+                    let weight = sinc * window;
+                    sum += at(k) * weight;
+                    weights += weight;
+                    let rotated_cos = window_cos * step_cos + window_sin * step_sin;
+                    window_sin = window_sin * step_cos - window_cos * step_sin;
+                    window_cos = rotated_cos;
+                }
+                sum / weights
+            }
+        }
+    }
+}
+
+/// Interpolation between the samples of a wavetable, ordered by quality and CPU cost.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, RustcDecodable, RustcEncodable)]
+pub enum Interpolation {
+    Linear,
+    /// Cubic Hermite interpolation of four samples.
+    Cubic,
+    /// Windowed-sinc interpolation of `2 * SINC_HALF_TAPS` samples.
+    Sinc,
+}
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Linear
+    }
+}
+
+/// The level change of the key scaling on one side of the breakpoint,
+/// the depth is the change in dB at an octave from the breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum KeyScalingCurve {
+    /// Changes the level by the depth every octave.
+    Linear(Float),
+    /// Doubles the level change every octave.
+    Exponential(Float),
+}
+impl KeyScalingCurve {
+    /// Returns the level change in dB at the given distance from the breakpoint.
+    fn level(self, octaves: Float) -> Float {
+        match self {
+            KeyScalingCurve::Linear(depth) => depth * octaves,
+            KeyScalingCurve::Exponential(depth) => depth * (Float::powf(2.0, octaves) - 1.0),
+        }
     }
 }
 
 /// Implemented waveforms.
-#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone, RustcDecodable, RustcEncodable)]
 pub enum Waveform {
     Sine,
     Saw,
@@ -58,6 +158,26 @@ pub enum Waveform {
     Tri,
     SharpTri,
     Random,
+    /// A wavetable that was loaded from a file, see `load_wavetable`.
+    User(usize),
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+    /// Bandpassed noise, see `Noise::set_tone`.
+    ColoredNoise,
+    /// A pulse with a variable width, built from two saws.
+    Pulse,
+}
+impl Waveform {
+    /// Returns `true` for the noise generators, which don't use wavetables.
+    pub fn is_noise(self) -> bool {
+        [
+            Waveform::WhiteNoise,
+            Waveform::PinkNoise,
+            Waveform::BrownNoise,
+            Waveform::ColoredNoise,
+        ].contains(&self)
+    }
 }
 
 /// Normalizes the signal into a range of `[-1.0, 1.0]`.
@@ -75,11 +195,13 @@ macro_rules! scale {
 }
 
 /// Builds wavetables for each waveform and returns a `HashMap` containing them.
+/// The tables are read from the cache if it contains matching ones.
 pub fn generate_wavetables(
     fundamental_freq: Float,
     sample_rate: usize,
-) -> HashMap<Waveform, Vec<Wavetable>> {
-    let mut tables: HashMap<Waveform, Vec<Wavetable>> = HashMap::new();
+    cache: &WavetableCache,
+) -> WavetableBank {
+    let mut tables = WavetableBank::new();
     for waveform in &[
         Waveform::Saw,
         Waveform::Sine,
@@ -88,32 +210,179 @@ pub fn generate_wavetables(
         Waveform::SharpTri,
         Waveform::Random,
     ] {
-        let filename = format!(
-            "ytterbium-{}-wavetable-{:?}.bin",
-            env!("CARGO_PKG_VERSION"),
-            waveform
-        );
-        let band_limited_table = {
-            if let Ok(file) = File::open(&filename) {
-                let mut reader = BufReader::new(file);
-                decode_from(&mut reader, SizeLimit::Infinite)
-                    .unwrap_or_else(|_| panic!("could not decode wavetable: {}", filename))
-            } else {
+        let band_limited_table = match cache.load(*waveform, fundamental_freq, sample_rate) {
+            Some(band_limited_table) => band_limited_table,
+            None => {
                 let band_limited_table = build_wavetables(*waveform, fundamental_freq, sample_rate);
-                let file = File::create(&filename).unwrap_or_else(|_| {
-                    panic!("could not create file for wavetable: {}", filename)
-                });
-                let mut writer = BufWriter::new(file);
-                encode_into(&band_limited_table, &mut writer, SizeLimit::Infinite)
-                    .unwrap_or_else(|_| panic!("could not encode wavetable: {}", filename));
+                cache.store(
+                    *waveform,
+                    fundamental_freq,
+                    sample_rate,
+                    &band_limited_table,
+                );
                 band_limited_table
             }
         };
-        tables.insert(*waveform, band_limited_table);
+        tables.insert(*waveform, Arc::new(vec![band_limited_table]));
     }
     tables
 }
 
+/// Content of a cache file, the generation inputs are stored with the tables
+/// to detect files that were renamed or written by another version.
+#[derive(RustcDecodable, RustcEncodable)]
+struct CacheEntry {
+    version: String,
+    waveform: Waveform,
+    fundamental_freq: Float,
+    sample_rate: usize,
+    tables: Vec<Wavetable>,
+}
+
+/// Directory where the band-limited tables of the builtin waveforms are stored
+/// between runs.
+///
+/// Each file is named after all inputs of the generation and starts with a checksum
+/// of its content. Missing, corrupt or mismatching files are regenerated.
+pub struct WavetableCache {
+    dir: Option<PathBuf>, // `None` disables the cache
+    rebuild: bool,        // ignore the cached tables and overwrite them
+}
+impl WavetableCache {
+    pub fn new(dir: Option<PathBuf>, rebuild: bool) -> Self {
+        WavetableCache { dir, rebuild }
+    }
+
+    /// Returns `$XDG_CACHE_HOME/ytterbium` or `$HOME/.cache/ytterbium` if the
+    /// former is not set.
+    pub fn default_dir() -> Option<PathBuf> {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .map(|dir| dir.join("ytterbium"))
+    }
+
+    fn path(
+        &self,
+        waveform: Waveform,
+        fundamental_freq: Float,
+        sample_rate: usize,
+    ) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| {
+            dir.join(format!(
+                "wavetable-{}-{:?}-{}-{}.bin",
+                env!("CARGO_PKG_VERSION"),
+                waveform,
+                fundamental_freq,
+                sample_rate
+            ))
+        })
+    }
+
+    fn load(
+        &self,
+        waveform: Waveform,
+        fundamental_freq: Float,
+        sample_rate: usize,
+    ) -> Option<Vec<Wavetable>> {
+        let path = match self.path(waveform, fundamental_freq, sample_rate) {
+            Some(ref path) if !self.rebuild && path.exists() => path.clone(),
+            _ => return None,
+        };
+        let mut data = Vec::new();
+        let result = File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|err| err.to_string())
+            .and_then(|_| decode_cache_entry(&data));
+        match result {
+            Ok(ref entry)
+                if entry.version == env!("CARGO_PKG_VERSION")
+                    && entry.waveform == waveform
+                    && entry.fundamental_freq.to_bits() == fundamental_freq.to_bits()
+                    && entry.sample_rate == sample_rate =>
+            {
+                result.ok().map(|entry| entry.tables)
+            }
+            Ok(_) => {
+                println!(
+                    "Regenerating wavetable {}: mismatching parameters",
+                    path.display()
+                );
+                None
+            }
+            Err(err) => {
+                println!("Regenerating wavetable {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Writes the tables into a temporary file that is renamed afterwards, so that
+    /// concurrent or interrupted runs don't leave partial files behind.
+    fn store(
+        &self,
+        waveform: Waveform,
+        fundamental_freq: Float,
+        sample_rate: usize,
+        tables: &[Wavetable],
+    ) {
+        let path = match self.path(waveform, fundamental_freq, sample_rate) {
+            Some(path) => path,
+            None => return,
+        };
+        let entry = CacheEntry {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            waveform,
+            fundamental_freq,
+            sample_rate,
+            tables: tables.to_vec(),
+        };
+        let tmp_path = path.with_extension("tmp");
+        let result = encode(&entry, SizeLimit::Infinite)
+            .map_err(|err| err.to_string())
+            .and_then(|payload| {
+                let mut data = Vec::with_capacity(payload.len() + 8);
+                data.extend_from_slice(&checksum(&payload).to_le_bytes());
+                data.extend_from_slice(&payload);
+                fs::create_dir_all(self.dir.as_ref().unwrap())
+                    .and_then(|_| File::create(&tmp_path))
+                    .and_then(|mut file| file.write_all(&data))
+                    .and_then(|_| fs::rename(&tmp_path, &path))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            println!("Could not cache wavetable {}: {}", path.display(), err);
+        }
+    }
+}
+impl Default for WavetableCache {
+    fn default() -> Self {
+        WavetableCache::new(WavetableCache::default_dir(), false)
+    }
+}
+
+/// Verifies the checksum in front of the payload before decoding it, a corrupt
+/// length prefix could otherwise lead to huge allocations.
+fn decode_cache_entry(data: &[u8]) -> Result<CacheEntry, String> {
+    if data.len() < 8 {
+        return Err("file is truncated".to_owned());
+    }
+    let mut stored = [0; 8];
+    stored.copy_from_slice(&data[..8]);
+    if u64::from_le_bytes(stored) != checksum(&data[8..]) {
+        return Err("checksum mismatch".to_owned());
+    }
+    decode(&data[8..]).map_err(|err| err.to_string())
+}
+
+/// 64-bit FNV-1a hash.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 /// Builds the band-limited wavetables for the given waveform, fundamental frequency and
 /// sample rate.
 fn build_wavetables(
@@ -121,9 +390,7 @@ fn build_wavetables(
     fundamental_freq: Float,
     sample_rate: usize,
 ) -> Vec<Wavetable> {
-    let min_table_size = 64;
-    let mut phase_incr = fundamental_freq * 2.0 / sample_rate as Float;
-    let (mut harmonics, mut table_size) = match waveform {
+    let (harmonics, table_size) = match waveform {
         Waveform::Sine => (1, 4096),
         _ => {
             let harmonics = sample_rate / (2 * (2.0 * fundamental_freq) as usize);
@@ -131,6 +398,32 @@ fn build_wavetables(
             (harmonics, table_size)
         }
     };
+    build_mipmaps(
+        harmonics,
+        table_size,
+        fundamental_freq,
+        sample_rate,
+        SCALE,
+        |harmonics, spectrum| generate_spectrum(waveform, harmonics, spectrum),
+    )
+}
+
+/// Builds a table for each octave above the fundamental frequency, the number of
+/// harmonics is halved from table to table. `fill_spectrum` sets the spectrum of a
+/// table with the given number of harmonics.
+fn build_mipmaps<F>(
+    mut harmonics: usize,
+    mut table_size: usize,
+    fundamental_freq: Float,
+    sample_rate: usize,
+    scale: bool,
+    fill_spectrum: F,
+) -> Vec<Wavetable>
+where
+    F: Fn(usize, &mut Vec<Complex<Float>>),
+{
+    let min_table_size = 64;
+    let mut phase_incr = fundamental_freq * 2.0 / sample_rate as Float;
     let mut tables: Vec<Wavetable> = Vec::with_capacity((harmonics as Float).log2() as usize);
     // use sine if only 1 harmonic is left, otherwise the last table for waveforms with
     // only odd harmonics would be empty!
@@ -139,10 +432,10 @@ fn build_wavetables(
         let mut spectrum = vec![num::Complex::zero(); table_size];
         let mut signal = spectrum.clone();
 
-        generate_spectrum(waveform, harmonics, &mut spectrum);
+        fill_spectrum(harmonics, &mut spectrum);
 
         fft.process(spectrum.as_mut_slice(), signal.as_mut_slice());
-        scale!(SCALE, signal);
+        scale!(scale, signal);
 
         tables.push(Wavetable {
             table: signal.iter().map(|c| c.re).collect::<Vec<_>>(),
@@ -152,11 +445,140 @@ fn build_wavetables(
         harmonics >>= 1; // half the number of harmonics
         phase_incr *= 2.0;
         let next_table_size = harmonics.next_power_of_two() * 2 * OVERSAMPLING;
-        table_size = ::std::cmp::max(min_table_size, next_table_size);
+        table_size = cmp::max(min_table_size, next_table_size);
     }
     tables
 }
 
+/// Loads the frames of a wavetable file and band-limits each of them.
+///
+/// The frame size is read from the `clm ` chunk that is written by most wavetable
+/// editors. Files without it are split into frames of 2048 samples if possible,
+/// otherwise the whole file is used as a single cycle.
+pub fn load_wavetable<P: AsRef<Path>>(
+    path: P,
+    fundamental_freq: Float,
+    sample_rate: usize,
+) -> Result<Vec<Vec<Wavetable>>, RunError> {
+    let wav = try!(read_wav(path));
+    let len = wav.samples.len();
+    let frame_size = wav.frame_size.unwrap_or(if len % DEFAULT_FRAME_SIZE == 0 {
+        DEFAULT_FRAME_SIZE
+    } else {
+        len
+    });
+    if frame_size < 4 || len < frame_size {
+        return Err(RunError::WavError(format!(
+            "{} samples are too short for frames of {} samples",
+            len, frame_size
+        )));
+    }
+    if len / frame_size > MAX_FRAMES {
+        return Err(RunError::WavError(format!(
+            "more than {} frames of {} samples",
+            MAX_FRAMES, frame_size
+        )));
+    }
+    Ok(build_user_wavetables(
+        &wav.samples,
+        frame_size,
+        fundamental_freq,
+        sample_rate,
+    ))
+}
+
+/// Band-limits each frame of the signal. All frames are normalized by the same
+/// factor, so that their levels don't jump when the frames are crossfaded.
+pub fn build_user_wavetables(
+    samples: &[Float],
+    frame_size: usize,
+    fundamental_freq: Float,
+    sample_rate: usize,
+) -> Vec<Vec<Wavetable>> {
+    let harmonics = sample_rate / (2 * (2.0 * fundamental_freq) as usize);
+    let table_size = harmonics.next_power_of_two() * 2 * OVERSAMPLING;
+    let fft = FFTplanner::new(!INVERSE).plan_fft(frame_size);
+    let mut frames = samples
+        .chunks(frame_size)
+        .filter(|frame| frame.len() == frame_size)
+        .map(|frame| {
+            let mut signal = frame
+                .iter()
+                .map(|sample| Complex::new(*sample, 0.0))
+                .collect::<Vec<_>>();
+            let mut bins = vec![Complex::zero(); frame_size];
+            fft.process(&mut signal, &mut bins);
+            // skip DC and the nyquist frequency
+            let bin_cnt = (frame_size - 1) / 2 + 1;
+            build_mipmaps(
+                harmonics,
+                table_size,
+                fundamental_freq,
+                sample_rate,
+                !SCALE,
+                |harmonics, spectrum| {
+                    let size = spectrum.len();
+                    // keep the fundamental in the table with the least harmonics
+                    for i in 1..cmp::max(2, cmp::min(harmonics, bin_cnt)) {
+                        spectrum[i] = bins[i] / frame_size as Float;
+                        spectrum[size - i] = spectrum[i].conj();
+                    }
+                },
+            )
+        }).collect::<Vec<_>>();
+    normalize(&mut frames);
+    frames
+}
+
+/// Renders the partials with the given amplitudes and phases into a band-limited
+/// frame. The phases are given in periods, a phase of zero starts with a sine.
+pub fn render_spectrum(
+    amplitudes: &[Float],
+    phases: &[Float],
+    fundamental_freq: Float,
+    sample_rate: usize,
+) -> Vec<Vec<Wavetable>> {
+    let harmonics = sample_rate / (2 * (2.0 * fundamental_freq) as usize);
+    let table_size = harmonics.next_power_of_two() * 2 * OVERSAMPLING;
+    let mut frames = vec![build_mipmaps(
+        harmonics,
+        table_size,
+        fundamental_freq,
+        sample_rate,
+        !SCALE,
+        |harmonics, spectrum| {
+            let size = spectrum.len();
+            let partials = cmp::min(amplitudes.len(), size / 2 - 1);
+            // keep the fundamental in the table with the least harmonics
+            for i in 1..cmp::min(cmp::max(2, harmonics), partials + 1) {
+                let phase = phases.get(i - 1).cloned().unwrap_or(0.0);
+                // sin(x + phase) = (e^i(x + phase) - e^-i(x + phase)) / 2i
+                spectrum[i] = Complex::from_polar(&(0.5 * amplitudes[i - 1]), &(2.0 * PI * phase))
+                    * Complex::new(0.0, -1.0);
+                spectrum[size - i] = spectrum[i].conj();
+            }
+        },
+    )];
+    normalize(&mut frames);
+    frames
+}
+
+/// Scales all tables by the same factor, so that the loudest table has a peak of 1.0.
+fn normalize(frames: &mut [Vec<Wavetable>]) {
+    let peak = frames
+        .iter()
+        .flat_map(|tables| tables.iter())
+        .flat_map(|wavetable| wavetable.table.iter())
+        .fold(0.0, |acc: Float, sample| acc.max(sample.abs()));
+    if peak > 0.0 {
+        for wavetable in frames.iter_mut().flat_map(|tables| tables.iter_mut()) {
+            for sample in &mut wavetable.table {
+                *sample /= peak;
+            }
+        }
+    }
+}
+
 /// Generates a band-limited spectrum with given number of harmonics for the given waveform.
 fn generate_spectrum(waveform: Waveform, harmonics: usize, spectrum: &mut Vec<Complex<Float>>) {
     let table_size = spectrum.len();
@@ -224,50 +646,113 @@ fn generate_spectrum(waveform: Waveform, harmonics: usize, spectrum: &mut Vec<Co
 }
 
 /// A band-limited wavetable oscillator.
+///
+/// Wavetables with multiple frames are scanned by the position, which can be
+/// modulated by an envelope and an LFO. The samples of the two frames next to the
+/// position are crossfaded.
 pub struct WavetableOsc {
     phase_incr: Float,
     sample_rate: usize,
     key: u8,
     detune_hz: Float,
+    coarse: Float, // frequency ratio to the key
+    fine: Float,   // fine ratio in the range `[0.0, 1.0]`
+    fixed: bool,   // ignores the key and plays `fixed_freq`
+    fixed_freq: Float,
     phase: Float,
     phase_changed: bool,
     phasor: Float,
-    transpose: i32, // transposition in octaves
+    wrap_offset: Option<Float>, // samples since the phasor wrapped during the last tick
+    transpose: i32,             // transposition in octaves
     last_sample: Float,
     waveform: Waveform,
     id: String,
-    pitch_convert: Rc<PitchConvert>,
-    tables: Rc<HashMap<Waveform, Vec<Wavetable>>>,
+    pitch_convert: SharedMut<PitchConvert>,
+    tables: SharedMut<WavetableBank>,
+    frames: WavetableFrames, // frames of the current waveform
+    fade_frames: Option<(WavetableFrames, Waveform)>, // faded out after a waveform change
+    fade_ticks: usize,
+    position: Float, // position in the frames in the range `[0.0, 1.0]`
+    position_envelope: ADSR,
+    envelope_depth: Float,
+    lfo: Lfo,
+    lfo_depth: Float,
+    modulated_position: Float,
+    interpolation: Interpolation,
+    noise: Noise,
+    pulse_width: Float, // part of the period in which the pulse is high
+    pwm_lfo: Lfo,
+    pwm_depth: Float,
+    modulated_width: Float,
+    mod_lfo: Lfo,         // modulates the pitch and the amplitude
+    vibrato_depth: Float, // in semitones
+    tremolo_depth: Float,
+    key_breakpoint: u8,
+    left_key_scaling: KeyScalingCurve, // of the keys below the breakpoint
+    right_key_scaling: KeyScalingCurve,
+    key_gain: Float, // of the played key
 }
 impl WavetableOsc {
     /// Constructs a wavetable oscillator for the given sample rate.
     pub fn new(
         sample_rate: usize,
-        wavetables: Rc<HashMap<Waveform, Vec<Wavetable>>>,
-        pitch_convert: Rc<PitchConvert>,
+        wavetables: SharedMut<WavetableBank>,
+        pitch_convert: SharedMut<PitchConvert>,
     ) -> Self {
+        let frames = wavetables
+            .borrow()
+            .get(&Waveform::Sine)
+            .cloned()
+            .unwrap_or_default();
         WavetableOsc {
             phase_incr: 0.0,
             sample_rate,
             key: 0,
             detune_hz: 0.0, // Hz
+            coarse: 1.0,
+            fine: 0.0,
+            fixed: false,
+            fixed_freq: 440.0,
             phase: 0.0,
             phase_changed: false,
             phasor: 0.0,
+            wrap_offset: None,
             transpose: 0,
             last_sample: 0.0,
             waveform: Waveform::Sine,
             id: "".to_owned(),
             pitch_convert,
             tables: wavetables,
+            frames,
+            fade_frames: None,
+            fade_ticks: 0,
+            position: 0.0,
+            position_envelope: ADSR::new(sample_rate),
+            envelope_depth: 0.0,
+            lfo: Lfo::new(sample_rate),
+            lfo_depth: 0.0,
+            modulated_position: 0.0,
+            interpolation: Interpolation::default(),
+            noise: Noise::new(sample_rate),
+            pulse_width: 0.5,
+            pwm_lfo: Lfo::new(sample_rate),
+            pwm_depth: 0.0,
+            modulated_width: 0.5,
+            mod_lfo: Lfo::new(sample_rate),
+            vibrato_depth: 0.0,
+            tremolo_depth: 0.0,
+            key_breakpoint: 60,
+            left_key_scaling: KeyScalingCurve::Linear(0.0),
+            right_key_scaling: KeyScalingCurve::Linear(0.0),
+            key_gain: 1.0,
         }
     }
 
     pub fn with_id<S: Into<String>>(
         id: S,
         sample_rate: usize,
-        wavetables: Rc<HashMap<Waveform, Vec<Wavetable>>>,
-        pitch_convert: Rc<PitchConvert>,
+        wavetables: SharedMut<WavetableBank>,
+        pitch_convert: SharedMut<PitchConvert>,
     ) -> Self {
         let mut osc = WavetableOsc::new(sample_rate, wavetables, pitch_convert);
         osc.set_id(id);
@@ -279,11 +764,66 @@ impl WavetableOsc {
         self.phase_incr = (freq * Float::powi(2.0, self.transpose)) / self.sample_rate as Float;
     }
 
-    /// Sets the waveform to use.
+    /// Sets the frequency of the played key or the fixed frequency.
+    fn retune(&mut self) {
+        let freq = if self.fixed {
+            self.fixed_freq
+        } else {
+            let key_freq = self.pitch_convert.borrow().key_to_hz(self.key);
+            (key_freq + self.detune_hz) * self.coarse * (1.0 + self.fine)
+        };
+        self.set_freq(freq);
+    }
+
+    /// Sets the gain of the key scaling for the played key.
+    fn scale_key(&mut self) {
+        let octaves = (Float::from(self.key) - Float::from(self.key_breakpoint)) / 12.0;
+        let level = if octaves < 0.0 {
+            self.left_key_scaling.level(-octaves)
+        } else {
+            self.right_key_scaling.level(octaves)
+        };
+        self.key_gain = Float::from_db(level.max(MIN_KEY_SCALING_LEVEL).min(MAX_KEY_SCALING_LEVEL));
+    }
+
+    /// Sets the waveform to use, unknown waveforms are ignored.
+    /// The old tables are crossfaded with the new ones to avoid clicks,
+    /// tables that follow a noise waveform are faded in.
     pub fn set_waveform(&mut self, waveform: Waveform) {
+        if waveform.is_noise() {
+            if !self.waveform.is_noise() {
+                self.fade_frames = Some((self.frames.clone(), self.waveform));
+                self.fade_ticks = self.fade_length();
+            }
+            self.waveform = waveform;
+            return;
+        }
+        // pulses are built from saws
+        let table = if waveform == Waveform::Pulse {
+            Waveform::Saw
+        } else {
+            waveform
+        };
+        let frames = match self.tables.borrow().get(&table) {
+            Some(frames) => frames.clone(),
+            None => return,
+        };
+        if self.waveform.is_noise() {
+            self.frames = frames;
+            self.fade_frames = None;
+            self.fade_ticks = self.fade_length();
+        } else if !Arc::ptr_eq(&frames, &self.frames) || waveform != self.waveform {
+            let old_frames = ::std::mem::replace(&mut self.frames, frames);
+            self.fade_frames = Some((old_frames, self.waveform));
+            self.fade_ticks = self.fade_length();
+        }
         self.waveform = waveform;
     }
 
+    fn fade_length(&self) -> usize {
+        cmp::max(1, (WAVEFORM_FADE_TIME * self.sample_rate as Time) as usize)
+    }
+
     pub fn set_phase(&mut self, phase: Float) {
         const PHASE_DELTA: Float = 0.01;
         if (self.phase - phase).abs() > PHASE_DELTA {
@@ -292,43 +832,193 @@ impl WavetableOsc {
         self.phase = phase;
     }
 
+    /// Sets the position in the frames of the wavetable in the range `[0.0, 1.0]`.
+    pub fn set_position(&mut self, position: Float) {
+        self.position = position.max(0.0).min(1.0);
+        self.modulated_position = self.position;
+    }
+
+    /// Sets the width of `Waveform::Pulse` in the range `[0.0, 1.0]`,
+    /// a width of `0.5` is a square.
+    pub fn set_pulse_width(&mut self, width: Float) {
+        self.pulse_width = width.max(MIN_PULSE_WIDTH).min(1.0 - MIN_PULSE_WIDTH);
+        self.modulated_width = self.pulse_width;
+    }
+
     pub fn set_id<S: Into<String>>(&mut self, id: S) {
         self.id = id.into();
+        let envelope_id = format!("ADSR-POSITION-{}", self.id);
+        self.position_envelope = ADSR::with_id(self.sample_rate, envelope_id);
     }
 
     /// Returns the next sample from the oscillator.
     pub fn tick(&mut self) -> Float {
+        self.modulate_position();
+        self.modulate_pulse_width();
+        let (phase_incr, gain) = self.lfo_modulation();
         let phasor = (self.phasor + self.phase).fract();
         let mut sample = self.sample(phasor);
         if self.phase_changed {
             sample = (self.last_sample + sample) / 2.0;
             self.phase_changed = false;
         }
-        self.phasor += self.phase_incr;
+        let next_phasor = self.phasor + phase_incr;
+        self.wrap_offset = if next_phasor.floor() > self.phasor.floor() && phase_incr > 0.0 {
+            Some(next_phasor.fract() / phase_incr)
+        } else {
+            None
+        };
+        self.phasor = next_phasor;
         self.last_sample = sample;
-        sample
+        sample * gain * self.key_gain
     }
 
-    /// Returns the sample from the appropriate band-limited wavetable.
-    fn sample(&self, phasor: Float) -> Float {
-        let wavetables = self.tables.get(&self.waveform).unwrap();
-        let mut idx = 0;
-        for i in 0..wavetables.len() {
-            idx = i;
-            if wavetables[idx].max_phase_incr > self.phase_incr {
-                break;
-            }
+    /// Returns the time in samples that passed since the period started again
+    /// if it did so during the last tick.
+    pub fn wrapped(&self) -> Option<Float> {
+        self.wrap_offset
+    }
+
+    /// Restarts the period as if it was started `offset` samples before the next
+    /// tick, which hard syncs the oscillator to another one. Returns the size of
+    /// the discontinuity, noise has no period and isn't synced.
+    pub fn sync(&mut self, offset: Float) -> Float {
+        if self.waveform.is_noise() {
+            return 0.0;
+        }
+        let before = (self.phasor - offset * self.phase_incr + self.phase).fract();
+        let after = self.phase.fract();
+        let delta = self.waveform_sample(&self.frames, self.waveform, after)
+            - self.waveform_sample(&self.frames, self.waveform, before);
+        self.phasor = offset * self.phase_incr;
+        delta * self.key_gain
+    }
+
+    /// The envelope and the LFO only run while they modulate the position, they are
+    /// restarted by the next note otherwise.
+    fn modulate_position(&mut self) {
+        if self.envelope_depth == 0.0 && self.lfo_depth == 0.0 {
+            return;
+        }
+        let envelope = self.position_envelope.tick();
+        let lfo = self.lfo.tick();
+        let position = self.position + self.envelope_depth * envelope + 0.5 * self.lfo_depth * lfo;
+        self.modulated_position = position.max(0.0).min(1.0);
+    }
+
+    fn modulate_pulse_width(&mut self) {
+        if self.pwm_depth != 0.0 {
+            let width = self.pulse_width + 0.5 * self.pwm_depth * self.pwm_lfo.tick();
+            self.modulated_width = width.max(MIN_PULSE_WIDTH).min(1.0 - MIN_PULSE_WIDTH);
+        }
+    }
+
+    /// Returns the phase increment with vibrato and the gain of the tremolo.
+    fn lfo_modulation(&mut self) -> (Float, Float) {
+        if self.vibrato_depth == 0.0 && self.tremolo_depth == 0.0 {
+            return (self.phase_incr, 1.0);
         }
+        let lfo = self.mod_lfo.tick();
+        let phase_incr = self.phase_incr * Float::powf(2.0, self.vibrato_depth * lfo / 12.0);
+        (phase_incr, 1.0 - 0.5 * self.tremolo_depth * (1.0 - lfo))
+    }
 
-        let wavetable = &wavetables[idx];
-        wavetable.sample(phasor)
+    /// Returns the sample from the appropriate band-limited wavetable or
+    /// the noise generator.
+    fn sample(&mut self, phasor: Float) -> Float {
+        let sample = if self.waveform.is_noise() {
+            self.noise.tick(self.waveform)
+        } else {
+            self.waveform_sample(&self.frames, self.waveform, phasor)
+        };
+        if self.fade_ticks == 0 {
+            return sample;
+        }
+        let fade = self.fade_ticks as Float / self.fade_length() as Float;
+        let old_sample = match self.fade_frames {
+            Some((ref frames, waveform)) => self.waveform_sample(frames, waveform, phasor),
+            None => 0.0,
+        };
+        self.fade_ticks -= 1;
+        if self.fade_ticks == 0 {
+            self.fade_frames = None;
+        }
+        sample + (old_sample - sample) * fade
+    }
+
+    /// Returns the sample from the frames of a waveform. Pulses are the difference of
+    /// two saws whose phases are offset by the pulse width.
+    fn waveform_sample(
+        &self,
+        frames: &[Vec<Wavetable>],
+        waveform: Waveform,
+        phasor: Float,
+    ) -> Float {
+        if waveform == Waveform::Pulse {
+            // the saw falls, so the pulse is high at the beginning of the period
+            let offset = (phasor + self.modulated_width).fract();
+            self.frames_sample(frames, offset) - self.frames_sample(frames, phasor)
+        } else {
+            self.frames_sample(frames, phasor)
+        }
+    }
+
+    /// Returns the sample from the frames of a waveform.
+    /// The frames next to the position are crossfaded.
+    fn frames_sample(&self, frames: &[Vec<Wavetable>], phasor: Float) -> Float {
+        if frames.is_empty() {
+            return 0.0;
+        }
+        let last_frame = frames.len() - 1;
+        if last_frame == 0 {
+            return self.mip_sample(&frames[0], phasor);
+        }
+        let position = self.modulated_position * last_frame as Float;
+        let frame = cmp::min(position as usize, last_frame);
+        let fraction = position - frame as Float;
+        let sample = self.mip_sample(&frames[frame], phasor);
+        if frame == last_frame || fraction == 0.0 {
+            sample
+        } else {
+            sample + (self.mip_sample(&frames[frame + 1], phasor) - sample) * fraction
+        }
+    }
+
+    /// Returns the sample from the band-limited tables of a frame.
+    /// The table for the current frequency is crossfaded over its octave with the next
+    /// table, which has half the bandwidth, to avoid timbre steps during pitch sweeps.
+    fn mip_sample(&self, wavetables: &[Wavetable], phasor: Float) -> Float {
+        if wavetables.is_empty() {
+            return 0.0;
+        }
+        let last = wavetables.len() - 1;
+        let idx = wavetables
+            .iter()
+            .position(|wavetable| wavetable.max_phase_incr > self.phase_incr)
+            .unwrap_or(last);
+        let sample = wavetables[idx].sample(phasor, self.interpolation);
+        let max_phase_incr = wavetables[idx].max_phase_incr;
+        let min_phase_incr = if idx == 0 {
+            max_phase_incr / 2.0
+        } else {
+            wavetables[idx - 1].max_phase_incr
+        };
+        let fade = (self.phase_incr - min_phase_incr) / (max_phase_incr - min_phase_incr);
+        if idx == last || fade <= 0.0 || fade >= 1.0 {
+            sample
+        } else {
+            sample + (wavetables[idx + 1].sample(phasor, self.interpolation) - sample) * fade
+        }
     }
 
     /// Resets the state of the oscillator.
     fn reset(&mut self) {
         self.phase = 0.0;
         self.phasor = 0.0;
+        self.wrap_offset = None;
         self.last_sample = 0.0;
+        self.fade_frames = None;
+        self.fade_ticks = 0;
     }
 }
 
@@ -337,14 +1027,123 @@ impl Controllable for WavetableOsc {
         match *msg {
             ControlEvent::NoteOn { key, .. } => {
                 self.key = key;
-                let freq = self.pitch_convert.key_to_hz(key) + self.detune_hz;
-                self.set_freq(freq);
+                self.retune();
+                self.scale_key();
+                if self.envelope_depth == 0.0 && self.lfo_depth == 0.0 {
+                    self.position_envelope.reset();
+                    self.lfo.reset();
+                }
+                self.position_envelope.handle(msg);
+            }
+            ControlEvent::NoteOff { .. }
+            | ControlEvent::ADSR { .. }
+            | ControlEvent::ADSRCurve { .. }
+            | ControlEvent::ADSRRetrigger { .. }
+            | ControlEvent::SegmentEnvelope { .. }
+            | ControlEvent::VelocityResponse { .. }
+            | ControlEvent::ADSRVelocity { .. }
+            | ControlEvent::ADSRRateScaling { .. } => self.position_envelope.handle(msg),
+            ControlEvent::WavetablePosition {
+                ref id,
+                position,
+                envelope,
+                lfo,
+                lfo_rate,
+            } => {
+                if *id == self.id {
+                    if let Some(position) = position {
+                        self.set_position(position);
+                    }
+                    if let Some(envelope) = envelope {
+                        self.envelope_depth = envelope.max(-1.0).min(1.0);
+                    }
+                    if let Some(lfo) = lfo {
+                        self.lfo_depth = lfo.max(0.0).min(1.0);
+                    }
+                    if let Some(lfo_rate) = lfo_rate {
+                        self.lfo.set_freq(lfo_rate);
+                    }
+                    if self.envelope_depth == 0.0 && self.lfo_depth == 0.0 {
+                        self.modulated_position = self.position;
+                    }
+                }
             }
+            // the table was already replaced, retune the playing key
+            ControlEvent::Tuning(_) | ControlEvent::KeyTuning(_) => self.retune(),
             ControlEvent::Waveform { ref id, waveform } => {
                 if *id == self.id {
                     self.set_waveform(waveform);
                 }
             }
+            ControlEvent::PulseWidth {
+                ref id,
+                width,
+                lfo,
+                lfo_rate,
+            } => {
+                if *id == self.id {
+                    if let Some(width) = width {
+                        self.set_pulse_width(width);
+                    }
+                    if let Some(lfo) = lfo {
+                        self.pwm_depth = lfo.max(0.0).min(1.0);
+                    }
+                    if let Some(lfo_rate) = lfo_rate {
+                        self.pwm_lfo.set_freq(lfo_rate);
+                    }
+                    if self.pwm_depth == 0.0 {
+                        self.modulated_width = self.pulse_width;
+                    }
+                }
+            }
+            ControlEvent::OscLfo {
+                ref id,
+                pitch,
+                amplitude,
+                rate,
+            } => {
+                if *id == self.id {
+                    if let Some(pitch) = pitch {
+                        self.vibrato_depth = pitch.max(0.0);
+                    }
+                    if let Some(amplitude) = amplitude {
+                        self.tremolo_depth = amplitude.max(0.0).min(1.0);
+                    }
+                    if let Some(rate) = rate {
+                        self.mod_lfo.set_freq(rate);
+                    }
+                }
+            }
+            ControlEvent::KeyScaling {
+                ref id,
+                breakpoint,
+                left,
+                right,
+            } => {
+                if *id == self.id {
+                    if let Some(breakpoint) = breakpoint {
+                        self.key_breakpoint = breakpoint.min(127);
+                    }
+                    if let Some(left) = left {
+                        self.left_key_scaling = left;
+                    }
+                    if let Some(right) = right {
+                        self.right_key_scaling = right;
+                    }
+                    self.scale_key();
+                }
+            }
+            ControlEvent::NoiseTone { ref id, tone } => {
+                if *id == self.id {
+                    self.noise.set_tone(tone);
+                }
+            }
+            ControlEvent::Interpolation(interpolation) => self.interpolation = interpolation,
+            // a wavetable that is in use might have been replaced
+            ControlEvent::AddWavetable { .. } => {
+                let waveform = self.waveform;
+                self.set_waveform(waveform);
+            }
             ControlEvent::Phase { ref id, phase } => {
                 if *id == self.id {
                     self.set_phase(phase)
@@ -357,11 +1156,14 @@ impl Controllable for WavetableOsc {
             }
             ControlEvent::Detune { ref id, detune } => {
                 if *id == self.id {
-                    let (low, current, high) = (
-                        self.pitch_convert.key_to_hz(self.key - 1),
-                        self.pitch_convert.key_to_hz(self.key),
-                        self.pitch_convert.key_to_hz(self.key + 1),
-                    );
+                    let (low, current, high) = {
+                        let pitch_convert = self.pitch_convert.borrow();
+                        (
+                            pitch_convert.key_to_hz(self.key - 1),
+                            pitch_convert.key_to_hz(self.key),
+                            pitch_convert.key_to_hz(self.key + 1),
+                        )
+                    };
                     // linear approximation of cents
                     let cent = if detune < 0 {
                         (low - current)
@@ -369,8 +1171,30 @@ impl Controllable for WavetableOsc {
                         (high - current)
                     } / 100.0;
                     self.detune_hz = Float::from(detune) * cent;
-                    let detuned_freq = current + self.detune_hz;
-                    self.set_freq(detuned_freq);
+                    self.retune();
+                }
+            }
+            ControlEvent::OscFrequency {
+                ref id,
+                coarse,
+                fine,
+                fixed,
+                fixed_freq,
+            } => {
+                if *id == self.id {
+                    if let Some(coarse) = coarse {
+                        self.coarse = coarse.max(0.0);
+                    }
+                    if let Some(fine) = fine {
+                        self.fine = fine.max(0.0).min(1.0);
+                    }
+                    if let Some(fixed) = fixed {
+                        self.fixed = fixed;
+                    }
+                    if let Some(fixed_freq) = fixed_freq {
+                        self.fixed_freq = fixed_freq.max(0.0);
+                    }
+                    self.retune();
                 }
             }
             _ => (),
@@ -378,13 +1202,379 @@ impl Controllable for WavetableOsc {
     }
 }
 
+#[test]
+fn test_wavetable_cache() {
+    const SAMPLE_RATE: usize = 48_000;
+    let dir = env::temp_dir().join(format!(
+        "ytterbium-{}-wavetable-cache",
+        env!("CARGO_PKG_VERSION")
+    ));
+    fs::remove_dir_all(&dir).ok();
+    let cache = WavetableCache::new(Some(dir.clone()), false);
+    let tables = build_wavetables(Waveform::Sine, 440.0, SAMPLE_RATE);
+    assert!(cache.load(Waveform::Sine, 440.0, SAMPLE_RATE).is_none());
+    cache.store(Waveform::Sine, 440.0, SAMPLE_RATE, &tables);
+    let cached = cache.load(Waveform::Sine, 440.0, SAMPLE_RATE).unwrap();
+    assert_eq!(cached.len(), tables.len());
+    for (cached, table) in cached.iter().zip(&tables) {
+        assert_eq!(cached.table, table.table);
+        assert_relative_eq!(cached.max_phase_incr, table.max_phase_incr);
+    }
+    // every generation input is part of the key
+    assert!(cache.load(Waveform::Sine, 440.0, 96_000).is_none());
+    assert!(cache.load(Waveform::Sine, 220.0, SAMPLE_RATE).is_none());
+    assert!(cache.load(Waveform::Saw, 440.0, SAMPLE_RATE).is_none());
+    let rebuild = WavetableCache::new(Some(dir.clone()), true);
+    assert!(rebuild.load(Waveform::Sine, 440.0, SAMPLE_RATE).is_none());
+
+    // a file with other parameters is detected
+    let path = cache.path(Waveform::Sine, 440.0, SAMPLE_RATE).unwrap();
+    let other_path = cache.path(Waveform::Sine, 440.0, 96_000).unwrap();
+    fs::copy(&path, &other_path).unwrap();
+    assert!(cache.load(Waveform::Sine, 440.0, 96_000).is_none());
+
+    // a corrupt file is regenerated
+    let mut data = fs::read(&path).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&path, &data).unwrap();
+    assert!(cache.load(Waveform::Sine, 440.0, SAMPLE_RATE).is_none());
+    fs::write(&path, &data[..4]).unwrap();
+    assert!(cache.load(Waveform::Sine, 440.0, SAMPLE_RATE).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_interpolation() {
+    const TABLE_LEN: usize = 32;
+    const PERIODS: Float = 4.0; // eight samples per period
+    let wavetable = Wavetable {
+        table: (0..TABLE_LEN)
+            .map(|idx| Float::sin(2.0 * PI * PERIODS * idx as Float / TABLE_LEN as Float))
+            .collect(),
+        max_phase_incr: 0.5,
+    };
+    let max_error = |interpolation| {
+        (0..1000).fold(0.0, |acc: Float, idx| {
+            let phasor = idx as Float / 1000.0;
+            let sine = Float::sin(2.0 * PI * PERIODS * phasor);
+            let error = wavetable.sample(phasor, interpolation) - sine;
+            acc.max(error.abs())
+        })
+    };
+    let (linear, cubic, sinc) = (
+        max_error(Interpolation::Linear),
+        max_error(Interpolation::Cubic),
+        max_error(Interpolation::Sinc),
+    );
+    assert!(linear > cubic && cubic > sinc);
+    assert!(sinc < 1e-3);
+    // the table samples are returned unchanged
+    for interpolation in &[Interpolation::Cubic, Interpolation::Sinc] {
+        assert_relative_eq!(
+            wavetable.sample(2.0 / 32.0, *interpolation),
+            1.0,
+            epsilon = 1e-9
+        );
+    }
+}
+
+#[test]
+fn test_mip_crossfade() {
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::new(SAMPLE_RATE, wavetables, pitch_convert);
+    osc.set_waveform(Waveform::Saw);
+    let frames = osc.frames.clone();
+    let boundary = frames[0][3].max_phase_incr;
+    // the timbre doesn't change at the boundary of two tables
+    osc.phase_incr = boundary * (1.0 - 1e-9);
+    let below = osc.frames_sample(&frames, 0.05);
+    osc.phase_incr = boundary * (1.0 + 1e-9);
+    let above = osc.frames_sample(&frames, 0.05);
+    assert_relative_eq!(below, above, epsilon = 1e-6);
+    assert!((frames[0][3].sample(0.05, Interpolation::Linear) - above).abs() > 1e-3);
+}
+
+/// Prints the time needed for a second of samples with each interpolation, run it with
+/// `cargo test --release -- --ignored bench_interpolation --nocapture`.
+#[test]
+#[ignore]
+fn bench_interpolation() {
+    use std::time::Instant;
+
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    for &interpolation in &[
+        Interpolation::Linear,
+        Interpolation::Cubic,
+        Interpolation::Sinc,
+    ] {
+        let pitch_convert = SharedMut::wrap(PitchConvert::default());
+        let mut osc = WavetableOsc::new(SAMPLE_RATE, wavetables.clone(), pitch_convert);
+        osc.set_waveform(Waveform::Saw);
+        osc.set_freq(440.0);
+        osc.handle(&ControlEvent::Interpolation(interpolation));
+        let start = Instant::now();
+        let sum = (0..SAMPLE_RATE).fold(0.0, |acc, _| acc + osc.tick());
+        let elapsed = start.elapsed();
+        // the samples are used, so that the loop isn't optimized away
+        assert!(sum.is_finite());
+        println!(
+            "{:?}: {:.2} ms",
+            interpolation,
+            elapsed.as_secs() as f64 * 1e3 + f64::from(elapsed.subsec_nanos()) / 1e6
+        );
+    }
+}
+
+#[test]
+fn test_noise_waveform() {
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::new(SAMPLE_RATE, wavetables, pitch_convert);
+    osc.set_freq(440.0);
+    osc.set_waveform(Waveform::PinkNoise);
+    let fade_length = osc.fade_length();
+    for _ in 0..fade_length {
+        osc.tick();
+    }
+    assert!(osc.fade_frames.is_none());
+    let energy = (0..1000).fold(0.0, |acc, _| acc + osc.tick().powi(2));
+    assert!(energy > 10.0);
+    // the tables are faded in after the noise
+    osc.set_waveform(Waveform::Sine);
+    assert_eq!(osc.tick(), 0.0);
+    assert_eq!(osc.fade_ticks, fade_length - 1);
+}
+
+#[test]
+fn test_pulse_width() {
+    const SAMPLE_RATE: usize = 48_000;
+    const FREQ: Float = 100.0;
+    let period = SAMPLE_RATE / FREQ as usize;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::with_id("OSC1", SAMPLE_RATE, wavetables, pitch_convert);
+    osc.set_waveform(Waveform::Pulse);
+    osc.set_freq(FREQ);
+    for &width in &[0.5, 0.25, 0.9] {
+        osc.handle(&ControlEvent::PulseWidth {
+            id: "OSC1".to_owned(),
+            width: Some(width),
+            lfo: None,
+            lfo_rate: None,
+        });
+        osc.reset();
+        let samples = (0..period).map(|_| osc.tick()).collect::<Vec<_>>();
+        let high = samples.iter().filter(|sample| **sample > 0.0).count();
+        assert_relative_eq!(high as Float / period as Float, width, epsilon = 0.02);
+        // the pulse has no DC offset
+        let mean = samples.iter().sum::<Float>() / period as Float;
+        assert_relative_eq!(mean, 0.0, epsilon = 0.02);
+    }
+
+    // the LFO modulates the width
+    osc.handle(&ControlEvent::PulseWidth {
+        id: "OSC1".to_owned(),
+        width: Some(0.5),
+        lfo: Some(0.5),
+        lfo_rate: Some(1.0),
+    });
+    let widths = (0..SAMPLE_RATE).map(|_| {
+        osc.tick();
+        osc.modulated_width
+    });
+    let (min, max) = widths.fold((1.0, 0.0), |(min, max): (Float, Float), width| {
+        (min.min(width), max.max(width))
+    });
+    assert_relative_eq!(min, 0.25, epsilon = 1e-3);
+    assert_relative_eq!(max, 0.75, epsilon = 1e-3);
+}
+
+#[test]
+fn test_osc_frequency() {
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::with_id("OSC1", SAMPLE_RATE, wavetables, pitch_convert);
+    let freq = |osc: &WavetableOsc| osc.phase_incr * SAMPLE_RATE as Float;
+    let frequency = |coarse, fine, fixed, fixed_freq| ControlEvent::OscFrequency {
+        id: "OSC1".to_owned(),
+        coarse,
+        fine,
+        fixed,
+        fixed_freq,
+    };
+    osc.handle(&ControlEvent::NoteOn {
+        key: 69,
+        velocity: 1.0,
+    });
+    assert_relative_eq!(freq(&osc), 440.0, epsilon = 1e-6);
+    osc.handle(&frequency(Some(1.0), Some(0.41), None, None));
+    assert_relative_eq!(freq(&osc), 440.0 * 1.41, epsilon = 1e-6);
+    osc.handle(&frequency(Some(3.5), None, None, None));
+    assert_relative_eq!(freq(&osc), 440.0 * 3.5 * 1.41, epsilon = 1e-6);
+    // the ratio follows the key
+    osc.handle(&frequency(None, Some(0.0), None, None));
+    osc.handle(&ControlEvent::NoteOn {
+        key: 57,
+        velocity: 1.0,
+    });
+    assert_relative_eq!(freq(&osc), 220.0 * 3.5, epsilon = 1e-6);
+
+    // a fixed frequency ignores the key
+    osc.handle(&frequency(None, None, Some(true), Some(100.0)));
+    assert_relative_eq!(freq(&osc), 100.0, epsilon = 1e-6);
+    osc.handle(&ControlEvent::NoteOn {
+        key: 81,
+        velocity: 1.0,
+    });
+    assert_relative_eq!(freq(&osc), 100.0, epsilon = 1e-6);
+    osc.handle(&frequency(None, None, Some(false), None));
+    assert_relative_eq!(freq(&osc), 880.0 * 3.5, epsilon = 1e-6);
+}
+
+#[test]
+fn test_osc_lfo() {
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::with_id("OSC1", SAMPLE_RATE, wavetables, pitch_convert);
+    osc.set_freq(100.0);
+    // an octave of vibrato, the LFO is positive in the first half of its period
+    osc.handle(&ControlEvent::OscLfo {
+        id: "OSC1".to_owned(),
+        pitch: Some(12.0),
+        amplitude: None,
+        rate: Some(1.0),
+    });
+    let samples = (0..SAMPLE_RATE).map(|_| osc.tick()).collect::<Vec<_>>();
+    let periods = |samples: &[Float]| {
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count()
+    };
+    let (rising, falling) = samples.split_at(SAMPLE_RATE / 2);
+    assert!(periods(rising) > 60 && periods(falling) < 40);
+
+    // full tremolo silences the oscillator in the LFO's minimum
+    osc.handle(&ControlEvent::OscLfo {
+        id: "OSC1".to_owned(),
+        pitch: Some(0.0),
+        amplitude: Some(1.0),
+        rate: None,
+    });
+    let samples = (0..SAMPLE_RATE).map(|_| osc.tick()).collect::<Vec<_>>();
+    let peak = |samples: &[Float]| samples.iter().fold(0.0, |acc: Float, s| acc.max(s.abs()));
+    assert!(peak(&samples[..SAMPLE_RATE / 2]) > 0.9);
+    let minimum = 3 * SAMPLE_RATE / 4;
+    assert!(peak(&samples[minimum - 500..minimum + 500]) < 0.01);
+}
+
+#[test]
+fn test_key_scaling() {
+    const SAMPLE_RATE: usize = 48_000;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::with_id("OSC1", SAMPLE_RATE, wavetables, pitch_convert);
+    osc.handle(&ControlEvent::KeyScaling {
+        id: "OSC1".to_owned(),
+        breakpoint: Some(60),
+        left: Some(KeyScalingCurve::Linear(-6.0)),
+        right: Some(KeyScalingCurve::Exponential(-6.0)),
+    });
+    let mut gain = |key: u8| {
+        osc.handle(&ControlEvent::NoteOn { key, velocity: 1.0 });
+        let peak = (0..SAMPLE_RATE / 10).fold(0.0, |acc: Float, _| acc.max(osc.tick().abs()));
+        (osc.key_gain, peak)
+    };
+    assert_relative_eq!(gain(60).0, 1.0);
+    assert_relative_eq!(gain(48).0, Float::from_db(-6.0));
+    // the exponential curve doubles the change every octave
+    assert_relative_eq!(gain(84).0, Float::from_db(-18.0));
+    let (key_gain, peak) = gain(96);
+    assert_relative_eq!(key_gain, Float::from_db(-42.0));
+    assert_relative_eq!(peak, key_gain, max_relative = 0.05);
+}
+
+#[test]
+fn test_hard_sync() {
+    const SAMPLE_RATE: usize = 48_000;
+    const MASTER_FREQ: Float = 113.0;
+    const SLAVE_FREQ: Float = 347.0;
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut master = WavetableOsc::new(SAMPLE_RATE, wavetables.clone(), pitch_convert.clone());
+    let mut slave = WavetableOsc::new(SAMPLE_RATE, wavetables, pitch_convert);
+    master.set_freq(MASTER_FREQ);
+    slave.set_waveform(Waveform::Saw);
+    slave.set_freq(SLAVE_FREQ);
+    slave.reset();
+    let (master_incr, slave_incr) = (master.phase_incr, slave.phase_incr);
+    let mut syncs = 0;
+    for idx in 0..SAMPLE_RATE / 10 {
+        // the slave restarts its period with each period of the master
+        let elapsed = (idx as Float * master_incr).fract() / master_incr;
+        let phasor = (elapsed * slave_incr).fract();
+        let expected = slave.waveform_sample(&slave.frames, Waveform::Saw, phasor);
+        master.tick();
+        assert_relative_eq!(slave.tick(), expected, epsilon = 1e-6);
+        if let Some(offset) = master.wrapped() {
+            let delta = slave.sync(offset);
+            assert!(delta.abs() < 2.0);
+            syncs += 1;
+        }
+    }
+    assert_eq!(syncs, (0.1 * MASTER_FREQ) as usize);
+}
+
 #[test]
 fn test_wavetable_sweep() {
     extern crate hound;
     const SAMPLE_RATE: usize = 48_000;
     const LOW_FREQ: Float = 20.0;
-    let wavetables = Rc::new(generate_wavetables(LOW_FREQ, SAMPLE_RATE));
-    let pitch_convert = Rc::new(PitchConvert::default());
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOW_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
     let mut osc = WavetableOsc::new(SAMPLE_RATE, wavetables, pitch_convert);
 
     let wave_spec = hound::WavSpec {
@@ -430,8 +1620,12 @@ fn test_wavetable_phase() {
     const SAMPLE_RATE: usize = 48_000;
     const LOW_FREQ: Float = 20.0;
     const EPSILON: f64 = 0.0001;
-    let wavetables = Rc::new(generate_wavetables(LOW_FREQ, SAMPLE_RATE));
-    let pitch_convert = Rc::new(PitchConvert::default());
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOW_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
     let mut osc = WavetableOsc::new(SAMPLE_RATE, wavetables, pitch_convert);
 
     for freq in &[1.0, 1000.0, ((SAMPLE_RATE >> 1) - 1) as Float] {
@@ -496,8 +1690,12 @@ fn test_wavetable_fm() {
     extern crate hound;
     const SAMPLE_RATE: usize = 48_000;
     const LOW_FREQ: Float = 20.0;
-    let wavetables = Rc::new(generate_wavetables(LOW_FREQ, SAMPLE_RATE));
-    let pitch_convert = Rc::new(PitchConvert::default());
+    let wavetables = SharedMut::wrap(generate_wavetables(
+        LOW_FREQ,
+        SAMPLE_RATE,
+        &WavetableCache::new(None, false),
+    ));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
     let mut carrier = WavetableOsc::new(SAMPLE_RATE, wavetables.clone(), pitch_convert.clone());
     let mut modulator = WavetableOsc::new(SAMPLE_RATE, wavetables.clone(), pitch_convert.clone());
     let num_samples = SAMPLE_RATE * 10;
@@ -534,3 +1732,139 @@ fn test_wavetable_fm() {
     }
     writer.finalize().unwrap();
 }
+
+#[test]
+fn test_load_wavetable() {
+    extern crate hound;
+    const SAMPLE_RATE: usize = 48_000;
+    let wave_spec = hound::WavSpec {
+        channels: 1,
+        sample_format: hound::SampleFormat::Float,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample: 32,
+    };
+    let path = ::std::env::temp_dir().join(format!(
+        "ytterbium-{}-user-wavetable.wav",
+        env!("CARGO_PKG_VERSION")
+    ));
+    // two frames of 2048 samples, a sine and a sine at half the level
+    let mut writer = hound::WavWriter::create(&path, wave_spec).unwrap();
+    for &level in &[1.0, 0.5] {
+        for idx in 0..2048 {
+            let sample = level * Float::sin(2.0 * PI * idx as Float / 2048.0);
+            writer.write_sample(sample as f32).unwrap();
+        }
+    }
+    writer.finalize().unwrap();
+
+    let frames = load_wavetable(&path, LOWEST_FREQ, SAMPLE_RATE).unwrap();
+    let saw = build_wavetables(Waveform::Saw, LOWEST_FREQ, SAMPLE_RATE);
+    assert_eq!(frames.len(), 2);
+    for (tables, level) in frames.iter().zip(&[1.0, 0.5]) {
+        // the tables are switched at the same frequencies as the builtin waveforms
+        assert_eq!(tables.len(), saw.len());
+        for (table, saw_table) in tables.iter().zip(&saw) {
+            assert_relative_eq!(table.max_phase_incr, saw_table.max_phase_incr);
+            assert_relative_eq!(
+                table.sample(0.25, Interpolation::Linear),
+                *level,
+                epsilon = 1e-6
+            );
+            assert_relative_eq!(
+                table.sample(0.0, Interpolation::Linear),
+                0.0,
+                epsilon = 1e-6
+            );
+        }
+    }
+}
+
+#[test]
+fn test_wavetable_position() {
+    const SAMPLE_RATE: usize = 48_000;
+    // three frames with a decreasing level
+    let samples = [1.0, 0.5, 0.0]
+        .iter()
+        .flat_map(|level| {
+            (0..256).map(move |idx| level * Float::sin(2.0 * PI * idx as Float / 256.0))
+        }).collect::<Vec<_>>();
+    let frames = build_user_wavetables(&samples, 256, LOWEST_FREQ, SAMPLE_RATE);
+    let mut bank = WavetableBank::new();
+    bank.insert(Waveform::User(0), Arc::new(frames));
+    let pitch_convert = SharedMut::wrap(PitchConvert::default());
+    let mut osc = WavetableOsc::with_id("OSC1", SAMPLE_RATE, SharedMut::wrap(bank), pitch_convert);
+    osc.set_waveform(Waveform::User(0));
+    // returns the sample at a quarter period, which is the level of the frame
+    let level = |osc: &mut WavetableOsc| {
+        osc.reset();
+        osc.set_freq(SAMPLE_RATE as Float / 4.0);
+        osc.tick();
+        osc.tick()
+    };
+    let set_position = |osc: &mut WavetableOsc, position, envelope| {
+        osc.handle(&ControlEvent::WavetablePosition {
+            id: "OSC1".to_owned(),
+            position,
+            envelope,
+            lfo: None,
+            lfo_rate: None,
+        })
+    };
+    assert_relative_eq!(level(&mut osc), 1.0, epsilon = 1e-6);
+    set_position(&mut osc, Some(0.25), None);
+    assert_relative_eq!(level(&mut osc), 0.75, epsilon = 1e-6);
+    set_position(&mut osc, Some(1.0), None);
+    assert_relative_eq!(level(&mut osc), 0.0, epsilon = 1e-6);
+    // the envelope doesn't run without a depth
+    osc.handle(&ControlEvent::NoteOn {
+        key: 69,
+        velocity: 1.0,
+    });
+    for _ in 0..SAMPLE_RATE {
+        osc.tick();
+    }
+    assert_eq!(osc.position_envelope.level(), 0.0);
+    // the envelope moves the position by its sustain level of -12dB
+    set_position(&mut osc, Some(0.0), Some(1.0));
+    osc.handle(&ControlEvent::NoteOn {
+        key: 69,
+        velocity: 1.0,
+    });
+    for _ in 0..SAMPLE_RATE {
+        osc.tick();
+    }
+    assert_relative_eq!(level(&mut osc), 0.75, epsilon = 0.02);
+}
+
+#[test]
+fn test_render_spectrum() {
+    const SAMPLE_RATE: usize = 48_000;
+    // a cosine and its third harmonic with the phase shifted by half a period
+    let frames = render_spectrum(
+        &[1.0, 0.0, 0.5],
+        &[0.25, 0.0, 0.5],
+        LOWEST_FREQ,
+        SAMPLE_RATE,
+    );
+    assert_eq!(frames.len(), 1);
+    let signal =
+        |phasor: Float| Float::cos(2.0 * PI * phasor) - 0.5 * Float::sin(6.0 * PI * phasor);
+    let peak = (0..4096).fold(0.0, |acc: Float, idx| {
+        acc.max(signal(idx as Float / 4096.0).abs())
+    });
+    for idx in 0..64 {
+        let phasor = idx as Float / 64.0;
+        assert_relative_eq!(
+            frames[0][0].sample(phasor, Interpolation::Linear),
+            signal(phasor) / peak,
+            epsilon = 1e-3
+        );
+    }
+    // only the fundamental is left in the highest table
+    let highest = frames[0].last().unwrap();
+    assert_relative_eq!(
+        highest.sample(0.0, Interpolation::Linear),
+        peak.recip(),
+        epsilon = 1e-6
+    );
+}
@@ -1,10 +1,18 @@
+extern crate bincode;
 extern crate portmidi;
 extern crate rosc;
 
+use self::bincode::rustc_serialize::{DecodingError, EncodingError};
+
 #[derive(Debug)]
 pub enum RunError {
     IoError(::std::io::Error),
     OscError(rosc::OscError),
     MidiError(portmidi::Error),
     NoMidiDeviceAvailable,
+    PatchEncodingError(EncodingError),
+    PatchDecodingError(DecodingError),
+    ScalaError(String),
+    SysExError(String),
+    WavError(String),
 }
@@ -1,9 +1,16 @@
 extern crate portmidi;
 extern crate rosc;
 
+use std::mem;
+use std::sync::Arc;
+
+use patch::Patch;
 use types::*;
 
-use dsp::{FilterType, Waveform};
+use dsp::{
+    ArpeggiatorMode, Breakpoint, EnvelopeCurve, FilterType, Interpolation, KeyScalingCurve,
+    RetriggerMode, Transport, VelocityCurve, VoiceAllocation, Waveform, WavetableFrames,
+};
 
 macro_rules! feq {
     ($lhs:expr, $rhs:expr) => {
@@ -11,7 +18,7 @@ macro_rules! feq {
     };
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
 pub enum ControlEvent {
     Unsupported,
     NoteOn {
@@ -29,10 +36,112 @@ pub enum ControlEvent {
         sustain: Float,
         release: Time,
     },
+    /// Sets the curves of the stages of the envelope with the given id.
+    ADSRCurve {
+        id: String,
+        attack: Option<EnvelopeCurve>,
+        decay: Option<EnvelopeCurve>,
+        release: Option<EnvelopeCurve>,
+    },
+    /// Sets how the envelope with the given id restarts when a new note is played.
+    ADSRRetrigger {
+        id: String,
+        mode: RetriggerMode,
+    },
+    /// Sets how the velocity of a note in the range `[0.0, 1.0]` changes the level of the
+    /// envelopes of all oscillators, `range` is the dynamic range in dB.
+    VelocityResponse {
+        curve: Option<VelocityCurve>,
+        range: Option<Float>,
+    },
+    /// Scales the dynamic range of the envelope with the given id by `sensitivity`,
+    /// `time` shortens the attack and decay of harder notes. Both are in the range
+    /// `[0.0, 1.0]`.
+    ADSRVelocity {
+        id: String,
+        sensitivity: Option<Float>,
+        time: Option<Float>,
+    },
+    /// Shortens the times of the envelope with the given id for higher keys, a rate of
+    /// `1.0` halves them every octave.
+    ADSRRateScaling {
+        id: String,
+        rate: Float,
+    },
+    /// Replaces the stages of the envelope with the given id by segments that end at the
+    /// breakpoints, an empty list restores the stages. The sustain and loop points are
+    /// indices of breakpoints.
+    SegmentEnvelope {
+        id: String,
+        breakpoints: Vec<Breakpoint>,
+        sustain: Option<usize>,
+        loop_points: Option<(usize, usize)>, // start and end
+    },
     Waveform {
         id: String,
         waveform: Waveform,
     },
+    /// Selects a loaded wavetable by its name, patches store the name because the
+    /// index of `Waveform::User` depends on the loading order.
+    UserWaveform {
+        id: String,
+        name: String,
+    },
+    /// Sets the harmonic spectrum of an oscillator and selects it as its waveform.
+    /// The phases of the partials are given in periods.
+    Spectrum {
+        id: String,
+        amplitudes: Vec<Float>,
+        phases: Vec<Float>,
+    },
+    /// Registers the band-limited frames of a wavetable file under the given name,
+    /// a wavetable with the same name is replaced.
+    AddWavetable {
+        name: String,
+        frames: WavetableFrames,
+    },
+    /// Scans the frames of a wavetable. The position is in the range `[0.0, 1.0]`,
+    /// it is modulated by an envelope with a depth in `[-1.0, 1.0]` and an LFO with
+    /// a depth in `[0.0, 1.0]` and a rate in Hz.
+    WavetablePosition {
+        id: String,
+        position: Option<Float>,
+        envelope: Option<Float>,
+        lfo: Option<Float>,
+        lfo_rate: Option<Float>,
+    },
+    /// Sets the width of the pulse waveform in the range `[0.0, 1.0]`, it is modulated
+    /// by an LFO with a depth in `[0.0, 1.0]` and a rate in Hz.
+    PulseWidth {
+        id: String,
+        width: Option<Float>,
+        lfo: Option<Float>,
+        lfo_rate: Option<Float>,
+    },
+    /// Modulates the pitch of an oscillator by up to `pitch` semitones and its amplitude
+    /// by a depth in `[0.0, 1.0]` with an LFO with a rate in Hz.
+    OscLfo {
+        id: String,
+        pitch: Option<Float>,
+        amplitude: Option<Float>,
+        rate: Option<Float>,
+    },
+    /// Scales the level of an oscillator with the distance of the played key from the
+    /// breakpoint key, the curves apply to the keys below and above it.
+    KeyScaling {
+        id: String,
+        breakpoint: Option<u8>,
+        left: Option<KeyScalingCurve>,
+        right: Option<KeyScalingCurve>,
+    },
+    /// Sets the center frequency of the colored noise of an oscillator,
+    /// the tone is in the range `[0.0, 1.0]`.
+    NoiseTone {
+        id: String,
+        tone: Float,
+    },
+    /// Sets the interpolation of the wavetables for all oscillators.
+    Interpolation(Interpolation),
     Volume(Vec<Float>),
     Pan(Vec<Float>),
     Phase {
@@ -47,15 +156,272 @@ pub enum ControlEvent {
         id: String,
         detune: i32,
     },
+    /// Sets the frequency of an oscillator to `coarse * (1.0 + fine)` times the frequency
+    /// of the played key, or to `fixed_freq` in Hz if `fixed` is set.
+    OscFrequency {
+        id: String,
+        coarse: Option<Float>,
+        fine: Option<Float>,
+        fixed: Option<bool>,
+        fixed_freq: Option<Float>,
+    },
+    /// Sets the levels of the phase modulation of an oscillator by each oscillator,
+    /// its own level is the feedback.
     FM {
         id: String,
         levels: Vec<Float>,
     },
+    /// Hard syncs an oscillator to the oscillator with the id `source`,
+    /// `None` disables the sync.
+    Sync {
+        id: String,
+        source: Option<String>,
+    },
+    /// Multiplies an oscillator with the other oscillators, the levels are in the range
+    /// `[0.0, 1.0]`. A level of `1.0` is ring modulation, lower levels keep part of the
+    /// unmodulated signal, which is amplitude modulation.
+    RingMod {
+        id: String,
+        levels: Vec<Float>,
+    },
+    /// Sets the number of oscillators that are played by each voice.
+    OscillatorCount(usize),
+    /// Applies the named FM algorithm, which sets the oscillator count, the modulations
+    /// and the levels of the carriers.
+    FMAlgorithm(String),
     Filter {
         filter_type: Option<FilterType>,
         freq: Option<Float>,
         q: Option<Float>,
     },
+    VoiceAllocation(VoiceAllocation),
+    Arpeggiator {
+        enabled: Option<bool>,
+        mode: Option<ArpeggiatorMode>,
+        octaves: Option<usize>,
+        division: Option<Float>, // step length in beats
+        gate: Option<Float>,
+        swing: Option<Float>,
+        latch: Option<bool>,
+    },
+    /// Internal tempo in beats per minute, used if no MIDI clock is received.
+    Tempo(Float),
+    /// A pulse of the 24 PPQN MIDI clock with its timestamp in milliseconds.
+    ClockTick(u32),
+    Transport(Transport),
+    Sequencer {
+        pattern: Option<usize>,
+        division: Option<Float>, // step length in beats
+    },
+    SequencerPattern {
+        pattern: usize,
+        length: Option<usize>,
+        next: Option<Option<usize>>, // `Some(None)` removes the chained pattern
+    },
+    SequencerStep {
+        pattern: usize,
+        step: usize,
+        note: Option<u8>,
+        velocity: Float,
+        gate: Float,
+        tie: bool,
+        probability: Float,
+    },
+    /// Locks a parameter on a sequencer step, `None` removes all locks of the step.
+    ParameterLock {
+        pattern: usize,
+        step: usize,
+        lock: Option<Box<ControlEvent>>,
+    },
+    /// Replaces the frequencies of all 128 keys.
+    Tuning(Arc<Vec<Float>>),
+    /// Changes the frequencies of single keys.
+    KeyTuning(Vec<(u8, Float)>),
+    SavePatch(String),
+    /// Replaces the parameters and patterns by the patch, which was loaded from the file
+    /// with the given name.
+    LoadPatch {
+        name: String,
+        patch: Arc<Patch>,
+    },
+}
+/// Identifies the sound parameter that is set by an event, events of the same parameter
+/// override each other. Events of combined parameters like the filter only override the
+/// fields that they set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parameter<'a> {
+    pub name: &'static str,
+    pub id: &'a str, // the oscillator or envelope, empty for global parameters
+}
+
+impl ControlEvent {
+    /// Returns the parameter that is set by this event or `None` if the event doesn't
+    /// change a sound parameter.
+    pub fn parameter<'a>(&'a self) -> Option<Parameter<'a>> {
+        let (name, id) = match *self {
+            ControlEvent::ADSR { ref id, .. } => ("ADSR", id.as_str()),
+            ControlEvent::ADSRCurve { ref id, .. } => ("ADSR-CURVE", id.as_str()),
+            ControlEvent::ADSRRetrigger { ref id, .. } => ("ADSR-RETRIGGER", id.as_str()),
+            ControlEvent::ADSRVelocity { ref id, .. } => ("ADSR-VELOCITY", id.as_str()),
+            ControlEvent::ADSRRateScaling { ref id, .. } => ("ADSR-RATE-SCALING", id.as_str()),
+            ControlEvent::SegmentEnvelope { ref id, .. } => ("SEGMENTS", id.as_str()),
+            ControlEvent::VelocityResponse { .. } => ("VELOCITY", ""),
+            ControlEvent::Waveform { ref id, .. } | ControlEvent::UserWaveform { ref id, .. } => {
+                ("WAVEFORM", id.as_str())
+            }
+            ControlEvent::Spectrum { ref id, .. } => ("SPECTRUM", id.as_str()),
+            ControlEvent::WavetablePosition { ref id, .. } => ("WAVETABLE", id.as_str()),
+            ControlEvent::PulseWidth { ref id, .. } => ("PULSE", id.as_str()),
+            ControlEvent::OscLfo { ref id, .. } => ("LFO", id.as_str()),
+            ControlEvent::KeyScaling { ref id, .. } => ("KEY-SCALING", id.as_str()),
+            ControlEvent::NoiseTone { ref id, .. } => ("NOISE-TONE", id.as_str()),
+            ControlEvent::Interpolation(_) => ("INTERPOLATION", ""),
+            ControlEvent::Volume(_) => ("VOLUME", ""),
+            ControlEvent::Pan(_) => ("PAN", ""),
+            ControlEvent::Phase { ref id, .. } => ("PHASE", id.as_str()),
+            ControlEvent::Transpose { ref id, .. } => ("TRANSPOSE", id.as_str()),
+            ControlEvent::Detune { ref id, .. } => ("DETUNE", id.as_str()),
+            ControlEvent::OscFrequency { ref id, .. } => ("FREQUENCY", id.as_str()),
+            ControlEvent::FM { ref id, .. } => ("FM", id.as_str()),
+            ControlEvent::Sync { ref id, .. } => ("SYNC", id.as_str()),
+            ControlEvent::RingMod { ref id, .. } => ("RING-MOD", id.as_str()),
+            ControlEvent::OscillatorCount(_) => ("OSCILLATOR-COUNT", ""),
+            ControlEvent::FMAlgorithm(_) => ("FM-ALGORITHM", ""),
+            ControlEvent::Filter { .. } => ("FILTER", ""),
+            ControlEvent::VoiceAllocation(_) => ("VOICE-ALLOCATION", ""),
+            ControlEvent::Arpeggiator { .. } => ("ARPEGGIATOR", ""),
+            ControlEvent::Tempo(_) => ("TEMPO", ""),
+            _ => return None,
+        };
+        Some(Parameter { name, id })
+    }
+}
+
+/// Implements the access to the fields of the parameter events. The fields of the
+/// combined events are options, which are numbered in the listed order. The values of
+/// the other events are a single field.
+macro_rules! parameter_fields {
+    (
+        combined { $($combined:ident { $($field:ident),+ }),+ }
+        single { $($single:ident { $($value:ident),+ }),+ }
+        tuple { $($tuple:ident),+ }
+    ) => {
+        impl ControlEvent {
+            /// Returns a bit for each field of the event that is set.
+            pub fn fields(&self) -> u8 {
+                match *self {
+                    $(ControlEvent::$combined { $(ref $field,)+ .. } => [$($field.is_some()),+]
+                        .iter()
+                        .enumerate()
+                        .filter(|&(_, set)| *set)
+                        .fold(0, |fields, (idx, _)| fields | 1 << idx),)+
+                    _ => 1,
+                }
+            }
+
+            /// Copies the given fields that are set in `other`, which sets the same
+            /// parameter. The values are copied in place, so this doesn't allocate.
+            pub fn merge(&mut self, other: &ControlEvent, fields: u8) {
+                // `Waveform` and `UserWaveform` set the same parameter
+                if mem::discriminant(self) != mem::discriminant(other) {
+                    if fields & 1 != 0 {
+                        *self = other.clone();
+                    }
+                    return;
+                }
+                match *self {
+                    $(ControlEvent::$combined { $(ref mut $field,)+ .. } => {
+                        let mut bits = (0..).map(|idx| 1 << idx);
+                        $(
+                            let bit: u8 = bits.next().unwrap();
+                            if let ControlEvent::$combined {
+                                $field: ref value @ Some(_),
+                                ..
+                            } = *other
+                            {
+                                if fields & bit != 0 {
+                                    $field.clone_from(value);
+                                }
+                            }
+                        )+
+                    })+
+                    $(ControlEvent::$single { $(ref mut $value,)+ .. } => {
+                        $(
+                            if let ControlEvent::$single { $value: ref new, .. } = *other {
+                                if fields & 1 != 0 {
+                                    $value.clone_from(new);
+                                }
+                            }
+                        )+
+                    })+
+                    $(ControlEvent::$tuple(ref mut value) => {
+                        if let ControlEvent::$tuple(ref new) = *other {
+                            if fields & 1 != 0 {
+                                value.clone_from(new);
+                            }
+                        }
+                    })+
+                    _ => (),
+                }
+            }
+
+            /// Unsets the fields of a combined event that are not given.
+            pub fn retain_fields(&mut self, fields: u8) {
+                match *self {
+                    $(ControlEvent::$combined { $(ref mut $field,)+ .. } => {
+                        let mut bits = (0..).map(|idx| 1 << idx);
+                        $(
+                            let bit: u8 = bits.next().unwrap();
+                            if fields & bit == 0 {
+                                *$field = None;
+                            }
+                        )+
+                    })+
+                    _ => (),
+                }
+            }
+        }
+    };
+}
+
+parameter_fields! {
+    combined {
+        ADSRCurve { attack, decay, release },
+        VelocityResponse { curve, range },
+        ADSRVelocity { sensitivity, time },
+        WavetablePosition { position, envelope, lfo, lfo_rate },
+        PulseWidth { width, lfo, lfo_rate },
+        OscLfo { pitch, amplitude, rate },
+        KeyScaling { breakpoint, left, right },
+        OscFrequency { coarse, fine, fixed, fixed_freq },
+        Filter { filter_type, freq, q },
+        Arpeggiator { enabled, mode, octaves, division, gate, swing, latch }
+    }
+    single {
+        ADSR { attack, decay, sustain, release },
+        ADSRRetrigger { mode },
+        ADSRRateScaling { rate },
+        SegmentEnvelope { breakpoints, sustain, loop_points },
+        Waveform { waveform },
+        UserWaveform { name },
+        Spectrum { amplitudes, phases },
+        NoiseTone { tone },
+        Phase { phase },
+        Transpose { transpose },
+        Detune { detune },
+        FM { levels },
+        Sync { source },
+        RingMod { levels }
+    }
+    tuple {
+        Interpolation,
+        Volume,
+        Pan,
+        OscillatorCount,
+        FMAlgorithm,
+        VoiceAllocation,
+        Tempo
+    }
 }
 
 pub trait Controllable {
@@ -0,0 +1,525 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use dsp::{find_algorithm, KeyScalingCurve, Waveform, FEEDBACK_SCALE};
+use errors::RunError;
+use event::ControlEvent;
+use patch::Patch;
+use types::*;
+
+const OPERATOR_CNT: usize = 6;
+const BANK_SIZE: usize = 32;
+/// Length of a voice in the packed format of the bulk dumps.
+const PACKED_VOICE_LEN: usize = 128;
+/// Length of a voice in the format of the single voice dumps.
+const VOICE_LEN: usize = 155;
+/// Peak phase deviation in periods of a modulator with the highest output level.
+const MAX_MOD_INDEX: Float = 2.0;
+/// Peak phase deviation in periods of an operator with the highest feedback.
+const MAX_FEEDBACK: Float = 0.5;
+/// Duration of an envelope segment with rate 0 over the full level range.
+const SLOWEST_SEGMENT: Time = 40.0;
+/// Detune of a step of the operator detune, the detune of the DX7 depends on the pitch.
+const DETUNE_CENTS: Float = 1.0;
+/// Scales of the LFO pitch modulation depth, the highest sensitivity is an octave.
+const PITCH_MOD_SENSITIVITY: [Float; 8] = [0.0, 0.039, 0.078, 0.129, 0.216, 0.361, 0.6, 1.0];
+/// Scales of the LFO amplitude modulation depth.
+const AMP_MOD_SENSITIVITY: [Float; 4] = [0.0, 0.26, 0.43, 1.0];
+/// Key of the lowest break point, A-1 on the DX7.
+const LOWEST_BREAKPOINT: u8 = 21;
+/// Level change in dB an octave from the break point per step of the scaling depth.
+const SCALING_DEPTH_DB: Float = 0.24;
+/// Highest keyboard rate scaling, which halves the envelope times about every octave.
+const MAX_RATE_SCALING: u8 = 7;
+
+/// An operator of a DX7 voice, the parameters are in the ranges of the DX7.
+#[derive(Debug, Clone, Default)]
+pub struct Dx7Operator {
+    pub rates: [u8; 4],  // rates of the envelope segments in the range `[0, 99]`
+    pub levels: [u8; 4], // levels that end the envelope segments in the range `[0, 99]`
+    pub amp_mod_sensitivity: u8,
+    pub velocity_sensitivity: u8,
+    pub output_level: u8,
+    pub fixed: bool, // plays a fixed frequency instead of a ratio of the key
+    pub coarse: u8,
+    pub fine: u8,
+    pub detune: i32,     // in the range `[-7, 7]`
+    pub breakpoint: u8,  // of the level scaling, `0` is A-1 and `39` is C3
+    pub left_depth: u8,  // of the level scaling below the break point
+    pub right_depth: u8, // of the level scaling above the break point
+    pub left_curve: u8,  // `0` to `3` are -LIN, -EXP, +EXP and +LIN
+    pub right_curve: u8,
+    pub rate_scaling: u8,
+}
+
+/// A voice of a DX7 SysEx dump.
+///
+/// The pitch envelope and the LFO delay and waveform have no counterpart and are
+/// skipped, the LFO is a sine.
+#[derive(Debug, Clone, Default)]
+pub struct Dx7Voice {
+    pub name: String,
+    pub operators: [Dx7Operator; OPERATOR_CNT], // starting with operator 1
+    pub algorithm: usize,                       // in the range `[1, 32]`
+    pub feedback: u8,
+    pub lfo_speed: u8,
+    pub lfo_pitch_depth: u8,
+    pub lfo_amp_depth: u8,
+    pub pitch_mod_sensitivity: u8,
+    pub transpose: i32, // in semitones
+}
+impl Dx7Voice {
+    /// Parses a voice in the unpacked format of the single voice dumps.
+    /// Out of range values are clamped, banks often contain garbage in unused bits.
+    fn parse(data: &[u8]) -> Self {
+        let mut voice = Dx7Voice::default();
+        // the parameters of operator 6 come first
+        for (operator, data) in voice.operators.iter_mut().rev().zip(data.chunks(21)) {
+            for idx in 0..4 {
+                operator.rates[idx] = data[idx].min(99);
+                operator.levels[idx] = data[4 + idx].min(99);
+            }
+            operator.breakpoint = data[8].min(99);
+            operator.left_depth = data[9].min(99);
+            operator.right_depth = data[10].min(99);
+            operator.left_curve = data[11].min(3);
+            operator.right_curve = data[12].min(3);
+            operator.rate_scaling = data[13].min(MAX_RATE_SCALING);
+            operator.amp_mod_sensitivity = data[14].min(3);
+            operator.velocity_sensitivity = data[15].min(7);
+            operator.output_level = data[16].min(99);
+            operator.fixed = data[17] & 1 == 1;
+            operator.coarse = data[18].min(31);
+            operator.fine = data[19].min(99);
+            operator.detune = i32::from(data[20].min(14)) - 7;
+        }
+        voice.algorithm = usize::from(data[134].min(31)) + 1;
+        voice.feedback = data[135].min(7);
+        voice.lfo_speed = data[137].min(99);
+        voice.lfo_pitch_depth = data[139].min(99);
+        voice.lfo_amp_depth = data[140].min(99);
+        voice.pitch_mod_sensitivity = data[143].min(7);
+        voice.transpose = i32::from(data[144].min(48)) - 24;
+        voice.name = data[145..155]
+            .iter()
+            .map(|&c| {
+                if c >= 0x20 && c < 0x7F {
+                    c as char
+                } else {
+                    ' '
+                }
+            })
+            .collect::<String>()
+            .trim()
+            .to_owned();
+        voice
+    }
+
+    /// Converts a voice in the packed format of the bulk dumps to the unpacked one.
+    fn unpack(data: &[u8]) -> Vec<u8> {
+        let mut voice = Vec::with_capacity(VOICE_LEN);
+        for operator in data[..102].chunks(17) {
+            // rates, levels, break point and scaling depths are not packed
+            voice.extend_from_slice(&operator[..11]);
+            voice.extend_from_slice(&[
+                operator[11] & 0x03,
+                (operator[11] >> 2) & 0x03,
+                operator[12] & 0x07,
+                operator[13] & 0x03,
+                (operator[13] >> 2) & 0x07,
+                operator[14],
+                operator[15] & 0x01,
+                (operator[15] >> 1) & 0x1F,
+                operator[16],
+                (operator[12] >> 3) & 0x0F,
+            ]);
+        }
+        // pitch envelope
+        voice.extend_from_slice(&data[102..110]);
+        voice.extend_from_slice(&[data[110] & 0x1F, data[111] & 0x07, (data[111] >> 3) & 0x01]);
+        // LFO speed, delay and depths
+        voice.extend_from_slice(&data[112..116]);
+        voice.extend_from_slice(&[
+            data[116] & 0x01,
+            (data[116] >> 1) & 0x07,
+            (data[116] >> 4) & 0x07,
+            data[117],
+        ]);
+        voice.extend_from_slice(&data[118..128]);
+        voice
+    }
+
+    /// Translates the voice into a patch for the oscillators `OSC1` to `OSC6`.
+    pub fn to_patch(&self) -> Patch {
+        let algorithm = find_algorithm(&format!("DX7-{}", self.algorithm))
+            .expect("the algorithms of the DX7 are missing");
+        let mut patch = Patch::default();
+        patch.update(&ControlEvent::FMAlgorithm(algorithm.name.to_owned()));
+
+        let lfo_rate = lfo_freq(self.lfo_speed);
+        let pitch_depth = 12.0
+            * Float::from(self.lfo_pitch_depth)
+            * PITCH_MOD_SENSITIVITY[self.pitch_mod_sensitivity as usize]
+            / 99.0;
+        let mut gains = [0.0; OPERATOR_CNT];
+        for (idx, (operator, gain)) in self.operators.iter().zip(gains.iter_mut()).enumerate() {
+            let id = format!("OSC{}", idx + 1);
+            let (attack, decay, sustain, release, peak) = operator.envelope();
+            *gain = level_gain(operator.output_level) * peak;
+            patch.update(&ControlEvent::Waveform {
+                id: id.clone(),
+                waveform: Waveform::Sine,
+            });
+            patch.update(&ControlEvent::Sync {
+                id: id.clone(),
+                source: None,
+            });
+            patch.update(&ControlEvent::Transpose {
+                id: id.clone(),
+                transpose: 0,
+            });
+            patch.update(&operator.frequency(&id, self.transpose));
+            patch.update(&ControlEvent::ADSR {
+                id: format!("ADSR-{}", id),
+                attack,
+                decay,
+                sustain,
+                release,
+            });
+            patch.update(&ControlEvent::ADSRVelocity {
+                id: format!("ADSR-{}", id),
+                sensitivity: Some(Float::from(operator.velocity_sensitivity) / 7.0),
+                time: Some(0.0),
+            });
+            patch.update(&ControlEvent::ADSRRateScaling {
+                id: format!("ADSR-{}", id),
+                rate: Float::from(operator.rate_scaling) / Float::from(MAX_RATE_SCALING),
+            });
+            patch.update(&ControlEvent::KeyScaling {
+                id: id.clone(),
+                breakpoint: Some(LOWEST_BREAKPOINT + operator.breakpoint),
+                left: Some(key_scaling(operator.left_depth, operator.left_curve)),
+                right: Some(key_scaling(operator.right_depth, operator.right_curve)),
+            });
+            patch.update(&ControlEvent::OscLfo {
+                id,
+                pitch: Some(pitch_depth),
+                amplitude: Some(
+                    Float::from(self.lfo_amp_depth)
+                        * AMP_MOD_SENSITIVITY[operator.amp_mod_sensitivity as usize]
+                        / 99.0,
+                ),
+                rate: Some(lfo_rate),
+            });
+        }
+
+        // the modulation indices and the feedback depend on the output of the modulators
+        let mut fm_mod = [[0.0; OPERATOR_CNT]; OPERATOR_CNT];
+        for &(modulator, carrier) in algorithm.modulations {
+            fm_mod[carrier - 1][modulator - 1] = MAX_MOD_INDEX * gains[modulator - 1];
+        }
+        if self.feedback > 0 {
+            let (modulator, carrier) = algorithm.feedback;
+            let feedback = MAX_FEEDBACK
+                * Float::powi(2.0, i32::from(self.feedback) - 7)
+                * gains[modulator - 1];
+            // the self modulation is scaled down by the voices
+            fm_mod[carrier - 1][modulator - 1] = if modulator == carrier {
+                feedback / FEEDBACK_SCALE
+            } else {
+                feedback
+            };
+        }
+        for (idx, levels) in fm_mod.iter().enumerate() {
+            patch.update(&ControlEvent::FM {
+                id: format!("OSC{}", idx + 1),
+                levels: levels.to_vec(),
+            });
+        }
+
+        // the carriers share the level of a single oscillator
+        let carrier_gain = MINUS_THREE_DB / algorithm.carriers.len() as Float;
+        let volume = gains
+            .iter()
+            .enumerate()
+            .map(|(idx, gain)| {
+                if algorithm.carriers.contains(&(idx + 1)) && *gain > 0.0 {
+                    Float::to_db(carrier_gain * gain)
+                } else {
+                    -61.0
+                }
+            }).collect();
+        patch.update(&ControlEvent::Volume(volume));
+        patch.update(&ControlEvent::Pan(vec![0.0; OPERATOR_CNT]));
+        patch
+    }
+}
+impl Dx7Operator {
+    /// Returns the attack, decay, sustain and release of the envelope and its
+    /// peak level. The peak is the level at the end of the attack, the decay lasts
+    /// until the third level is reached, which is held.
+    fn envelope(&self) -> (Time, Time, Float, Time, Float) {
+        let (rates, levels) = (&self.rates, &self.levels);
+        let attack = segment_time(rates[0], levels[3], levels[0]);
+        let decay = segment_time(rates[1], levels[0], levels[1])
+            + segment_time(rates[2], levels[1], levels[2]);
+        let release = segment_time(rates[3], levels[2], levels[3]);
+        let peak = level_gain(levels[0]);
+        let sustain = if peak > 0.0 {
+            (level_gain(levels[2]) / peak).min(1.0)
+        } else {
+            0.0
+        };
+        (attack, decay, sustain, release, peak)
+    }
+
+    /// Returns the event that sets the frequency ratio or the fixed frequency.
+    fn frequency(&self, id: &str, transpose: i32) -> ControlEvent {
+        let detune = Float::powf(2.0, Float::from(self.detune) * DETUNE_CENTS / 1200.0);
+        if self.fixed {
+            // the fixed frequencies start at 1, 10, 100 and 1000 Hz
+            let exponent = Float::from(self.coarse % 4) + Float::from(self.fine) / 100.0;
+            ControlEvent::OscFrequency {
+                id: id.to_owned(),
+                coarse: None,
+                fine: None,
+                fixed: Some(true),
+                fixed_freq: Some(Float::powf(10.0, exponent) * detune),
+            }
+        } else {
+            let coarse = if self.coarse == 0 {
+                0.5
+            } else {
+                Float::from(self.coarse)
+            };
+            ControlEvent::OscFrequency {
+                id: id.to_owned(),
+                coarse: Some(coarse * Float::powf(2.0, Float::from(transpose) / 12.0) * detune),
+                fine: Some(Float::from(self.fine) / 100.0),
+                fixed: Some(false),
+                fixed_freq: None,
+            }
+        }
+    }
+}
+
+/// Converts an output or envelope level to a gain, a step is about 0.75 dB.
+fn level_gain(level: u8) -> Float {
+    if level == 0 {
+        0.0
+    } else {
+        Float::powf(2.0, (Float::from(level) - 99.0) / 8.0)
+    }
+}
+
+/// Converts a scaling depth and curve to the level scaling on one side of the break point.
+fn key_scaling(depth: u8, curve: u8) -> KeyScalingCurve {
+    let depth = SCALING_DEPTH_DB * Float::from(depth);
+    match curve {
+        0 => KeyScalingCurve::Linear(-depth),
+        1 => KeyScalingCurve::Exponential(-depth),
+        2 => KeyScalingCurve::Exponential(depth),
+        _ => KeyScalingCurve::Linear(depth),
+    }
+}
+
+/// Returns the duration of an envelope segment between two levels.
+/// The duration halves every four steps of the rate, which has 64 steps internally.
+fn segment_time(rate: u8, from: u8, to: u8) -> Time {
+    let rate = Time::from(rate) * 41.0 / 64.0;
+    let distance = (i32::from(to) - i32::from(from)).abs() as Time / 99.0;
+    SLOWEST_SEGMENT * Time::powf(2.0, -rate / 4.0) * distance
+}
+
+/// Converts the LFO speed to a frequency in Hz, the speeds above 62 are steeper.
+fn lfo_freq(speed: u8) -> Float {
+    let speed = if speed == 0 {
+        1
+    } else {
+        165 * usize::from(speed) / 64
+    };
+    let scale = if speed < 160 {
+        11
+    } else {
+        11 + (speed - 160) / 16
+    };
+    0.005_865 * (speed * scale) as Float
+}
+
+/// Parses a DX7 SysEx file, see `parse_dx7_sysex`.
+pub fn load_dx7_sysex<P: AsRef<Path>>(path: P) -> Result<Vec<Dx7Voice>, RunError> {
+    let mut file = try!(File::open(path).map_err(RunError::IoError));
+    let mut data = Vec::new();
+    try!(file.read_to_end(&mut data).map_err(RunError::IoError));
+    parse_dx7_sysex(&data)
+}
+
+/// Parses the voices of a DX7 32 voice bulk dump or the voice of a single voice dump.
+/// See the SysEx section of the DX7 manual.
+pub fn parse_dx7_sysex(data: &[u8]) -> Result<Vec<Dx7Voice>, RunError> {
+    let error = |msg: &str| Err(RunError::SysExError(msg.to_owned()));
+    // F0 43 <channel> <format> <byte count> <byte count> ... <checksum> F7
+    if data.len() < 8 || data[0] != 0xF0 || data[1] != 0x43 || data[2] > 0x0F {
+        return error("not a Yamaha SysEx message");
+    }
+    let (format, len) = (data[3], (usize::from(data[4]) << 7) | usize::from(data[5]));
+    let expected_len = match format {
+        0x00 => VOICE_LEN,
+        0x09 => BANK_SIZE * PACKED_VOICE_LEN,
+        _ => return error("not a DX7 voice dump"),
+    };
+    if len != expected_len || data.len() != len + 8 || data[data.len() - 1] != 0xF7 {
+        return error("invalid length");
+    }
+    let voices = &data[6..6 + len];
+    let sum = voices.iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte));
+    if sum.wrapping_neg() & 0x7F != data[6 + len] {
+        return error("checksum mismatch");
+    }
+    if format == 0x00 {
+        Ok(vec![Dx7Voice::parse(voices)])
+    } else {
+        Ok(voices
+            .chunks(PACKED_VOICE_LEN)
+            .map(|voice| Dx7Voice::parse(&Dx7Voice::unpack(voice)))
+            .collect())
+    }
+}
+
+/// The fixtures are generated by `tests/fixtures/generate_dx7_fixtures.py`.
+#[cfg(test)]
+fn fixture(name: &str) -> ::std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn test_dx7_bank() {
+    let voices = load_dx7_sysex(fixture("dx7_bank.syx")).unwrap();
+    assert_eq!(voices.len(), BANK_SIZE);
+    let names = voices
+        .iter()
+        .take(4)
+        .map(|voice| voice.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["E.PIANO", "SYN BASS", "TUB BELLS", "STRINGS"]);
+    assert_eq!(voices[BANK_SIZE - 1].name, "INIT VOICE");
+
+    let piano = &voices[0];
+    assert_eq!((piano.algorithm, piano.feedback), (5, 6));
+    assert_eq!((piano.lfo_speed, piano.lfo_amp_depth), (34, 12));
+    assert_eq!(piano.operators[1].coarse, 14);
+    assert_eq!(piano.operators[1].output_level, 58);
+    assert_eq!(piano.operators[2].detune, 3);
+    assert_eq!(piano.operators[5].amp_mod_sensitivity, 1);
+    assert_eq!(piano.operators[1].velocity_sensitivity, 7);
+    assert_eq!(piano.operators[0].rates, [96, 25, 25, 67]);
+    let patch = piano.to_patch();
+    match patch.get("FM-ALGORITHM", "") {
+        Some(ControlEvent::FMAlgorithm(ref name)) => assert_eq!(name, "DX7-5"),
+        _ => panic!("algorithm is missing"),
+    }
+    match patch.get("FM", "OSC1") {
+        Some(ControlEvent::FM { ref levels, .. }) => {
+            let modulator_gain = level_gain(58) * level_gain(99);
+            assert_relative_eq!(levels[1], MAX_MOD_INDEX * modulator_gain);
+            assert_eq!(levels.iter().filter(|level| **level > 0.0).count(), 1);
+        }
+        _ => panic!("modulation of OSC1 is missing"),
+    }
+    match patch.get("FM", "OSC6") {
+        Some(ControlEvent::FM { ref levels, .. }) => {
+            let feedback = MAX_FEEDBACK * 0.5 * level_gain(79) / FEEDBACK_SCALE;
+            assert_relative_eq!(levels[5], feedback);
+        }
+        _ => panic!("feedback of OSC6 is missing"),
+    }
+    match patch.get("ADSR-VELOCITY", "ADSR-OSC1") {
+        Some(ControlEvent::ADSRVelocity { sensitivity, .. }) => {
+            assert_relative_eq!(sensitivity.unwrap(), 2.0 / 7.0)
+        }
+        _ => panic!("velocity sensitivity of OSC1 is missing"),
+    }
+    match patch.get("KEY-SCALING", "OSC1") {
+        Some(ControlEvent::KeyScaling {
+            breakpoint,
+            left,
+            right,
+            ..
+        }) => {
+            assert_eq!(breakpoint, Some(69));
+            assert_eq!(left, Some(KeyScalingCurve::Linear(10.0 * SCALING_DEPTH_DB)));
+            assert_eq!(
+                right,
+                Some(KeyScalingCurve::Exponential(-20.0 * SCALING_DEPTH_DB))
+            );
+        }
+        _ => panic!("key scaling of OSC1 is missing"),
+    }
+    match patch.get("ADSR-RATE-SCALING", "ADSR-OSC1") {
+        Some(ControlEvent::ADSRRateScaling { rate, .. }) => assert_relative_eq!(rate, 3.0 / 7.0),
+        _ => panic!("rate scaling of OSC1 is missing"),
+    }
+
+    // the transpose moves the ratios, a coarse ratio of 0 is half the key frequency
+    let bass = voices[1].to_patch();
+    match bass.get("FREQUENCY", "OSC1") {
+        Some(ControlEvent::OscFrequency { coarse, fixed, .. }) => {
+            assert_relative_eq!(coarse.unwrap(), 0.25);
+            assert_eq!(fixed, Some(false));
+        }
+        _ => panic!("frequency of OSC1 is missing"),
+    }
+
+    let bell = voices[2].to_patch();
+    match bell.get("FREQUENCY", "OSC5") {
+        Some(ControlEvent::OscFrequency { fixed_freq, .. }) => {
+            assert_relative_eq!(fixed_freq.unwrap(), Float::powf(10.0, 2.46), epsilon = 1e-9)
+        }
+        _ => panic!("fixed frequency of OSC5 is missing"),
+    }
+
+    // only the first carrier of the initial voice is audible
+    let init = voices[BANK_SIZE - 1].to_patch();
+    match init.get("VOLUME", "") {
+        Some(ControlEvent::Volume(ref volume)) => {
+            assert_relative_eq!(volume[0], Float::to_db(MINUS_THREE_DB / 2.0));
+            assert!(volume[1..].iter().all(|&db| db == -61.0));
+        }
+        _ => panic!("volume is missing"),
+    }
+}
+
+#[test]
+fn test_dx7_voice() {
+    let mut data = Vec::new();
+    File::open(fixture("dx7_voice.syx"))
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    let voices = parse_dx7_sysex(&data).unwrap();
+    assert_eq!(voices.len(), 1);
+    let brass = &voices[0];
+    assert_eq!(brass.name, "BRASS");
+    assert_eq!(
+        (brass.algorithm, brass.feedback, brass.transpose),
+        (22, 7, 0)
+    );
+    assert_eq!((brass.lfo_pitch_depth, brass.pitch_mod_sensitivity), (5, 3));
+    assert_eq!(brass.operators[5].detune, 1);
+    assert_eq!(brass.operators[1].levels, [82, 95, 96, 0]);
+    let (attack, decay, sustain, release, peak) = brass.operators[0].envelope();
+    assert!(attack > 0.0 && decay > 0.0 && release > 0.0);
+    assert_relative_eq!(peak, 1.0);
+    assert_relative_eq!(sustain, level_gain(96));
+
+    let mut corrupted = data.clone();
+    corrupted[10] ^= 0x01;
+    assert!(parse_dx7_sysex(&corrupted).is_err());
+    assert!(parse_dx7_sysex(&data[..100]).is_err());
+    let mut other_manufacturer = data.clone();
+    other_manufacturer[1] = 0x41;
+    assert!(parse_dx7_sysex(&other_manufacturer).is_err());
+}
@@ -6,17 +6,143 @@ use std::thread;
 use std::time::Duration;
 use types::*;
 
-use io::Receiver;
+use io::{parse_tuning_sysex, Receiver};
 
+use dsp::Transport;
 use event::ControlEvent;
 
+/// The PortMidi functions that are needed to read complete SysEx messages.
+/// `portmidi::InputPort` drops the fourth byte of each event, which carries SysEx data.
+mod ffi {
+    use std::os::raw::{c_int, c_void};
+
+    #[derive(Debug, Clone, Copy, Default)]
+    #[repr(C)]
+    pub struct PmEvent {
+        pub message: i32,
+        pub timestamp: u32,
+    }
+
+    extern "C" {
+        pub fn Pm_OpenInput(
+            stream: *mut *mut c_void,
+            device: c_int,
+            driver_info: *mut c_void,
+            buffer_size: i32,
+            time_proc: *const c_void,
+            time_info: *mut c_void,
+        ) -> c_int;
+        pub fn Pm_Read(stream: *mut c_void, buffer: *mut PmEvent, length: i32) -> c_int;
+        pub fn Pm_Close(stream: *mut c_void) -> c_int;
+    }
+}
+
+/// Upper bound for the size of a SysEx message, larger messages are dropped.
+const MAX_SYSEX_LEN: usize = 64 * 1024;
+
+/// An input port that returns the raw four byte PortMidi events.
+struct InputPort {
+    stream: *mut ::std::os::raw::c_void,
+    buffer: Vec<ffi::PmEvent>,
+    sysex: SysExAssembler,
+}
+impl InputPort {
+    fn open(device: &portmidi::DeviceInfo, buf_len: usize) -> Option<Self> {
+        let mut stream = ::std::ptr::null_mut();
+        let err = unsafe {
+            ffi::Pm_OpenInput(
+                &mut stream,
+                device.id(),
+                ::std::ptr::null_mut(),
+                buf_len as i32,
+                ::std::ptr::null(),
+                ::std::ptr::null_mut(),
+            )
+        };
+        if err == 0 && !stream.is_null() {
+            Some(InputPort {
+                stream,
+                buffer: vec![ffi::PmEvent::default(); buf_len],
+                sysex: SysExAssembler::default(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Appends the decoded events and their timestamps to `events`.
+    fn read(&mut self, events: &mut Vec<(u32, MidiEvent)>) -> Result<(), RunError> {
+        let cnt = unsafe {
+            ffi::Pm_Read(
+                self.stream,
+                self.buffer.as_mut_ptr(),
+                self.buffer.len() as i32,
+            )
+        };
+        if cnt < 0 {
+            return Err(RunError::MidiError(portmidi::Error::Unknown));
+        }
+        for event in &self.buffer[..cnt as usize] {
+            if let Some(decoded) = self.sysex.decode(event.message as u32) {
+                events.push((event.timestamp, decoded));
+            }
+        }
+        Ok(())
+    }
+}
+impl Drop for InputPort {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::Pm_Close(self.stream);
+        }
+    }
+}
+
+/// Collects SysEx messages, which PortMidi splits into events of four bytes.
+/// Real-time messages may be interleaved with the SysEx events.
+#[derive(Default)]
+struct SysExAssembler {
+    data: Vec<u8>,
+    receiving: bool,
+}
+impl SysExAssembler {
+    /// Returns the decoded event or `None` if the event is part of an unfinished SysEx message.
+    fn decode(&mut self, message: u32) -> Option<MidiEvent> {
+        let status = (message & 0xFF) as u8;
+        if status >= 0xF8 {
+            return Some(MidiEvent::from(message));
+        }
+        if status == 0xF0 {
+            self.data.clear();
+            self.receiving = true;
+        } else if !self.receiving || (status >= 0x80 && status != 0xF7) {
+            // any other status byte ends an unfinished SysEx message
+            self.receiving = false;
+            return Some(MidiEvent::from(message));
+        }
+        for shift in 0..4 {
+            let byte = (message >> (8 * shift)) as u8;
+            self.data.push(byte);
+            if byte == 0xF7 {
+                self.receiving = false;
+                return Some(MidiEvent::SysEx(self.data.drain(..).collect()));
+            }
+        }
+        if self.data.len() > MAX_SYSEX_LEN {
+            self.receiving = false;
+            self.data.clear();
+        }
+        None
+    }
+}
+
 /// `MidiReceiver` needs to hold a reference to the portmidi context.
 /// This is because portmidi's terminate function is called when the context
 /// is dropped.
 #[allow(dead_code)]
 pub struct MidiReceiver {
     context: portmidi::PortMidi,
-    in_ports: Vec<portmidi::InputPort>,
+    in_ports: Vec<InputPort>,
     buf_len: usize,
 }
 impl MidiReceiver {
@@ -30,9 +156,9 @@ impl MidiReceiver {
             .filter(|dev| dev.is_input())
             .collect::<Vec<portmidi::DeviceInfo>>();
         let in_ports = in_devices
-            .into_iter()
-            .filter_map(|dev| context.input_port(dev, BUF_LEN).ok())
-            .collect::<Vec<portmidi::InputPort>>();
+            .iter()
+            .filter_map(|dev| InputPort::open(dev, BUF_LEN))
+            .collect::<Vec<InputPort>>();
         if in_ports.is_empty() {
             Err(RunError::NoMidiDeviceAvailable)
         } else {
@@ -45,17 +171,23 @@ impl MidiReceiver {
     }
 }
 impl MidiReceiver {
-    fn receive(
-        &self,
-        port: &portmidi::InputPort,
-    ) -> Result<Option<Vec<portmidi::MidiEvent>>, RunError> {
-        port.read_n(self.buf_len).map_err(RunError::MidiError)
-    }
-
-    fn to_control_event(&self, event: &MidiEvent) -> ControlEvent {
+    /// The timestamp of the event in milliseconds is needed to measure the MIDI clock tempo.
+    fn to_control_event(&self, event: &MidiEvent, timestamp: u32) -> ControlEvent {
         match event {
             MidiEvent::NoteOn { key, velocity, .. } => ControlEvent::NoteOn { key: *key, velocity: *velocity },
             MidiEvent::NoteOff { key, velocity, .. } => ControlEvent::NoteOff { key: *key, velocity: *velocity },
+            MidiEvent::TimingClock => ControlEvent::ClockTick(timestamp),
+            MidiEvent::Start => ControlEvent::Transport(Transport::Start),
+            MidiEvent::Stop => ControlEvent::Transport(Transport::Stop),
+            MidiEvent::Continue => ControlEvent::Transport(Transport::Continue),
+            // the song position is counted in sixteenth notes
+            MidiEvent::SongPosition(position) => {
+                ControlEvent::Transport(Transport::SongPosition(Float::from(*position) / 4.0))
+            }
+            MidiEvent::SysEx(data) => match parse_tuning_sysex(data) {
+                Some(tunings) => ControlEvent::KeyTuning(tunings),
+                None => ControlEvent::Unsupported,
+            },
             _ => ControlEvent::Unsupported,
         }
     }
@@ -65,19 +197,18 @@ impl Receiver for MidiReceiver {
         let mut event_buf = Vec::with_capacity(self.buf_len);
         let timeout = Duration::from_millis(20);
         loop {
-            for port in &self.in_ports {
-                match self.receive(port) {
-                    Ok(Some(mut events)) => event_buf.append(&mut events),
-                    Ok(_) => (),
+            for port in &mut self.in_ports {
+                match port.read(&mut event_buf) {
+                    Ok(()) => (),
                     Err(RunError::MidiError(err)) => println!("receive_and_send) Error: {:?}", err),
                     Err(err) => panic!(err),
                 }
             }
 
-            // event_buf.sort_by_key(|e| e.timestamp);
-            while let Some(event) = event_buf.pop() {
-                tx.send(self.to_control_event(&MidiEvent::from(event)))
-                    .unwrap();
+            // the events of all ports are sent in the order they were received
+            event_buf.sort_by_key(|&(timestamp, _)| timestamp);
+            for (timestamp, event) in event_buf.drain(..) {
+                tx.send(self.to_control_event(&event, timestamp)).unwrap();
             }
 
             thread::sleep(timeout);
@@ -85,18 +216,28 @@ impl Receiver for MidiReceiver {
     }
 }
 
+impl From<u32> for MidiEvent {
+    /// Decodes a PortMidi message, SysEx data has to be assembled from several messages.
+    fn from(message: u32) -> Self {
+        MidiEvent::from(portmidi::MidiEvent::from(portmidi::MidiMessage::from(
+            message as i32,
+        )))
+    }
+}
+
 impl From<portmidi::MidiEvent> for MidiEvent {
     fn from(event: portmidi::MidiEvent) -> Self {
         let status = event.message.status;
         let data1 = event.message.data1;
         let data2 = event.message.data2;
         match status {
-            0xF0 => MidiEvent::SysEx,
+            // the payload is assembled from the following events
+            0xF0 => MidiEvent::SysEx(Vec::new()),
             0xF1 => MidiEvent::TimeCodeQuarterFrame {
                 msg_type: ((data1 & 0xF0) >> 4) as u8,
                 value: (data1 & 0x0F) as u8,
             },
-            0xF2 => MidiEvent::SongPosition(u16::from(data1) + (u16::from(data2) << 8)),
+            0xF2 => MidiEvent::SongPosition(u16::from(data1) + (u16::from(data2) << 7)),
             0xF3 => MidiEvent::SongSelect(data1 as u8),
             0xF6 => MidiEvent::TuneRequest,
             0xF7 => MidiEvent::SysExEnd,
@@ -189,7 +330,7 @@ pub enum MidiEvent {
         pitchbend: u16,
         channel: u8,
     },
-    SysEx,
+    SysEx(Vec<u8>),
     SysExEnd,
     TimeCodeQuarterFrame {
         msg_type: u8,
@@ -205,3 +346,28 @@ pub enum MidiEvent {
     ActiveSensing,
     Reset,
 }
+
+#[test]
+fn test_sysex_assembly() {
+    let mut sysex = SysExAssembler::default();
+    // F0 7E 00 09 | 01 <clock> 02 03 | F7 <garbage>
+    assert!(sysex.decode(0x09_00_7E_F0).is_none());
+    match sysex.decode(0xF8) {
+        Some(MidiEvent::TimingClock) => (),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(sysex.decode(0x03_02_00_01).is_none());
+    match sysex.decode(0x00_00_00_F7) {
+        Some(MidiEvent::SysEx(data)) => {
+            assert_eq!(data, vec![0xF0, 0x7E, 0x00, 0x09, 0x01, 0x00, 0x02, 0x03, 0xF7])
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+    // a status byte aborts an unfinished message
+    assert!(sysex.decode(0x09_00_7E_F0).is_none());
+    match sysex.decode(0x00_40_3C_90) {
+        Some(MidiEvent::NoteOn { key: 60, .. }) => (),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    assert!(sysex.decode(0x00_00_00_F7).is_some());
+}
@@ -1,11 +1,22 @@
+mod dx7;
 mod midi;
 mod osc;
+mod oscquery;
+mod registry;
+mod tuning;
+mod wav;
 
 use event::ControlEvent;
 use std::sync::mpsc;
+use std::sync::Arc;
 
+pub use self::dx7::*;
 pub use self::midi::*;
 pub use self::osc::*;
+pub use self::oscquery::OscQueryServer;
+pub use self::registry::{Registry, Scaling};
+pub use self::tuning::*;
+pub use self::wav::*;
 
 use types::Float;
 
@@ -15,19 +26,45 @@ pub trait Receiver {
 
 pub const CONCERT_A: Float = 440.0;
 
+/// Converts keys to frequencies using a table with an entry for each of the 128 keys.
+/// The table is shared, so a new tuning can be built on another thread and swapped in
+/// without copying it.
 pub struct PitchConvert {
-    table: Vec<Float>,
+    table: Arc<Vec<Float>>,
 }
 impl PitchConvert {
     pub fn new(tune_freq: Float) -> Self {
         PitchConvert {
             // see https://en.wikipedia.org/wiki/MIDI_Tuning_Standard
-            table: (0..128)
-                .map(|key| {
-                    let dist_concert_a = key as isize - 69;
-                    let two: Float = 2.0;
-                    two.powf(dist_concert_a as Float / 12.0) * tune_freq
-                }).collect::<Vec<_>>(),
+            table: Arc::new(
+                (0..128)
+                    .map(|key| {
+                        let dist_concert_a = key as isize - 69;
+                        let two: Float = 2.0;
+                        two.powf(dist_concert_a as Float / 12.0) * tune_freq
+                    }).collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    pub fn table(&self) -> &Arc<Vec<Float>> {
+        &self.table
+    }
+
+    /// Replaces the frequency table, tables without 128 entries are ignored.
+    pub fn set_table(&mut self, table: &Arc<Vec<Float>>) {
+        if table.len() == 128 {
+            self.table = table.clone();
+        }
+    }
+
+    /// Changes the frequencies of single keys.
+    pub fn set_key_freqs(&mut self, freqs: &[(u8, Float)]) {
+        let table = Arc::make_mut(&mut self.table);
+        for &(key, freq) in freqs {
+            if let Some(entry) = table.get_mut(key as usize) {
+                *entry = freq;
+            }
         }
     }
 
@@ -3,13 +3,21 @@ extern crate rosc;
 use rosc::{OscMessage, OscPacket, OscType};
 
 use errors::RunError;
+use std::collections::HashMap;
 use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 
-use io::Receiver;
+use io::registry::NAMESPACE;
+use io::{load_tuning, PitchConvert, Receiver, Registry};
 
-use dsp::{FilterType, Waveform};
+use dsp::{
+    load_wavetable, resynthesize_wavetable, Breakpoint, EnvelopeCurve, KeyScalingCurve,
+    SpectrumQueue, Transport, VelocityCurve, LOWEST_FREQ, MAX_OSC_CNT, MAX_VELOCITY_RANGE,
+};
 use event::ControlEvent;
+use patch::Patch;
 use types::*;
 
 macro_rules! exp_scale {
@@ -19,53 +27,695 @@ macro_rules! exp_scale {
 }
 
 const TRANSPOSE_RANGE: usize = 6; // Octaves
+const MAX_COARSE_RATIO: Float = 31.0;
+const RESYNTHESIS_FRAMES: usize = 64;
 const GRID_SIZE: usize = 96;
+/// Address of the query for the descriptions of the parameters.
+const QUERY_ADDRESS: &str = "/ytterbium/query";
+/// Address of the replies to the query.
+const INFO_ADDRESS: &str = "/ytterbium/info";
+
+/// Converts between the range of a widget of the controller and the range of a parameter.
+#[derive(Debug, Clone, Copy)]
+enum Conversion {
+    Identity,
+    Exp10,
+    Exp20,
+    Sustain,
+    FixedFreq,
+    Ratio,
+    Cents,
+    FilterFreq,
+    FilterQ,
+    Volume,
+    VelocityRange,
+    Operators,
+    Bipolar,
+}
+impl Conversion {
+    fn parameter_value(self, x: Float) -> Float {
+        match self {
+            Conversion::Identity => x,
+            Conversion::Exp10 => 10.0 * exp_scale!(x),
+            Conversion::Exp20 => 20.0 * exp_scale!(x),
+            Conversion::Sustain => Float::from_db((1.0 - exp_scale!(x)) * -40.0),
+            Conversion::FixedFreq => 1.0 + 10_000.0 * exp_scale!(x),
+            // coarse frequency ratios like in the DX7, the lowest ratio is 0.5
+            Conversion::Ratio => (x * MAX_COARSE_RATIO).round().max(0.5),
+            Conversion::Cents => x * 100.0,
+            Conversion::FilterFreq => 40.0 + 20_000.0 * x,
+            Conversion::FilterQ => 4.0 * x,
+            Conversion::Volume => (1.0 - x) * -61.0,
+            Conversion::VelocityRange => x * MAX_VELOCITY_RANGE,
+            // the menu starts with a single operator
+            Conversion::Operators => x + 1.0,
+            Conversion::Bipolar => 2.0 * x - 1.0,
+        }
+    }
+
+    fn widget_value(self, value: Float) -> Float {
+        match self {
+            Conversion::Identity => value,
+            Conversion::Exp10 => exp_unscale(value / 10.0),
+            Conversion::Exp20 => exp_unscale(value / 20.0),
+            Conversion::Sustain => exp_unscale((1.0 + Float::to_db(value) / 40.0).max(0.0)),
+            Conversion::FixedFreq => exp_unscale((value - 1.0) / 10_000.0),
+            Conversion::Ratio => value / MAX_COARSE_RATIO,
+            Conversion::Cents => value / 100.0,
+            Conversion::FilterFreq => (value - 40.0) / 20_000.0,
+            Conversion::FilterQ => value / 4.0,
+            Conversion::Volume => (1.0 + value / 61.0).max(0.0),
+            Conversion::VelocityRange => value / MAX_VELOCITY_RANGE,
+            Conversion::Operators => value - 1.0,
+            Conversion::Bipolar => (value + 1.0) / 2.0,
+        }
+    }
+}
+
+/// A widget of the controller, whose arguments set one parameter each.
+struct Alias {
+    address: String,
+    parameters: Vec<(usize, Conversion)>,
+}
+
+/// Returns the widgets of the Lemur patch that set parameters of the registry.
+fn lemur_aliases(registry: &Registry) -> Vec<Alias> {
+    use self::Conversion::*;
+
+    let mut aliases = Vec::new();
+    {
+        let mut alias = |address: String, parameters: &[(String, Conversion)]| {
+            aliases.push(Alias {
+                address,
+                parameters: parameters
+                    .iter()
+                    .map(|&(ref parameter, conversion)| {
+                        let address = format!("{}/{}", NAMESPACE, parameter);
+                        (registry.find(&address).expect(&address), conversion)
+                    }).collect(),
+            })
+        };
+        let each_oscillator = |parameter: &str, conversion: Conversion| {
+            (1..MAX_OSC_CNT + 1)
+                .map(|osc| (format!("osc/{}/{}", osc, parameter), conversion))
+                .collect::<Vec<_>>()
+        };
+        for osc in 1..MAX_OSC_CNT + 1 {
+            let widget = |name: &str| format!("/OSCILLATORS/OSC{}/{}", osc, name);
+            let parameter = |name: &str, conversion| (format!("osc/{}/{}", osc, name), conversion);
+            for &(name, address, conversion) in &[
+                ("WAVEFORM/selection", "waveform", Identity),
+                ("TRANSPOSE/x", "transpose", Identity),
+                ("DETUNE/x", "detune", Cents),
+                ("PHASE/x", "phase", Identity),
+                ("RATIO/x", "ratio", Ratio),
+                ("FINE/x", "fine", Identity),
+                ("FIXED/x", "fixed", Identity),
+                ("FIXEDFREQ/x", "fixed-freq", FixedFreq),
+                ("PULSEWIDTH/x", "pulse-width", Identity),
+                ("PWM/x", "pwm/depth", Identity),
+                ("PWMRATE/x", "pwm/rate", Exp20),
+                ("NOISETONE/x", "noise-tone", Identity),
+                ("POSITION/x", "wavetable/position", Identity),
+                ("VELOCITY/x", "adsr/velocity", Identity),
+                ("VELOCITYTIME/x", "adsr/velocity-time", Identity),
+                ("RATESCALING/x", "adsr/rate-scaling", Identity),
+            ] {
+                alias(widget(name), &[parameter(address, conversion)]);
+            }
+            // envelope depth, LFO depth and LFO rate
+            alias(
+                widget("POSITIONMOD/x"),
+                &[
+                    parameter("wavetable/envelope", Bipolar),
+                    parameter("wavetable/lfo", Identity),
+                    parameter("wavetable/lfo-rate", Exp20),
+                ],
+            );
+            for &(name, prefix) in &[("ADSR/x", "adsr"), ("POSITIONADSR/x", "wavetable/adsr")] {
+                alias(
+                    widget(name),
+                    &[
+                        parameter(&format!("{}/attack", prefix), Exp10),
+                        parameter(&format!("{}/decay", prefix), Exp20),
+                        parameter(&format!("{}/sustain", prefix), Sustain),
+                        parameter(&format!("{}/release", prefix), Exp20),
+                    ],
+                );
+            }
+            let levels = |name: &str| {
+                (1..MAX_OSC_CNT + 1)
+                    .map(|modulator| parameter(&format!("{}/{}", name, modulator), Identity))
+                    .collect::<Vec<_>>()
+            };
+            alias(format!("/OSCILLATORS/FM/OSC{}/LEVEL/x", osc), &levels("fm"));
+            alias(
+                format!("/OSCILLATORS/RINGMOD/OSC{}/LEVEL/x", osc),
+                &levels("ringmod"),
+            );
+            // the first entry disables the sync
+            alias(
+                format!("/OSCILLATORS/SYNC/OSC{}/SOURCE/selection", osc),
+                &[parameter("sync", Identity)],
+            );
+        }
+        alias(
+            "/OSCILLATORS/MIX/PAN/x".to_owned(),
+            &each_oscillator("pan", Identity),
+        );
+        alias(
+            "/OSCILLATORS/MIX/VOLUME/x".to_owned(),
+            &each_oscillator("volume", Volume),
+        );
+        for &(address, parameter, conversion) in &[
+            (
+                "/OSCILLATORS/FILTER/FILTERTYPE/selection",
+                "filter/type",
+                Identity,
+            ),
+            ("/OSCILLATORS/FILTER/FREQRES/x", "filter/freq", FilterFreq),
+            ("/OSCILLATORS/FILTER/FREQRES/y", "filter/q", FilterQ),
+            (
+                "/OSCILLATORS/QUALITY/INTERPOLATION/selection",
+                "quality/interpolation",
+                Identity,
+            ),
+            (
+                "/OSCILLATORS/FM/ALGORITHM/selection",
+                "fm/algorithm",
+                Identity,
+            ),
+            (
+                "/OSCILLATORS/FM/OPERATORS/selection",
+                "fm/operators",
+                Operators,
+            ),
+            (
+                "/VOICES/ALLOCATION/selection",
+                "voices/allocation",
+                Identity,
+            ),
+            (
+                "/VOICES/VELOCITYCURVE/selection",
+                "voices/velocity-curve",
+                Identity,
+            ),
+            (
+                "/VOICES/VELOCITYRANGE/x",
+                "voices/velocity-range",
+                VelocityRange,
+            ),
+            ("/ARPEGGIATOR/ON/x", "arpeggiator/on", Identity),
+            ("/ARPEGGIATOR/MODE/selection", "arpeggiator/mode", Identity),
+            ("/ARPEGGIATOR/OCTAVES/x", "arpeggiator/octaves", Identity),
+            ("/ARPEGGIATOR/RATE/selection", "arpeggiator/rate", Identity),
+            ("/ARPEGGIATOR/GATE/x", "arpeggiator/gate", Identity),
+            ("/ARPEGGIATOR/SWING/x", "arpeggiator/swing", Identity),
+            ("/ARPEGGIATOR/LATCH/x", "arpeggiator/latch", Identity),
+            // the internal tempo in beats per minute
+            ("/TRANSPORT/TEMPO/x", "transport/tempo", Identity),
+            ("/SEQUENCER/PATTERN/x", "sequencer/pattern", Identity),
+            ("/SEQUENCER/RATE/selection", "sequencer/rate", Identity),
+        ] {
+            alias(address.to_owned(), &[(parameter.to_owned(), conversion)]);
+        }
+    }
+    aliases
+}
+
+/// The state of the controller that is shared by the receiver and the sender, so that
+/// the feedback reaches the controller and matches its key grid and parameters.
+pub struct OscRemote {
+    addr: Option<SocketAddr>, // sender of the last received packet
+    transpose: u8,
+    note_grid: [f32; GRID_SIZE + TRANSPOSE_RANGE * 12],
+    registry: Registry,
+    aliases: Vec<Alias>,
+    alias_index: HashMap<String, usize>, // index of the alias of an address
+    subscribers: Vec<mpsc::Sender<usize>>, // receive the indices of changed parameters
+}
+impl OscRemote {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let aliases = lemur_aliases(&registry);
+        OscRemote {
+            addr: None,
+            transpose: 0u8,
+            note_grid: [0.0; GRID_SIZE + TRANSPOSE_RANGE * 12],
+            alias_index: aliases
+                .iter()
+                .enumerate()
+                .map(|(idx, alias)| (alias.address.clone(), idx))
+                .collect(),
+            aliases,
+            registry,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Returns the key of a pad of the key grid.
+    /// The key grid goes from left to right and bottom to up, i.e. the lowest
+    /// note is in the bottom left corner and the highest note in the top right.
+    fn grid_key(&self, idx: usize) -> u8 {
+        let rev_idx = 12 * ((idx / 12) + 1) - idx % 12;
+        (GRID_SIZE - rev_idx) as u8 + self.transpose
+    }
+
+    /// Returns the parameters and their current values.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    /// Returns a channel that receives the index of each parameter that is changed by
+    /// the feedback of the synth.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<usize> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Sets the parameters of the widget with the given address and returns the events
+    /// that apply them, or `None` if the address is not an alias.
+    fn set_alias(&mut self, address: &str, args: &[Float]) -> Option<Vec<ControlEvent>> {
+        let changes = match self.alias_index.get(address) {
+            Some(idx) => self.aliases[*idx]
+                .parameters
+                .iter()
+                .zip(args)
+                .map(|(&(parameter, conversion), arg)| {
+                    (parameter, conversion.parameter_value(*arg))
+                }).collect::<Vec<_>>(),
+            None => return None,
+        };
+        Some(self.registry.set(&changes))
+    }
+
+    /// Stores the values set by the event and returns the indices of the changed
+    /// parameters, which are also sent to the subscribers.
+    fn update(&mut self, event: &ControlEvent) -> Vec<usize> {
+        let changed = self.registry.update(event);
+        // subscribers that hung up are removed
+        self.subscribers
+            .retain(|tx| changed.iter().all(|idx| tx.send(*idx).is_ok()));
+        changed
+    }
+
+    /// Returns the messages that move the widgets to the values of the changed parameters.
+    fn alias_messages(&self, changed: &[usize]) -> Vec<OscMessage> {
+        let registry = &self.registry;
+        self.aliases
+            .iter()
+            .filter(|alias| {
+                alias
+                    .parameters
+                    .iter()
+                    .any(|&(parameter, _)| changed.contains(&parameter))
+            }).map(|alias| {
+                let args = alias
+                    .parameters
+                    .iter()
+                    .map(|&(parameter, conversion)| {
+                        conversion.widget_value(registry.value(parameter))
+                    }).collect::<Vec<_>>();
+                OscSender::message(alias.address.clone(), &args)
+            }).collect()
+    }
+}
 
 pub struct OscReceiver {
     socket: UdpSocket,
+    sample_rate: usize, // wavetables are band-limited for the playback sample rate
+    patch_dir: PathBuf,
     buf: [u8; rosc::decoder::MTU],
-    transpose: u8,
-    note_grid: [f32; GRID_SIZE + TRANSPOSE_RANGE * 12],
+    remote: Arc<Mutex<OscRemote>>,
+    spectra: HashMap<String, (Vec<Float>, Vec<Float>)>, // partial amplitudes and phases
+    spectrum_queue: SpectrumQueue,                      // renders the received spectra
+    replies: Vec<OscMessage>,                           // answers to the queries of a packet
+    feedback: mpsc::Sender<ControlEvent>,               // reports the loaded patches
 }
 impl OscReceiver {
-    pub fn new(addr: SocketAddr) -> Result<Self, RunError> {
+    /// The changes made by the controller are not reported back to it, only the
+    /// parameters of loaded patches are sent to the `feedback` of the `OscSender`.
+    /// Spectra are rendered by the `SpectrumRenderer` of the `spectrum_queue`.
+    pub fn new(
+        addr: SocketAddr,
+        sample_rate: usize,
+        patch_dir: PathBuf,
+        spectrum_queue: SpectrumQueue,
+        feedback: mpsc::Sender<ControlEvent>,
+    ) -> Result<Self, RunError> {
         let socket = try!(UdpSocket::bind(addr).map_err(RunError::IoError));
         Ok(OscReceiver {
             socket,
+            sample_rate,
+            patch_dir,
             buf: [0u8; rosc::decoder::MTU],
-            transpose: 0u8,
-            note_grid: [0.0; GRID_SIZE + TRANSPOSE_RANGE * 12],
+            remote: Arc::new(Mutex::new(OscRemote::new())),
+            spectra: HashMap::new(),
+            spectrum_queue,
+            replies: Vec::new(),
+            feedback,
         })
     }
+
+    /// Returns the state of the controller, which is needed by the `OscSender`.
+    pub fn remote(&self) -> Arc<Mutex<OscRemote>> {
+        self.remote.clone()
+    }
+
+    /// Queues the rendering of a spectrum, the audio thread only installs it.
+    fn render_spectrum(&self, event: &ControlEvent) {
+        if let ControlEvent::Spectrum {
+            ref id,
+            ref amplitudes,
+            ref phases,
+        } = *event
+        {
+            self.spectrum_queue.render(id, amplitudes, phases);
+        }
+    }
 }
 impl OscReceiver {
     fn receive(&mut self) -> Result<OscPacket, RunError> {
-        let (size, _) = try!(
+        let (size, addr) = try!(
             self.socket
                 .recv_from(&mut self.buf)
                 .map_err(RunError::IoError)
         );
+        self.remote.lock().unwrap().addr = Some(addr);
         rosc::decoder::decode(&self.buf[..size]).map_err(RunError::OscError)
     }
 
     fn as_control_event(&mut self, packet: OscPacket) -> Vec<ControlEvent> {
         let mut events = Vec::new();
         for msg in Self::unwrap_packet(packet) {
-            let parts = msg
-                .addr
-                .split('/')
-                .filter(|s| !s.is_empty())
-                .collect::<Vec<&str>>();
-            if parts.is_empty() {
-                continue;
+            self.handle_message(&msg, &mut events);
+        }
+        events
+    }
+
+    fn handle_message(&mut self, msg: &OscMessage, events: &mut Vec<ControlEvent>) {
+        let parts = msg
+            .addr
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>();
+        if parts.is_empty() {
+            return;
+        }
+        // the widgets of the Lemur patch that set parameters of the registry
+        let address = format!("/{}", parts.join("/"));
+        let alias_events = self
+            .remote
+            .lock()
+            .unwrap()
+            .set_alias(&address, &Self::number_args(msg));
+        if let Some(alias_events) = alias_events {
+            events.extend(alias_events);
+            return;
+        }
+        match parts[0] {
+            "ytterbium" => self.handle_parameter(msg, &address, events),
+            "KEYS" => self.handle_keys(msg, &parts, events),
+            "OSCILLATORS" => self.handle_oscillators(msg, &parts[1..], events),
+            "VOICES" => self.handle_voices(msg, &parts, events),
+            "SEQUENCER" => self.handle_sequencer(msg, &parts, events),
+            "TRANSPORT" => Self::handle_transport(msg, &parts, events),
+            "PATCH" => self.handle_patch(msg, &parts, events),
+            "TUNING" => Self::handle_tuning(msg, &parts, events),
+            "WAVETABLE" => self.handle_wavetable(msg, &parts, events),
+            _ => println!("unmapped message: {:?}", msg),
+        }
+    }
+
+    /// Returns all numeric arguments of the message as floats.
+    fn number_args(msg: &OscMessage) -> Vec<Float> {
+        msg.args
+            .as_ref()
+            .map(|args| {
+                args.iter()
+                    .filter_map(|arg| match *arg {
+                        OscType::Float(val) => Some(Float::from(val)),
+                        OscType::Double(val) => Some(val),
+                        OscType::Int(val) => Some(Float::from(val)),
+                        OscType::Long(val) => Some(val as Float),
+                        _ => None,
+                    }).collect()
+            }).unwrap_or_default()
+    }
+
+    /// Returns all string arguments of the message.
+    fn string_args(msg: &OscMessage) -> Vec<String> {
+        msg.args
+            .as_ref()
+            .map(|args| {
+                args.iter()
+                    .filter_map(|arg| match *arg {
+                        OscType::String(ref val) => Some(val.clone()),
+                        _ => None,
+                    }).collect()
+            }).unwrap_or_default()
+    }
+
+    /// Handles the canonical addresses of the parameters. A number sets the parameter
+    /// and a message without arguments queries its value, the reply has the address
+    /// and value of the parameter.
+    /// `/ytterbium/query [<address prefix>...]` replies with
+    /// `/ytterbium/info <address> <value> <min> <max> <default> <unit> <scaling> <description>`
+    /// for each parameter.
+    fn handle_parameter(
+        &mut self,
+        msg: &OscMessage,
+        address: &str,
+        events: &mut Vec<ControlEvent>,
+    ) {
+        let mut remote = self.remote.lock().unwrap();
+        if address == QUERY_ADDRESS {
+            let prefixes = Self::string_args(msg);
+            let registry = remote.registry();
+            for (idx, parameter) in registry.parameters().iter().enumerate() {
+                if !prefixes.is_empty()
+                    && !prefixes
+                        .iter()
+                        .any(|prefix| parameter.address.starts_with(prefix.as_str()))
+                {
+                    continue;
+                }
+                self.replies.push(OscMessage {
+                    addr: INFO_ADDRESS.to_owned(),
+                    args: Some(vec![
+                        OscType::String(parameter.address.clone()),
+                        OscType::Float(registry.value(idx) as f32),
+                        OscType::Float(parameter.range.0 as f32),
+                        OscType::Float(parameter.range.1 as f32),
+                        OscType::Float(parameter.default as f32),
+                        OscType::String(parameter.unit.to_owned()),
+                        OscType::String(parameter.scaling.name().to_owned()),
+                        OscType::String(parameter.description.to_owned()),
+                    ]),
+                });
             }
-            match parts[0] {
-                "KEYS" => self.handle_keys(&msg, &parts, &mut events),
-                "OSCILLATORS" => self.handle_oscillators(&msg, &parts[1..], &mut events),
-                _ => println!("unmapped message: {:?}", msg),
+            return;
+        }
+        let idx = match remote.registry().find(address) {
+            Some(idx) => idx,
+            None => {
+                println!("unknown parameter: {}", address);
+                return;
             }
+        };
+        match Self::number_args(msg).first() {
+            Some(value) => events.extend(remote.registry.set(&[(idx, *value)])),
+            None => self.replies.push(OscSender::message(
+                address.to_owned(),
+                &[remote.registry().value(idx)],
+            )),
+        }
+    }
+
+    fn handle_sequencer(
+        &mut self,
+        msg: &OscMessage,
+        address: &[&str],
+        events: &mut Vec<ControlEvent>,
+    ) {
+        if address.len() < 2 {
+            return;
+        }
+        let args = Self::number_args(msg);
+        match (address[1], address.get(2).cloned(), args.len()) {
+            // <pattern> <length>
+            ("LENGTH", None, 2) => events.push(ControlEvent::SequencerPattern {
+                pattern: args[0] as usize,
+                length: Some(args[1] as usize),
+                next: None,
+            }),
+            // <pattern> <next pattern>, a negative value removes the chained pattern
+            ("CHAIN", None, 2) => events.push(ControlEvent::SequencerPattern {
+                pattern: args[0] as usize,
+                length: None,
+                next: Some(if args[1] < 0.0 {
+                    None
+                } else {
+                    Some(args[1] as usize)
+                }),
+            }),
+            // <pattern> <step> <key> <velocity> <gate> <tie> <probability>, a negative key is a rest
+            ("STEP", None, 7) => events.push(ControlEvent::SequencerStep {
+                pattern: args[0] as usize,
+                step: args[1] as usize,
+                note: if args[2] < 0.0 {
+                    None
+                } else {
+                    Some(args[2] as u8)
+                },
+                velocity: args[3],
+                gate: args[4],
+                tie: args[5] > 0.5,
+                probability: args[6],
+            }),
+            // <pattern> <step>
+            ("UNLOCK", None, 2) => events.push(ControlEvent::ParameterLock {
+                pattern: args[0] as usize,
+                step: args[1] as usize,
+                lock: None,
+            }),
+            // /SEQUENCER/LOCK/<pattern>/<step>/<parameter address>
+            ("LOCK", Some(pattern), _) if address.len() > 4 && address[4] != "KEYS" => {
+                let (pattern, step) = match (pattern.parse::<usize>(), address[3].parse::<usize>()) {
+                    (Ok(pattern), Ok(step)) => (pattern, step),
+                    _ => return,
+                };
+                let parameter_msg = OscMessage {
+                    addr: address[4..].join("/"),
+                    args: msg.args.clone(),
+                };
+                // the locks don't change the values of the parameters
+                let values = self.remote.lock().unwrap().registry.values();
+                let mut locks = Vec::new();
+                self.handle_message(&parameter_msg, &mut locks);
+                self.remote.lock().unwrap().registry.restore(values);
+                // spectra are rendered when they are received, so they can't be locked
+                let lockable = |lock: &ControlEvent| match *lock {
+                    ControlEvent::Spectrum { .. } => false,
+                    _ => lock.parameter().is_some(),
+                };
+                for lock in locks.into_iter().filter(lockable) {
+                    events.push(ControlEvent::ParameterLock {
+                        pattern,
+                        step,
+                        lock: Some(Box::new(lock)),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_transport(msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
+        if address.len() < 3 {
+            return;
+        }
+        let args = Self::number_args(msg);
+        match (address[1], address[2], args.len()) {
+            ("PLAY", "x", 1) => events.push(ControlEvent::Transport(if args[0] > 0.5 {
+                Transport::Start
+            } else {
+                Transport::Stop
+            })),
+            ("CONTINUE", "x", 1) if args[0] > 0.5 => {
+                events.push(ControlEvent::Transport(Transport::Continue))
+            }
+            _ => {}
+        }
+    }
+
+    /// Builds the tuning table here, so that the audio thread only has to swap it.
+    fn handle_tuning(msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
+        if address.len() < 2 {
+            return;
+        }
+        let paths = Self::string_args(msg);
+        match address[1] {
+            // <scl file> [<kbm file>]
+            "LOAD" if !paths.is_empty() => match load_tuning(&paths[0], paths.get(1)) {
+                Ok(table) => events.push(ControlEvent::Tuning(Arc::new(table))),
+                Err(err) => println!("Could not load tuning {:?}: {:?}", paths, err),
+            },
+            "RESET" => events.push(ControlEvent::Tuning(
+                PitchConvert::default().table().clone(),
+            )),
+            _ => {}
+        }
+    }
+
+    /// Band-limits the wavetable here, so that the audio thread only has to register it.
+    fn handle_wavetable(&self, msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
+        if address.len() < 2 {
+            return;
+        }
+        let args = Self::string_args(msg);
+        if args.is_empty() {
+            return;
+        }
+        let path = Path::new(&args[0]);
+        // the name defaults to the file name
+        let name = match args.get(1) {
+            Some(name) => name.clone(),
+            None => match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().into_owned(),
+                None => return,
+            },
+        };
+        let frames = match address[1] {
+            // <wav file> [<name>]
+            "LOAD" => load_wavetable(path, LOWEST_FREQ, self.sample_rate),
+            // <recording> [<name>] [<output wav file>] [<frame count>]
+            "RESYNTHESIZE" => {
+                let frame_cnt = Self::number_args(msg)
+                    .first()
+                    .map_or(RESYNTHESIS_FRAMES, |frames| *frames as usize);
+                let output = args.get(2).map(Path::new);
+                resynthesize_wavetable(path, frame_cnt, output, LOWEST_FREQ, self.sample_rate)
+            }
+            _ => return,
+        };
+        match frames {
+            Ok(frames) => events.push(ControlEvent::AddWavetable {
+                name,
+                frames: Arc::new(frames),
+            }),
+            Err(err) => println!("Could not load wavetable {:?}: {:?}", path, err),
+        }
+    }
+
+    /// Reads the patch here, so that the audio thread doesn't wait for the file.
+    fn handle_patch(&self, msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
+        if address.len() < 2 {
+            return;
+        }
+        let name = match msg.args.as_ref().and_then(|args| args.first()) {
+            Some(&OscType::String(ref name)) => name.clone(),
+            _ => return,
+        };
+        match address[1] {
+            "SAVE" => events.push(ControlEvent::SavePatch(name)),
+            "LOAD" => {
+                let path = Patch::path(&self.patch_dir, &name);
+                match Patch::load(&path) {
+                    Ok(patch) => events.push(ControlEvent::LoadPatch {
+                        name,
+                        patch: Arc::new(patch),
+                    }),
+                    Err(err) => println!("Could not load patch {:?}: {:?}", path, err),
+                }
+            }
+            _ => {}
         }
-        events
     }
 
     fn handle_keys(&mut self, msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
@@ -77,22 +727,19 @@ impl OscReceiver {
                 if msg.args.is_none() {
                     return;
                 }
+                let mut remote = self.remote.lock().unwrap();
                 for (idx, key) in msg.args.as_ref().unwrap().into_iter().enumerate() {
                     if let OscType::Float(velocity) = *key {
-                        // The key grid goes from left to right and bottom to up, i.e. the lowest
-                        // note is in the bottom left corner and the highest note in the top right.
-
-                        let rev_idx = 12 * ((idx / 12) + 1) - idx % 12;
-                        let transposed_key = (GRID_SIZE - rev_idx) as u8 + self.transpose;
+                        let transposed_key = remote.grid_key(idx);
                         // Determine if a `NoteOn` or `NoteOff` event was received by
                         // subtracting the last velocity from the received one.
                         // A negative difference determines a `NoteOff` and a positive
                         // one obviously a `NoteOn`.
-                        let old_velocity = self.note_grid[transposed_key as usize];
+                        let old_velocity = remote.note_grid[transposed_key as usize];
                         if feq!(velocity, old_velocity) {
                             continue;
                         }
-                        self.note_grid[transposed_key as usize] = velocity;
+                        remote.note_grid[transposed_key as usize] = velocity;
                         if velocity > old_velocity {
                             events.push(ControlEvent::NoteOn {
                                 key: transposed_key,
@@ -113,13 +760,26 @@ impl OscReceiver {
                 // updating the `transpose` value which is added to every received key.
                 // TODO: This could also be done completly by the Lemur App.
                 if let OscType::Float(scale) = args[0] {
-                    self.transpose = (scale * 6.0) as u8 * 12;
+                    self.remote.lock().unwrap().transpose = (scale * 6.0) as u8 * 12;
                 }
             }
             _ => {}
         }
     }
 
+    fn handle_voices(&mut self, msg: &OscMessage, address: &[&str], events: &mut Vec<ControlEvent>) {
+        if address.len() < 3 {
+            return;
+        }
+        // a multislider with the positions in the dynamic range
+        if let ("VELOCITYTABLE", "x") = (address[1], address[2]) {
+            events.push(ControlEvent::VelocityResponse {
+                curve: Some(VelocityCurve::Table(Self::number_args(msg))),
+                range: None,
+            });
+        }
+    }
+
     fn handle_oscillators(
         &mut self,
         msg: &OscMessage,
@@ -129,179 +789,93 @@ impl OscReceiver {
         if address.len() < 3 {
             return;
         }
-        // TODO: refactor this mess
-        match address[0] {
-            "FILTER" => match (address[1], address[2]) {
-                ("FILTERTYPE", "selection") => {
-                    let args = msg.args.as_ref().unwrap();
-                    if let OscType::Float(selection) = args[0] {
-                        let filter_type = match selection as usize {
-                            0 => Some(FilterType::LP),
-                            1 => Some(FilterType::HP),
-                            2 => Some(FilterType::BP),
-                            3 => Some(FilterType::Notch),
-                            _ => None,
-                        };
-                        events.push(ControlEvent::Filter {
-                            filter_type,
-                            freq: None,
-                            q: None,
-                        })
-                    }
-                }
-                ("FREQRES", _) => match address[2] {
-                    "x" => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(freq) = args[0] {
-                            events.push(ControlEvent::Filter {
-                                filter_type: None,
-                                freq: Some(40. + 20_000.0 * Float::from(freq)),
-                                q: None,
-                            });
-                        }
-                    }
-                    "y" => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(q) = args[0] {
-                            events.push(ControlEvent::Filter {
-                                filter_type: None,
-                                freq: None,
-                                q: Some(4. * Float::from(q)),
-                            });
-                        }
-                    }
-                    _ => {}
-                },
-                _ => {}
-            },
-            "MIX" => match (address[1], address[2]) {
-                ("PAN", "x") => {
-                    let args = msg
-                        .args
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .map(|arg| match *arg {
-                            OscType::Float(val) => Float::from(val),
-                            _ => 0.0,
-                        }).collect::<Vec<_>>();
-                    events.push(ControlEvent::Pan(args));
-                }
-
-                ("VOLUME", "x") => {
-                    let args = msg
-                        .args
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .map(|arg| match *arg {
-                            OscType::Float(val) => (1.0 - Float::from(val)) * -61.0,
-                            _ => 0.0,
-                        }).collect::<Vec<_>>();
-                    events.push(ControlEvent::Volume(args));
+        match (address[1], address[2]) {
+            // <breakpoint key> <left dB> <left curve> <right dB> <right curve>,
+            // curve 0 is linear and 1 is exponential
+            ("KEYSCALING", "x") => {
+                let args = Self::number_args(msg);
+                if args.len() != 5 {
+                    return;
                 }
-                _ => {}
-            },
-            "FM" => match address[1] {
-                "OSC1" | "OSC2" | "OSC3" | "OSC4" => {
-                    if let ("LEVEL", "x") = (address[2], address[3]) {
-                        let args = msg
-                            .args
-                            .as_ref()
-                            .unwrap()
-                            .iter()
-                            .map(|arg| match *arg {
-                                OscType::Float(val) => Float::from(val),
-                                _ => 0.0,
-                            }).collect::<Vec<Float>>();
-                        events.push(ControlEvent::FM {
-                            id: address[1].to_owned(),
-                            levels: args,
-                        });
+                let curve = |depth: Float, curve: Float| {
+                    if curve >= 1.0 {
+                        KeyScalingCurve::Exponential(depth)
+                    } else {
+                        KeyScalingCurve::Linear(depth)
                     }
+                };
+                events.push(ControlEvent::KeyScaling {
+                    id: address[0].to_owned(),
+                    breakpoint: Some(args[0].max(0.0).min(127.0) as u8),
+                    left: Some(curve(args[1], args[2])),
+                    right: Some(curve(args[3], args[4])),
+                });
+            }
+            // <sustain> <loop start> <loop end> followed by <time> <level> <curve> of
+            // each breakpoint, negative points are unset
+            ("SEGMENTS", "x") | ("POSITIONSEGMENTS", "x") => {
+                let args = Self::number_args(msg);
+                if args.len() < 3 || args.len() % 3 != 0 {
+                    return;
                 }
-                _ => {}
-            },
-            _ => {
-                match (address[1], address[2]) {
-                    ("ADSR", "x") => {
-                        let args = msg
-                            .args
-                            .as_ref()
-                            .unwrap()
-                            .iter()
-                            .map(|arg| {
-                                match *arg {
-                                    // TODO: Choose a quadratic scale?
-                                    OscType::Float(val) => exp_scale!(val),
-                                    _ => 1.0E-4,
-                                }
-                            }).collect::<Vec<Float>>();
-                        let id_prefix: String = "ADSR-".to_owned();
-                        events.push(ControlEvent::ADSR {
-                            id: id_prefix + address[0],
-                            attack: 10.0 * args[0] as Time,
-                            decay: 20.0 * args[1] as Time,
-                            sustain: Float::from_db((1.0 - args[2]) * -40.0),
-                            release: 20.0 * args[3] as Time,
-                        });
-                    }
-                    ("PHASE", "x") => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(phase) = args[0] {
-                            events.push(ControlEvent::Phase {
-                                id: address[0].to_owned(),
-                                phase: Float::from(phase),
-                            });
-                        }
-                    }
-                    ("TRANSPOSE", "x") => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(transpose) = args[0] {
-                            events.push(ControlEvent::Transpose {
-                                id: address[0].to_owned(),
-                                transpose: transpose as i32,
-                            });
-                        }
-                    }
-                    ("DETUNE", "x") => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(detune) = args[0] {
-                            events.push(ControlEvent::Detune {
-                                id: address[0].to_owned(),
-                                detune: (detune * 100.0) as i32,
-                            });
-                        }
-                    }
-                    ("WAVEFORM", "selection") => {
-                        let args = msg.args.as_ref().unwrap();
-                        if let OscType::Float(selection) = args[0] {
-                            if let Some(waveform) = match selection as usize {
-                                0 => Some(Waveform::Sine),
-                                1 => Some(Waveform::Saw),
-                                2 => Some(Waveform::Square),
-                                3 => Some(Waveform::Tri),
-                                4 => Some(Waveform::SharpTri),
-                                5 => Some(Waveform::Random),
-                                _ => None,
-                            } {
-                                events.push(ControlEvent::Waveform {
-                                    id: address[0].to_owned(),
-                                    waveform,
-                                })
-                            }
-                        }
+                let point = |arg: Float| if arg < 0.0 { None } else { Some(arg as usize) };
+                let breakpoints = args[3..]
+                    .chunks(3)
+                    .map(|breakpoint| Breakpoint {
+                        time: breakpoint[0] as Time,
+                        level: breakpoint[1],
+                        curve: envelope_curve(breakpoint[2]),
+                    }).collect();
+                let id_prefix = if address[1] == "SEGMENTS" {
+                    "ADSR-"
+                } else {
+                    "ADSR-POSITION-"
+                };
+                events.push(ControlEvent::SegmentEnvelope {
+                    id: id_prefix.to_owned() + address[0],
+                    breakpoints,
+                    sustain: point(args[0]),
+                    loop_points: point(args[1])
+                        .and_then(|start| point(args[2]).map(|end| (start, end))),
+                });
+            }
+            // a multislider with the amplitudes or phases of the partials
+            ("PARTIALS", "x") | ("PARTIALPHASES", "x") => {
+                let args = Self::number_args(msg);
+                let (amplitudes, phases) = {
+                    let spectrum = self
+                        .spectra
+                        .entry(address[0].to_owned())
+                        .or_insert_with(|| (Vec::new(), Vec::new()));
+                    if address[1] == "PARTIALS" {
+                        spectrum.0 = args;
+                    } else {
+                        spectrum.1 = args;
                     }
-                    _ => {}
+                    spectrum.clone()
+                };
+                events.push(ControlEvent::Spectrum {
+                    id: address[0].to_owned(),
+                    amplitudes,
+                    phases,
+                });
+            }
+            ("WAVETABLE", "name") => {
+                if let Some(name) = Self::string_args(msg).pop() {
+                    events.push(ControlEvent::UserWaveform {
+                        id: address[0].to_owned(),
+                        name,
+                    })
                 }
             }
+            _ => {}
         }
     }
 
     fn unwrap_packet(packet: OscPacket) -> Vec<OscMessage> {
         let mut messages = Vec::new();
-        if let OscPacket::Bundle(bundle) = packet {
-            if !bundle.content.is_empty() {
+        match packet {
+            OscPacket::Bundle(bundle) => {
                 for packet in bundle.content {
                     if let OscPacket::Message(msg) = packet {
                         messages.push(msg);
@@ -310,21 +884,68 @@ impl OscReceiver {
                     }
                 }
             }
+            // clients of the canonical namespace usually send single messages
+            OscPacket::Message(msg) => messages.push(msg),
         }
         messages
     }
+
+    /// Sends the replies to the queries to the sender of the last packet.
+    fn send_replies(&mut self) {
+        let addr = match self.remote.lock().unwrap().addr {
+            Some(addr) => addr,
+            None => return,
+        };
+        for reply in self.replies.drain(..) {
+            match rosc::encoder::encode(&OscPacket::Message(reply)) {
+                Ok(data) => {
+                    if let Err(err) = self.socket.send_to(&data, addr) {
+                        println!("Could not send osc reply: {:?}", err);
+                    }
+                }
+                Err(err) => println!("Could not encode osc reply: {:?}", err),
+            }
+        }
+    }
+}
+/// Converts a curve in the range `[-1.0, 1.0]` to an envelope curve, negative values
+/// are logarithmic and positive values exponential.
+fn envelope_curve(curve: Float) -> EnvelopeCurve {
+    if curve > 0.0 {
+        EnvelopeCurve::Exponential(curve)
+    } else if curve < 0.0 {
+        EnvelopeCurve::Logarithmic(-curve)
+    } else {
+        EnvelopeCurve::Linear
+    }
 }
+
 impl Receiver for OscReceiver {
     fn receive_and_send(&mut self, tx: mpsc::Sender<ControlEvent>) {
         loop {
             match self.receive() {
                 Ok(packet) => {
                     let events = self.as_control_event(packet);
+                    self.send_replies();
                     for event in events {
                         match event {
                             ControlEvent::Unsupported => continue,
-                            _ => tx.send(event).unwrap(),
+                            ControlEvent::LoadPatch { ref patch, .. } => {
+                                // the synth keeps playing without a controller
+                                self.feedback.send(event.clone()).ok();
+                                for parameter in patch.parameters() {
+                                    self.feedback.send(parameter.clone()).ok();
+                                    self.render_spectrum(parameter);
+                                }
+                            }
+                            // the controller already shows its changes, the other
+                            // clients of the registry are notified
+                            _ => {
+                                self.remote.lock().unwrap().update(&event);
+                                self.render_spectrum(&event);
+                            }
                         }
+                        tx.send(event).unwrap();
                     }
                 }
                 Err(RunError::OscError(err)) => println!("Could not decode osc packet: {:?}", err),
@@ -333,3 +954,366 @@ impl Receiver for OscReceiver {
         }
     }
 }
+
+/// Inverts `exp_scale!`.
+fn exp_unscale(val: Float) -> Float {
+    (10.0 * val + 1.0).max(1.0).log10()
+}
+
+/// Reports the parameter changes, the active notes and loaded patches to the controller,
+/// using the addresses and ranges of the `OscReceiver`. The feedback is sent to the
+/// configured address or to the out port of the sender of the last received packet.
+pub struct OscSender {
+    socket: UdpSocket,
+    addr: Option<SocketAddr>,
+    port: u16,
+    remote: Arc<Mutex<OscRemote>>,
+}
+impl OscSender {
+    pub fn new(
+        addr: Option<SocketAddr>,
+        port: u16,
+        remote: Arc<Mutex<OscRemote>>,
+    ) -> Result<Self, RunError> {
+        let local_addr = match addr {
+            Some(SocketAddr::V6(_)) => "[::]:0",
+            _ => "0.0.0.0:0",
+        };
+        let socket = try!(UdpSocket::bind(local_addr).map_err(RunError::IoError));
+        Ok(OscSender {
+            socket,
+            addr,
+            port,
+            remote,
+        })
+    }
+
+    /// Returns whether the controller shows the changes of the event, which are the
+    /// parameters, the notes, the transport and the loaded patches.
+    pub fn reports(event: &ControlEvent) -> bool {
+        match *event {
+            ControlEvent::NoteOn { .. }
+            | ControlEvent::NoteOff { .. }
+            | ControlEvent::Transport(_)
+            | ControlEvent::Sequencer { .. }
+            | ControlEvent::LoadPatch { .. } => true,
+            _ => event.parameter().is_some(),
+        }
+    }
+
+    /// Sends the feedback of the received events until the channel is closed.
+    pub fn send_events(&mut self, rx: mpsc::Receiver<ControlEvent>) {
+        for event in rx.iter() {
+            let target = self.addr.or_else(|| {
+                self.remote.lock().unwrap().addr.map(|mut addr| {
+                    addr.set_port(self.port);
+                    addr
+                })
+            });
+            let target = match target {
+                Some(target) => target,
+                // the notes are tracked even if no controller is known yet
+                None => {
+                    self.as_osc_messages(&event);
+                    continue;
+                }
+            };
+            for msg in self.as_osc_messages(&event) {
+                match rosc::encoder::encode(&OscPacket::Message(msg)) {
+                    Ok(data) => {
+                        if let Err(err) = self.socket.send_to(&data, target) {
+                            println!("Could not send osc feedback: {:?}", err);
+                        }
+                    }
+                    Err(err) => println!("Could not encode osc feedback: {:?}", err),
+                }
+            }
+        }
+    }
+
+    fn message(addr: String, args: &[Float]) -> OscMessage {
+        OscMessage {
+            addr,
+            args: Some(args.iter().map(|arg| OscType::Float(*arg as f32)).collect()),
+        }
+    }
+
+    /// Returns the messages that move the controls of the controller to the values
+    /// set by the event.
+    fn as_osc_messages(&self, event: &ControlEvent) -> Vec<OscMessage> {
+        // the names are the only string arguments
+        match *event {
+            ControlEvent::UserWaveform { ref id, ref name } => {
+                return vec![OscMessage {
+                    addr: format!("/OSCILLATORS/{}/WAVETABLE/name", id),
+                    args: Some(vec![OscType::String(name.clone())]),
+                }]
+            }
+            ControlEvent::LoadPatch { ref name, .. } => {
+                return vec![OscMessage {
+                    addr: "/PATCH/LOAD".to_owned(),
+                    args: Some(vec![OscType::String(name.clone())]),
+                }]
+            }
+            _ => {}
+        }
+        let mut remote = self.remote.lock().unwrap();
+        let changed = remote.update(event);
+        let mut messages = remote.alias_messages(&changed);
+        {
+            let mut push = |addr: String, args: &[Float]| messages.push(Self::message(addr, args));
+            match *event {
+                ControlEvent::NoteOn { key, velocity }
+                | ControlEvent::NoteOff { key, velocity } => {
+                    let velocity = match *event {
+                        ControlEvent::NoteOn { .. } => velocity,
+                        _ => 0.0,
+                    };
+                    match remote.note_grid.get_mut(key as usize) {
+                        Some(pad) => *pad = velocity as f32,
+                        None => return Vec::new(),
+                    }
+                    let grid = (0..GRID_SIZE)
+                        .map(|idx| Float::from(remote.note_grid[remote.grid_key(idx) as usize]))
+                        .collect::<Vec<_>>();
+                    push("/KEYS/GRID/x".to_owned(), &grid);
+                }
+                ControlEvent::KeyScaling {
+                    ref id,
+                    breakpoint: Some(breakpoint),
+                    left: Some(left),
+                    right: Some(right),
+                } => {
+                    let curve = |curve: KeyScalingCurve| match curve {
+                        KeyScalingCurve::Linear(depth) => (depth, 0.0),
+                        KeyScalingCurve::Exponential(depth) => (depth, 1.0),
+                    };
+                    let (left, right) = (curve(left), curve(right));
+                    push(
+                        format!("/OSCILLATORS/{}/KEYSCALING/x", id),
+                        &[Float::from(breakpoint), left.0, left.1, right.0, right.1],
+                    );
+                }
+                ControlEvent::SegmentEnvelope {
+                    ref id,
+                    ref breakpoints,
+                    sustain,
+                    loop_points,
+                } => {
+                    let (osc, widget) = match envelope_oscillator(id) {
+                        Some((osc, false)) => (osc, "SEGMENTS"),
+                        Some((osc, true)) => (osc, "POSITIONSEGMENTS"),
+                        None => return Vec::new(),
+                    };
+                    let point = |point: Option<usize>| point.map_or(-1.0, |point| point as Float);
+                    let mut args = vec![
+                        point(sustain),
+                        point(loop_points.map(|(start, _)| start)),
+                        point(loop_points.map(|(_, end)| end)),
+                    ];
+                    for breakpoint in breakpoints {
+                        let curve = match breakpoint.curve {
+                            EnvelopeCurve::Linear => 0.0,
+                            EnvelopeCurve::Exponential(curve) => curve,
+                            EnvelopeCurve::Logarithmic(curve) => -curve,
+                        };
+                        args.extend_from_slice(&[
+                            Float::from(breakpoint.time),
+                            breakpoint.level,
+                            curve,
+                        ]);
+                    }
+                    push(format!("/OSCILLATORS/{}/{}/x", osc, widget), &args);
+                }
+                ControlEvent::Spectrum {
+                    ref id,
+                    ref amplitudes,
+                    ref phases,
+                } => {
+                    push(format!("/OSCILLATORS/{}/PARTIALS/x", id), amplitudes);
+                    push(format!("/OSCILLATORS/{}/PARTIALPHASES/x", id), phases);
+                }
+                ControlEvent::VelocityResponse {
+                    curve: Some(VelocityCurve::Table(ref table)),
+                    ..
+                } => push("/VOICES/VELOCITYTABLE/x".to_owned(), table),
+                ControlEvent::Transport(Transport::Start) => {
+                    push("/TRANSPORT/PLAY/x".to_owned(), &[1.0])
+                }
+                ControlEvent::Transport(Transport::Stop) => {
+                    push("/TRANSPORT/PLAY/x".to_owned(), &[0.0])
+                }
+                _ => {}
+            }
+        }
+        messages
+    }
+}
+
+/// Returns the oscillator of an envelope id and whether it is the position envelope.
+fn envelope_oscillator(id: &str) -> Option<(&str, bool)> {
+    let mut parts = id.split('-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("ADSR"), Some("POSITION"), Some(osc)) => Some((osc, true)),
+        (Some("ADSR"), Some(osc), None) => Some((osc, false)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_osc_feedback() {
+    use dsp::{ArpeggiatorMode, Waveform};
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let mut receiver = OscReceiver::new(
+        addr,
+        48_000,
+        ::std::env::temp_dir(),
+        ::dsp::SpectrumRenderer::new(LOWEST_FREQ, 48_000).queue(),
+        mpsc::channel().0,
+    ).unwrap();
+    let sender = OscSender::new(Some(addr), 0, receiver.remote()).unwrap();
+    // the feedback is received like a message of the controller
+    let mut round_trip = |event: ControlEvent| {
+        let mut events = Vec::new();
+        for msg in sender.as_osc_messages(&event) {
+            receiver.handle_message(&msg, &mut events);
+        }
+        events
+    };
+
+    match round_trip(ControlEvent::ADSR {
+        id: "ADSR-POSITION-OSC2".to_owned(),
+        attack: 0.5,
+        decay: 2.0,
+        sustain: 0.5,
+        release: 4.0,
+    })[..]
+    {
+        [ControlEvent::ADSR {
+            ref id,
+            attack,
+            decay,
+            sustain,
+            release,
+        }] => {
+            assert_eq!(id, "ADSR-POSITION-OSC2");
+            assert_relative_eq!(attack, 0.5, max_relative = 1e-5);
+            assert_relative_eq!(decay, 2.0, max_relative = 1e-5);
+            assert_relative_eq!(sustain, 0.5, max_relative = 1e-5);
+            assert_relative_eq!(release, 4.0, max_relative = 1e-5);
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    match round_trip(ControlEvent::Waveform {
+        id: "OSC1".to_owned(),
+        waveform: Waveform::Pulse,
+    })[..]
+    {
+        [ControlEvent::Waveform { ref id, waveform }] => {
+            assert_eq!((id.as_str(), waveform), ("OSC1", Waveform::Pulse))
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    match round_trip(ControlEvent::Sync {
+        id: "OSC3".to_owned(),
+        source: Some("OSC1".to_owned()),
+    })[..]
+    {
+        [ControlEvent::Sync { ref source, .. }] => assert_eq!(*source, Some("OSC1".to_owned())),
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    match round_trip(ControlEvent::Arpeggiator {
+        enabled: None,
+        mode: Some(ArpeggiatorMode::UpDown),
+        octaves: None,
+        division: Some(1.0 / 6.0),
+        gate: None,
+        swing: None,
+        latch: None,
+    })[..]
+    {
+        [ControlEvent::Arpeggiator { mode, .. }, ControlEvent::Arpeggiator { division, .. }] => {
+            assert_eq!(mode, Some(ArpeggiatorMode::UpDown));
+            assert_eq!(division, Some(1.0 / 6.0));
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+
+    // the key grid shows the played notes without triggering them again
+    let grid = sender.as_osc_messages(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    assert_eq!(grid[0].addr, "/KEYS/GRID/x");
+    let pads = OscReceiver::number_args(&grid[0]);
+    assert_eq!(pads.iter().filter(|velocity| **velocity > 0.0).count(), 1);
+    assert!(round_trip(ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    }).is_empty());
+    assert!(round_trip(ControlEvent::NoteOff {
+        key: 60,
+        velocity: 0.0,
+    }).is_empty());
+}
+
+#[test]
+fn test_osc_parameters() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let mut receiver = OscReceiver::new(
+        addr,
+        48_000,
+        ::std::env::temp_dir(),
+        ::dsp::SpectrumRenderer::new(LOWEST_FREQ, 48_000).queue(),
+        mpsc::channel().0,
+    ).unwrap();
+    let mut handle = |addr: &str, args: Vec<OscType>| {
+        let mut events = Vec::new();
+        let msg = OscMessage {
+            addr: addr.to_owned(),
+            args: if args.is_empty() { None } else { Some(args) },
+        };
+        receiver.handle_message(&msg, &mut events);
+        events
+    };
+
+    // the canonical address and the Lemur widget set the same parameter
+    match handle("/ytterbium/osc/3/detune", vec![OscType::Float(-25.0)])[..] {
+        [ControlEvent::Detune { ref id, detune }] => {
+            assert_eq!((id.as_str(), detune), ("OSC3", -25))
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    match handle("/OSCILLATORS/OSC3/DETUNE/x", vec![OscType::Float(0.5)])[..] {
+        [ControlEvent::Detune { ref id, detune }] => {
+            assert_eq!((id.as_str(), detune), ("OSC3", 50))
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    // values are clamped to the range
+    match handle("/ytterbium/filter/freq", vec![OscType::Int(100_000)])[..] {
+        [ControlEvent::Filter { freq, .. }] => assert_eq!(freq, Some(20_040.0)),
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    assert!(handle("/ytterbium/osc/9/detune", vec![OscType::Float(1.0)]).is_empty());
+
+    // queries are answered with the current value and the description
+    assert!(handle("/ytterbium/osc/3/detune", Vec::new()).is_empty());
+    let prefix = OscType::String("/ytterbium/filter".to_owned());
+    assert!(handle("/ytterbium/query", vec![prefix]).is_empty());
+    let replies = receiver.replies.drain(..).collect::<Vec<_>>();
+    assert_eq!(replies.len(), 4);
+    assert_eq!(replies[0].addr, "/ytterbium/osc/3/detune");
+    assert_eq!(OscReceiver::number_args(&replies[0]), vec![50.0]);
+    assert!(replies[1..].iter().all(|reply| reply.addr == INFO_ADDRESS));
+    let strings = OscReceiver::string_args(&replies[2]);
+    assert_eq!(
+        strings[..3],
+        ["/ytterbium/filter/freq", "Hz", "exponential"]
+    );
+    assert_eq!(
+        OscReceiver::number_args(&replies[2]),
+        vec![20_040.0, 40.0, 20_040.0, 20_040.0]
+    );
+}
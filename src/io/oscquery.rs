@@ -0,0 +1,643 @@
+extern crate rosc;
+
+use rosc::{OscMessage, OscPacket, OscType};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::{Json, Object};
+
+use errors::RunError;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use event::ControlEvent;
+use io::{OscRemote, Receiver, Registry, Scaling};
+use types::*;
+
+/// Appended to the key of a WebSocket handshake before it is hashed, see RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+/// Larger frames close the connection, the clients only send commands and single values.
+const MAX_FRAME_LEN: u64 = 1 << 16;
+/// Readable and writable, see the `ACCESS` attribute.
+const READ_WRITE: u64 = 3;
+/// Clients that don't take a frame within this time are disconnected.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Clients that don't send their request within this time are disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests are read up to this length, the headers of the clients are short.
+const MAX_REQUEST_LEN: u64 = 1 << 13;
+/// Further connections are closed right away, each connection is served by a thread.
+const MAX_CONNECTIONS: usize = 16;
+
+/// A WebSocket connection and the addresses whose values it listens to.
+struct Listener {
+    id: usize,
+    stream: TcpStream,
+    addresses: HashSet<String>,
+}
+
+/// The state that is shared by the connections.
+#[derive(Clone)]
+struct Namespace {
+    osc_port: u16,
+    remote: Arc<Mutex<OscRemote>>,
+    listeners: Arc<Mutex<Vec<Listener>>>,
+}
+
+/// Describes the parameters of the registry to OSCQuery clients, so that controllers can
+/// discover and bind the parameters, see https://github.com/Vidvox/OSCQueryProposal.
+/// The description is served as JSON over HTTP, WebSocket clients can listen to the
+/// values of parameters and set them with binary OSC messages.
+pub struct OscQueryServer {
+    listener: TcpListener,
+    namespace: Namespace,
+    connections: Arc<AtomicUsize>, // number of open connections
+}
+impl OscQueryServer {
+    /// Binds the HTTP server, `osc_port` is the port of the `OscReceiver`.
+    pub fn new(
+        addr: SocketAddr,
+        osc_port: u16,
+        remote: Arc<Mutex<OscRemote>>,
+    ) -> Result<Self, RunError> {
+        let listener = try!(TcpListener::bind(addr).map_err(RunError::IoError));
+        Ok(OscQueryServer {
+            listener,
+            namespace: Namespace {
+                osc_port,
+                remote,
+                listeners: Arc::new(Mutex::new(Vec::new())),
+            },
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+impl Receiver for OscQueryServer {
+    fn receive_and_send(&mut self, tx: mpsc::Sender<ControlEvent>) {
+        let changes = self.namespace.remote.lock().unwrap().subscribe();
+        let namespace = self.namespace.clone();
+        thread::Builder::new()
+            .name("oscquery-listen".to_owned())
+            .spawn(move || namespace.notify_listeners(&changes))
+            .unwrap();
+        for (id, stream) in self.listener.incoming().enumerate() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Could not accept OSCQuery connection: {:?}", err);
+                    continue;
+                }
+            };
+            if self.connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                println!("Too many OSCQuery connections");
+                continue;
+            }
+            self.connections.fetch_add(1, Ordering::SeqCst);
+            let connections = self.connections.clone();
+            let namespace = self.namespace.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(err) = namespace.serve(stream, id, &tx) {
+                    println!("OSCQuery connection failed: {:?}", err);
+                }
+                namespace.remove_listener(id);
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+/// A HTTP GET request.
+struct Request {
+    path: String,
+    attribute: Option<String>, // the query string, like `VALUE` or `HOST_INFO`
+    headers: HashMap<String, String>, // with lowercase names
+}
+
+impl Namespace {
+    /// Answers a single HTTP request or upgrades the connection to a WebSocket.
+    fn serve(
+        &self,
+        mut stream: TcpStream,
+        id: usize,
+        tx: &mpsc::Sender<ControlEvent>,
+    ) -> io::Result<()> {
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        try!(stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+        let mut reader = BufReader::new(try!(stream.try_clone()));
+        let request = match try!(read_request(&mut (&mut reader).take(MAX_REQUEST_LEN))) {
+            Some(request) => request,
+            None => return respond(&mut stream, "400 Bad Request", ""),
+        };
+        let websocket_key = match request.headers.get("upgrade") {
+            Some(upgrade) if upgrade.eq_ignore_ascii_case("websocket") => {
+                request.headers.get("sec-websocket-key").cloned()
+            }
+            _ => None,
+        };
+        let key = match websocket_key {
+            Some(key) => key,
+            None => {
+                let (status, body) = self.route(&request);
+                return respond(&mut stream, status, &body);
+            }
+        };
+        try!(write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            websocket_accept(&key)
+        ));
+        // WebSocket clients only send frames when they change a parameter
+        try!(stream.set_read_timeout(None));
+        self.listeners.lock().unwrap().push(Listener {
+            id,
+            stream,
+            addresses: HashSet::new(),
+        });
+        loop {
+            let (opcode, payload) = try!(read_frame(&mut reader));
+            match opcode {
+                OPCODE_TEXT => self.handle_command(id, &payload),
+                OPCODE_BINARY => match rosc::decoder::decode(&payload) {
+                    Ok(packet) => {
+                        for event in self.set_parameters(packet) {
+                            // the synthesizer is shutting down
+                            if tx.send(event).is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Err(err) => println!("Could not decode osc packet: {:?}", err),
+                },
+                OPCODE_PING => try!(self.send_frame(id, OPCODE_PONG, &payload)),
+                OPCODE_CLOSE => return self.send_frame(id, OPCODE_CLOSE, &payload),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the status and JSON body of the answer to a HTTP request.
+    fn route(&self, request: &Request) -> (&'static str, String) {
+        let host_info = request
+            .attribute
+            .as_ref()
+            .map_or(false, |attribute| attribute == "HOST_INFO");
+        if host_info {
+            return ("200 OK", self.host_info().to_string());
+        }
+        let node = match describe(self.remote.lock().unwrap().registry(), &request.path) {
+            Some(node) => node,
+            None => return ("404 Not Found", String::new()),
+        };
+        match request.attribute {
+            Some(ref attribute) => match node.find(attribute) {
+                Some(value) => {
+                    let mut answer = Object::new();
+                    answer.insert(attribute.clone(), value.clone());
+                    ("200 OK", Json::Object(answer).to_string())
+                }
+                None => ("204 No Content", String::new()),
+            },
+            None => ("200 OK", node.to_string()),
+        }
+    }
+
+    fn host_info(&self) -> Json {
+        let mut extensions = Object::new();
+        for &(extension, supported) in &[
+            ("ACCESS", true),
+            ("VALUE", true),
+            ("RANGE", true),
+            ("DESCRIPTION", true),
+            ("UNIT", true),
+            ("CLIPMODE", true),
+            ("LISTEN", true),
+            ("TAGS", false),
+            ("EXTENDED_TYPE", false),
+            ("CRITICAL", false),
+            ("PATH_CHANGED", false),
+        ] {
+            extensions.insert(extension.to_owned(), Json::Boolean(supported));
+        }
+        let mut info = Object::new();
+        info.insert("NAME".to_owned(), Json::String("ytterbium".to_owned()));
+        info.insert("OSC_PORT".to_owned(), Json::U64(u64::from(self.osc_port)));
+        info.insert("OSC_TRANSPORT".to_owned(), Json::String("UDP".to_owned()));
+        info.insert("EXTENSIONS".to_owned(), Json::Object(extensions));
+        Json::Object(info)
+    }
+
+    /// Handles the `LISTEN` and `IGNORE` commands of a WebSocket client, like
+    /// `{"COMMAND": "LISTEN", "DATA": "/ytterbium/filter/freq"}`.
+    fn handle_command(&self, id: usize, payload: &[u8]) {
+        let command = match String::from_utf8_lossy(payload).parse::<Json>() {
+            Ok(command) => command,
+            Err(err) => {
+                println!("Could not parse OSCQuery command: {:?}", err);
+                return;
+            }
+        };
+        let address = match command.find("DATA").and_then(Json::as_string) {
+            Some(address) => address.to_owned(),
+            None => return,
+        };
+        let mut listeners = self.listeners.lock().unwrap();
+        if let Some(listener) = listeners.iter_mut().find(|listener| listener.id == id) {
+            match command.find("COMMAND").and_then(Json::as_string) {
+                Some("LISTEN") => {
+                    listener.addresses.insert(address);
+                }
+                Some("IGNORE") => {
+                    listener.addresses.remove(&address);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sets the parameters of the messages with a canonical address and a number.
+    fn set_parameters(&self, packet: OscPacket) -> Vec<ControlEvent> {
+        let messages = match packet {
+            OscPacket::Message(msg) => vec![msg],
+            OscPacket::Bundle(bundle) => bundle
+                .content
+                .into_iter()
+                .filter_map(|packet| match packet {
+                    OscPacket::Message(msg) => Some(msg),
+                    _ => None,
+                }).collect(),
+        };
+        let mut remote = self.remote.lock().unwrap();
+        let changes = messages
+            .iter()
+            .filter_map(|msg| {
+                let value = match msg.args.as_ref().and_then(|args| args.first()) {
+                    Some(&OscType::Float(value)) => Float::from(value),
+                    Some(&OscType::Double(value)) => value,
+                    Some(&OscType::Int(value)) => Float::from(value),
+                    _ => return None,
+                };
+                remote.registry().find(&msg.addr).map(|idx| (idx, value))
+            }).collect::<Vec<_>>();
+        remote.registry_mut().set(&changes)
+    }
+
+    /// Sends the changed values to the WebSocket clients that listen to them.
+    fn notify_listeners(&self, changes: &mpsc::Receiver<usize>) {
+        for idx in changes.iter() {
+            let msg = {
+                let remote = self.remote.lock().unwrap();
+                let parameter = &remote.registry().parameters()[idx];
+                let value = remote.registry().value(idx);
+                OscMessage {
+                    addr: parameter.address.clone(),
+                    args: Some(vec![match parameter.scaling {
+                        Scaling::Discrete => OscType::Int(value.round() as i32),
+                        _ => OscType::Float(value as f32),
+                    }]),
+                }
+            };
+            let data = match rosc::encoder::encode(&OscPacket::Message(msg.clone())) {
+                Ok(data) => data,
+                Err(err) => {
+                    println!("Could not encode osc value: {:?}", err);
+                    continue;
+                }
+            };
+            // the streams are written without the lock, so a slow client can't block the others
+            let streams = self
+                .listeners
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|listener| listener.addresses.contains(&msg.addr))
+                .filter_map(|listener| listener.stream.try_clone().ok())
+                .collect::<Vec<_>>();
+            for stream in streams {
+                if write_frame(&stream, OPCODE_BINARY, &data).is_err() {
+                    // ends the connection, its thread removes the listener
+                    stream.shutdown(Shutdown::Both).ok();
+                }
+            }
+        }
+    }
+
+    fn send_frame(&self, id: usize, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let stream = match self
+            .listeners
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|listener| listener.id == id)
+        {
+            Some(listener) => try!(listener.stream.try_clone()),
+            None => return Ok(()),
+        };
+        write_frame(&stream, opcode, payload)
+    }
+
+    fn remove_listener(&self, id: usize) {
+        self.listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| listener.id != id);
+    }
+}
+
+/// Returns the OSCQuery node of an address, which is either a parameter or a container
+/// of the parameters below it.
+fn describe(registry: &Registry, path: &str) -> Option<Json> {
+    let path = path.trim_end_matches('/');
+    if let Some(idx) = registry.find(path) {
+        return Some(describe_parameter(registry, idx));
+    }
+    let prefix = format!("{}/", path);
+    let children = registry
+        .parameters()
+        .iter()
+        .filter(|parameter| parameter.address.starts_with(&prefix))
+        .filter_map(|parameter| parameter.address[prefix.len()..].split('/').next())
+        .collect::<BTreeSet<_>>();
+    if children.is_empty() {
+        return None;
+    }
+    let contents = children
+        .into_iter()
+        .filter_map(|child| {
+            describe(registry, &format!("{}{}", prefix, child)).map(|node| (child.to_owned(), node))
+        }).collect::<Object>();
+    let full_path = if path.is_empty() { "/" } else { path };
+    let mut node = Object::new();
+    node.insert("FULL_PATH".to_owned(), Json::String(full_path.to_owned()));
+    node.insert("CONTENTS".to_owned(), Json::Object(contents));
+    Some(Json::Object(node))
+}
+
+fn describe_parameter(registry: &Registry, idx: usize) -> Json {
+    let parameter = &registry.parameters()[idx];
+    let (osc_type, number): (&str, fn(Float) -> Json) = match parameter.scaling {
+        Scaling::Discrete => ("i", |value| Json::I64(value.round() as i64)),
+        _ => ("f", Json::F64),
+    };
+    let mut range = Object::new();
+    range.insert("MIN".to_owned(), number(parameter.range.0));
+    range.insert("MAX".to_owned(), number(parameter.range.1));
+    let mut node = Object::new();
+    node.insert(
+        "FULL_PATH".to_owned(),
+        Json::String(parameter.address.clone()),
+    );
+    node.insert("TYPE".to_owned(), Json::String(osc_type.to_owned()));
+    node.insert("ACCESS".to_owned(), Json::U64(READ_WRITE));
+    node.insert(
+        "VALUE".to_owned(),
+        Json::Array(vec![number(registry.value(idx))]),
+    );
+    node.insert("RANGE".to_owned(), Json::Array(vec![Json::Object(range)]));
+    node.insert(
+        "CLIPMODE".to_owned(),
+        Json::Array(vec![Json::String("both".to_owned())]),
+    );
+    node.insert(
+        "DESCRIPTION".to_owned(),
+        Json::String(parameter.description.to_owned()),
+    );
+    if !parameter.unit.is_empty() {
+        node.insert(
+            "UNIT".to_owned(),
+            Json::Array(vec![Json::String(parameter.unit.to_owned())]),
+        );
+    }
+    Json::Object(node)
+}
+
+/// Reads the request line and the headers of a HTTP request, returns `None` for
+/// requests other than GET.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    try!(reader.read_line(&mut line));
+    let target = {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) => target.to_owned(),
+            _ => return Ok(None),
+        }
+    };
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if try!(reader.read_line(&mut line)) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            headers.insert(
+                line[..colon].trim().to_lowercase(),
+                line[colon + 1..].trim().to_owned(),
+            );
+        }
+    }
+    let mut target = target.splitn(2, '?');
+    Ok(Some(Request {
+        path: target.next().unwrap_or("/").to_owned(),
+        attribute: target.next().map(str::to_owned),
+        headers,
+    }))
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Reads a WebSocket frame and returns its opcode and unmasked payload.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    try!(reader.read_exact(&mut header));
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            try!(reader.read_exact(&mut len));
+            u64::from(len[0]) << 8 | u64::from(len[1])
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            try!(reader.read_exact(&mut len));
+            len.iter().fold(0, |acc, byte| acc << 8 | u64::from(*byte))
+        }
+        len => u64::from(len),
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "WebSocket frame too large",
+        ));
+    }
+    let mut mask = [0u8; 4];
+    if header[1] & 0x80 != 0 {
+        try!(reader.read_exact(&mut mask));
+    }
+    let mut payload = vec![0u8; len as usize];
+    try!(reader.read_exact(&mut payload));
+    for (idx, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[idx % 4];
+    }
+    Ok((header[0] & 0x0F, payload))
+}
+
+/// Writes an unfragmented and unmasked WebSocket frame.
+fn write_frame(mut stream: &TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    let len = payload.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= 0xFFFF {
+        frame.extend_from_slice(&[126, (len >> 8) as u8, len as u8]);
+    } else {
+        frame.push(127);
+        frame.extend((0..8).rev().map(|byte| (len as u64 >> (8 * byte)) as u8));
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+/// Returns the `Sec-WebSocket-Accept` header of the answer to a handshake.
+fn websocket_accept(key: &str) -> String {
+    sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()).to_base64(STANDARD)
+}
+
+/// Returns the SHA-1 digest of the data, which is only used for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = data.len() as u64 * 8;
+    message.extend((0..8).rev().map(|byte| (bits >> (8 * byte)) as u8));
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = bytes
+                .iter()
+                .fold(0, |acc, byte| acc << 8 | u32::from(*byte));
+        }
+        for idx in 16..80 {
+            words[idx] = (words[idx - 3] ^ words[idx - 8] ^ words[idx - 14] ^ words[idx - 16])
+                .rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) =
+            (state[0], state[1], state[2], state[3], state[4]);
+        for (idx, word) in words.iter().enumerate() {
+            let (f, k) = match idx / 20 {
+                0 => ((b & c) | (!b & d), 0x5A82_7999),
+                1 => (b ^ c ^ d, 0x6ED9_EBA1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(&state) {
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            *byte = (value >> (24 - 8 * idx)) as u8;
+        }
+    }
+    digest
+}
+
+#[test]
+fn test_websocket_handshake() {
+    // see the examples of RFC 3174 and RFC 6455
+    let digest = sha1(b"abc")
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    assert_eq!(digest, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(
+        websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+
+    // masked frames of the client are unmasked
+    let frame = [
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+    let (opcode, payload) = read_frame(&mut &frame[..]).unwrap();
+    assert_eq!((opcode, &payload[..]), (OPCODE_TEXT, &b"Hello"[..]));
+}
+
+#[test]
+fn test_oscquery_namespace() {
+    let registry = Registry::new();
+    let root = describe(&registry, "/").unwrap();
+    assert_eq!(root.find("FULL_PATH").and_then(Json::as_string), Some("/"));
+    let osc = root
+        .find_path(&["CONTENTS", "ytterbium", "CONTENTS", "osc", "CONTENTS", "1"])
+        .unwrap();
+    assert_eq!(
+        osc.find("FULL_PATH").and_then(Json::as_string),
+        Some("/ytterbium/osc/1")
+    );
+    let attack = osc.find_path(&["CONTENTS", "adsr", "CONTENTS", "attack"]);
+    assert!(attack.is_some());
+
+    let freq = describe(&registry, "/ytterbium/filter/freq").unwrap();
+    assert_eq!(freq.find("TYPE").and_then(Json::as_string), Some("f"));
+    assert_eq!(
+        freq.find("RANGE").unwrap().to_string(),
+        r#"[{"MAX":20040.0,"MIN":40.0}]"#
+    );
+    assert_eq!(freq.find("UNIT").unwrap().to_string(), r#"["Hz"]"#);
+    let waveform = describe(&registry, "/ytterbium/osc/2/waveform").unwrap();
+    assert_eq!(waveform.find("VALUE").unwrap().to_string(), "[0]");
+    assert!(describe(&registry, "/ytterbium/osc/9").is_none());
+
+    let request = b"GET /ytterbium/transport/tempo?VALUE HTTP/1.1\r\nHost: localhost\r\n\
+                    Upgrade: websocket\r\n\r\n";
+    let request = read_request(&mut &request[..]).unwrap().unwrap();
+    assert_eq!(request.path, "/ytterbium/transport/tempo");
+    assert_eq!(request.attribute, Some("VALUE".to_owned()));
+    assert_eq!(
+        request.headers.get("upgrade"),
+        Some(&"websocket".to_owned())
+    );
+}
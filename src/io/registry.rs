@@ -0,0 +1,875 @@
+use std::collections::HashMap;
+
+use dsp::{
+    ArpeggiatorMode, FilterType, Interpolation, VelocityCurve, VoiceAllocation, Waveform,
+    ALGORITHMS, MAX_OCTAVES, MAX_OSC_CNT, MAX_VELOCITY_RANGE, PATTERN_CNT,
+};
+use event::ControlEvent;
+use types::*;
+
+/// Prefix of the canonical addresses of the parameters.
+pub const NAMESPACE: &str = "/ytterbium";
+
+/// The entries of the menus, the value of a menu parameter is the index of its entry.
+pub const WAVEFORMS: [Waveform; 11] = [
+    Waveform::Sine,
+    Waveform::Saw,
+    Waveform::Square,
+    Waveform::Tri,
+    Waveform::SharpTri,
+    Waveform::Random,
+    Waveform::WhiteNoise,
+    Waveform::PinkNoise,
+    Waveform::BrownNoise,
+    Waveform::ColoredNoise,
+    Waveform::Pulse,
+];
+pub const FILTER_TYPES: [FilterType; 4] = [
+    FilterType::LP,
+    FilterType::HP,
+    FilterType::BP,
+    FilterType::Notch,
+];
+pub const INTERPOLATIONS: [Interpolation; 3] = [
+    Interpolation::Linear,
+    Interpolation::Cubic,
+    Interpolation::Sinc,
+];
+pub const VOICE_ALLOCATIONS: [VoiceAllocation; 6] = [
+    VoiceAllocation::Oldest,
+    VoiceAllocation::Quietest,
+    VoiceAllocation::LowestNote,
+    VoiceAllocation::HighestNote,
+    VoiceAllocation::SameNote,
+    VoiceAllocation::RoundRobin,
+];
+pub const ARPEGGIATOR_MODES: [ArpeggiatorMode; 5] = [
+    ArpeggiatorMode::Up,
+    ArpeggiatorMode::Down,
+    ArpeggiatorMode::UpDown,
+    ArpeggiatorMode::Random,
+    ArpeggiatorMode::AsPlayed,
+];
+/// Step lengths in beats: 1/4, 1/8, 1/8T, 1/16, 1/16T, 1/32
+pub const DIVISIONS: [Float; 6] = [1.0, 1.0 / 2.0, 1.0 / 3.0, 1.0 / 4.0, 1.0 / 6.0, 1.0 / 8.0];
+/// Volume in dB at which an oscillator is muted.
+const MUTED: Float = -61.0;
+/// Lowest rate of the LFOs in Hz, exponential ranges can't start at zero.
+const MIN_LFO_RATE: Float = 0.01;
+/// The sustain level of the envelopes, -12 dB.
+const DEFAULT_SUSTAIN: Float = 0.251_188_643_150_958;
+
+/// How a controller should map its range to the range of a parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaling {
+    Linear,
+    /// Equal ratios have equal distances, used for times and frequencies.
+    Exponential,
+    /// Only whole numbers, like the entries of a menu or a switch.
+    Discrete,
+}
+impl Scaling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaling::Linear => "linear",
+            Scaling::Exponential => "exponential",
+            Scaling::Discrete => "discrete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Envelope {
+    Volume,
+    Position,
+}
+impl Envelope {
+    fn id(self, osc: usize) -> String {
+        match self {
+            Envelope::Volume => format!("ADSR-OSC{}", osc + 1),
+            Envelope::Position => format!("ADSR-POSITION-OSC{}", osc + 1),
+        }
+    }
+}
+
+/// The value of an event that is set by a parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Control {
+    Waveform,
+    Transpose,
+    Detune,
+    Phase,
+    Ratio,
+    Fine,
+    Fixed,
+    FixedFreq,
+    PulseWidth,
+    PwmDepth,
+    PwmRate,
+    NoiseTone,
+    Volume,
+    Pan,
+    Sync,
+    FmLevel(usize), // of the modulating oscillator
+    RingModLevel(usize),
+    Attack(Envelope),
+    Decay(Envelope),
+    Sustain(Envelope),
+    Release(Envelope),
+    VelocitySensitivity,
+    VelocityTime,
+    RateScaling,
+    Position,
+    PositionEnvelope,
+    PositionLfo,
+    PositionLfoRate,
+    FilterType,
+    FilterFreq,
+    FilterQ,
+    Interpolation,
+    Algorithm,
+    Operators,
+    VoiceAllocation,
+    VelocityCurve,
+    VelocityRange,
+    ArpeggiatorOn,
+    ArpeggiatorMode,
+    ArpeggiatorOctaves,
+    ArpeggiatorRate,
+    ArpeggiatorGate,
+    ArpeggiatorSwing,
+    ArpeggiatorLatch,
+    Tempo,
+    SequencerPattern,
+    SequencerRate,
+}
+
+type Spec = (
+    &'static str,
+    &'static str,
+    (Float, Float),
+    &'static str,
+    Float,
+    Scaling,
+    Control,
+);
+
+/// Parameters of each oscillator: address below `osc/<n>`, description, range, unit,
+/// default, scaling and the controlled value.
+#[rustfmt::skip]
+const OSC_PARAMETERS: &[Spec] = &[
+    ("waveform", "Waveform: sine, saw, square, triangle, sharp triangle, random, white, pink, brown and colored noise, pulse", (0.0, (WAVEFORMS.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::Waveform),
+    ("transpose", "Transposition", (-3.0, 3.0), "octaves", 0.0, Scaling::Discrete, Control::Transpose),
+    ("detune", "Detuning", (-100.0, 100.0), "cents", 0.0, Scaling::Discrete, Control::Detune),
+    ("phase", "Phase offset in periods", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::Phase),
+    ("ratio", "Coarse frequency ratio to the key", (0.5, 31.0), "", 1.0, Scaling::Linear, Control::Ratio),
+    ("fine", "Fine frequency ratio that is added to the coarse ratio", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::Fine),
+    ("fixed", "Plays the fixed frequency instead of a ratio of the key", (0.0, 1.0), "", 0.0, Scaling::Discrete, Control::Fixed),
+    ("fixed-freq", "Fixed frequency", (1.0, 10_001.0), "Hz", 440.0, Scaling::Exponential, Control::FixedFreq),
+    ("pulse-width", "Part of the period in which the pulse is high", (0.0, 1.0), "", 0.5, Scaling::Linear, Control::PulseWidth),
+    ("pwm/depth", "Depth of the pulse width modulation", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::PwmDepth),
+    ("pwm/rate", "Rate of the pulse width modulation", (MIN_LFO_RATE, 20.0), "Hz", 1.0, Scaling::Exponential, Control::PwmRate),
+    ("noise-tone", "Center frequency of the colored noise", (0.0, 1.0), "", 0.5, Scaling::Linear, Control::NoiseTone),
+    ("volume", "Volume, oscillators at -61 dB are muted", (MUTED, 0.0), "dB", MUTED, Scaling::Linear, Control::Volume),
+    ("pan", "Stereo position", (-1.0, 1.0), "", 0.0, Scaling::Linear, Control::Pan),
+    ("sync", "Oscillator that restarts the period, 0 disables the sync", (0.0, MAX_OSC_CNT as Float), "", 0.0, Scaling::Discrete, Control::Sync),
+    ("adsr/attack", "Attack time of the volume envelope", (0.001, 60.0), "s", 0.02, Scaling::Exponential, Control::Attack(Envelope::Volume)),
+    ("adsr/decay", "Decay time of the volume envelope", (0.001, 60.0), "s", 0.2, Scaling::Exponential, Control::Decay(Envelope::Volume)),
+    ("adsr/sustain", "Sustain level of the volume envelope", (0.0, 1.0), "", DEFAULT_SUSTAIN, Scaling::Linear, Control::Sustain(Envelope::Volume)),
+    ("adsr/release", "Release time of the volume envelope", (0.001, 60.0), "s", 0.6, Scaling::Exponential, Control::Release(Envelope::Volume)),
+    ("adsr/velocity", "Velocity sensitivity of the volume envelope", (0.0, 1.0), "", 1.0, Scaling::Linear, Control::VelocitySensitivity),
+    ("adsr/velocity-time", "How much harder notes shorten the attack and decay", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::VelocityTime),
+    ("adsr/rate-scaling", "How much higher keys shorten the envelope times", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::RateScaling),
+    ("wavetable/position", "Position in the frames of the wavetable", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::Position),
+    ("wavetable/envelope", "Depth of the position envelope", (-1.0, 1.0), "", 0.0, Scaling::Linear, Control::PositionEnvelope),
+    ("wavetable/lfo", "Depth of the position LFO", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::PositionLfo),
+    ("wavetable/lfo-rate", "Rate of the position LFO", (MIN_LFO_RATE, 20.0), "Hz", 1.0, Scaling::Exponential, Control::PositionLfoRate),
+    ("wavetable/adsr/attack", "Attack time of the position envelope", (0.001, 60.0), "s", 0.02, Scaling::Exponential, Control::Attack(Envelope::Position)),
+    ("wavetable/adsr/decay", "Decay time of the position envelope", (0.001, 60.0), "s", 0.2, Scaling::Exponential, Control::Decay(Envelope::Position)),
+    ("wavetable/adsr/sustain", "Sustain level of the position envelope", (0.0, 1.0), "", DEFAULT_SUSTAIN, Scaling::Linear, Control::Sustain(Envelope::Position)),
+    ("wavetable/adsr/release", "Release time of the position envelope", (0.001, 60.0), "s", 0.6, Scaling::Exponential, Control::Release(Envelope::Position)),
+];
+
+/// Parameters that apply to all voices.
+#[rustfmt::skip]
+const GLOBAL_PARAMETERS: &[Spec] = &[
+    ("filter/type", "Filter type: low pass, high pass, band pass, notch", (0.0, (FILTER_TYPES.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::FilterType),
+    ("filter/freq", "Cutoff frequency of the filter", (40.0, 20_040.0), "Hz", 20_040.0, Scaling::Exponential, Control::FilterFreq),
+    ("filter/q", "Resonance of the filter", (0.0, 4.0), "", 1.0, Scaling::Linear, Control::FilterQ),
+    ("quality/interpolation", "Interpolation of the wavetables: linear, cubic, sinc", (0.0, (INTERPOLATIONS.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::Interpolation),
+    ("fm/algorithm", "Index of the FM algorithm, the four operator algorithms come first", (0.0, (ALGORITHMS.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::Algorithm),
+    ("fm/operators", "Number of playing oscillators", (1.0, MAX_OSC_CNT as Float), "", 4.0, Scaling::Discrete, Control::Operators),
+    ("voices/allocation", "Voice stealing: oldest, quietest, lowest, highest, same note, round robin", (0.0, (VOICE_ALLOCATIONS.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::VoiceAllocation),
+    ("voices/velocity-curve", "Velocity curve: linear, exponential", (0.0, 1.0), "", 1.0, Scaling::Discrete, Control::VelocityCurve),
+    ("voices/velocity-range", "Dynamic range of the velocity", (0.0, MAX_VELOCITY_RANGE), "dB", 30.0, Scaling::Linear, Control::VelocityRange),
+    ("arpeggiator/on", "Enables the arpeggiator", (0.0, 1.0), "", 0.0, Scaling::Discrete, Control::ArpeggiatorOn),
+    ("arpeggiator/mode", "Order of the notes: up, down, up and down, random, as played", (0.0, (ARPEGGIATOR_MODES.len() - 1) as Float), "", 0.0, Scaling::Discrete, Control::ArpeggiatorMode),
+    ("arpeggiator/octaves", "Octave range", (1.0, MAX_OCTAVES as Float), "octaves", 1.0, Scaling::Discrete, Control::ArpeggiatorOctaves),
+    ("arpeggiator/rate", "Step length: 1/4, 1/8, 1/8T, 1/16, 1/16T, 1/32", (0.0, (DIVISIONS.len() - 1) as Float), "", 3.0, Scaling::Discrete, Control::ArpeggiatorRate),
+    ("arpeggiator/gate", "Note length relative to the step length", (0.0, 1.0), "", 0.5, Scaling::Linear, Control::ArpeggiatorGate),
+    ("arpeggiator/swing", "Delay of every second step", (0.0, 1.0), "", 0.0, Scaling::Linear, Control::ArpeggiatorSwing),
+    ("arpeggiator/latch", "Holds the notes after they were released", (0.0, 1.0), "", 0.0, Scaling::Discrete, Control::ArpeggiatorLatch),
+    ("transport/tempo", "Tempo if no MIDI clock is received", (20.0, 300.0), "BPM", 120.0, Scaling::Linear, Control::Tempo),
+    ("sequencer/pattern", "Playing pattern", (0.0, (PATTERN_CNT - 1) as Float), "", 0.0, Scaling::Discrete, Control::SequencerPattern),
+    ("sequencer/rate", "Step length: 1/4, 1/8, 1/8T, 1/16, 1/16T, 1/32", (0.0, (DIVISIONS.len() - 1) as Float), "", 3.0, Scaling::Discrete, Control::SequencerRate),
+];
+
+/// A sound parameter with its address in the canonical namespace.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub address: String,
+    pub description: &'static str,
+    pub range: (Float, Float),
+    pub unit: &'static str,
+    pub default: Float,
+    pub scaling: Scaling,
+    osc: Option<usize>, // index of the oscillator
+    control: Control,
+}
+
+/// The parameters of the synth and their current values.
+pub struct Registry {
+    parameters: Vec<Parameter>,
+    values: Vec<Float>,
+    addresses: HashMap<String, usize>,
+    controls: HashMap<(Option<usize>, Control), usize>,
+}
+impl Registry {
+    pub fn new() -> Self {
+        let mut parameters = Vec::new();
+        let mut add = |address: String, spec: &Spec, osc: Option<usize>| {
+            let &(_, description, range, unit, default, scaling, control) = spec;
+            parameters.push(Parameter {
+                address,
+                description,
+                range,
+                unit,
+                default,
+                scaling,
+                osc,
+                control,
+            })
+        };
+        for osc in 0..MAX_OSC_CNT {
+            for spec in OSC_PARAMETERS {
+                add(
+                    format!("{}/osc/{}/{}", NAMESPACE, osc + 1, spec.0),
+                    spec,
+                    Some(osc),
+                );
+            }
+            for modulator in 0..MAX_OSC_CNT {
+                add(
+                    format!("{}/osc/{}/fm/{}", NAMESPACE, osc + 1, modulator + 1),
+                    &(
+                        "",
+                        "Phase modulation by another oscillator in periods",
+                        (0.0, 2.0),
+                        "",
+                        0.0,
+                        Scaling::Linear,
+                        Control::FmLevel(modulator),
+                    ),
+                    Some(osc),
+                );
+                add(
+                    format!("{}/osc/{}/ringmod/{}", NAMESPACE, osc + 1, modulator + 1),
+                    &(
+                        "",
+                        "Ring modulation by another oscillator",
+                        (0.0, 1.0),
+                        "",
+                        0.0,
+                        Scaling::Linear,
+                        Control::RingModLevel(modulator),
+                    ),
+                    Some(osc),
+                );
+            }
+        }
+        for spec in GLOBAL_PARAMETERS {
+            add(format!("{}/{}", NAMESPACE, spec.0), spec, None);
+        }
+        // only the first oscillator is audible initially
+        parameters[0..OSC_PARAMETERS.len()]
+            .iter_mut()
+            .filter(|parameter| parameter.control == Control::Volume)
+            .for_each(|parameter| parameter.default = Float::to_db(MINUS_THREE_DB));
+
+        Registry {
+            values: parameters
+                .iter()
+                .map(|parameter| parameter.default)
+                .collect(),
+            addresses: parameters
+                .iter()
+                .enumerate()
+                .map(|(idx, parameter)| (parameter.address.clone(), idx))
+                .collect(),
+            controls: parameters
+                .iter()
+                .enumerate()
+                .map(|(idx, parameter)| ((parameter.osc, parameter.control), idx))
+                .collect(),
+            parameters,
+        }
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// Returns the index of the parameter with the given canonical address.
+    pub fn find(&self, address: &str) -> Option<usize> {
+        self.addresses.get(address).cloned()
+    }
+
+    pub fn value(&self, idx: usize) -> Float {
+        self.values[idx]
+    }
+
+    /// Returns all current values, see `restore`.
+    pub fn values(&self) -> Vec<Float> {
+        self.values.clone()
+    }
+
+    /// Replaces the current values with values returned by `values`.
+    pub fn restore(&mut self, values: Vec<Float>) {
+        if values.len() == self.values.len() {
+            self.values = values;
+        }
+    }
+
+    fn get(&self, osc: Option<usize>, control: Control) -> Float {
+        self.controls
+            .get(&(osc, control))
+            .map_or(0.0, |idx| self.values[*idx])
+    }
+
+    /// Sets the parameters to the given values, which are clamped to their ranges, and
+    /// returns the events that apply them. Parameters that share an event, like the
+    /// times of an envelope, result in a single event.
+    pub fn set(&mut self, changes: &[(usize, Float)]) -> Vec<ControlEvent> {
+        for &(idx, value) in changes {
+            let (min, max) = self.parameters[idx].range;
+            self.values[idx] = value.max(min).min(max);
+        }
+        self.events_of(changes.iter().map(|&(idx, _)| idx))
+    }
+
+    /// Returns the events that apply the current values of all parameters.
+    pub fn events(&self) -> Vec<ControlEvent> {
+        self.events_of(0..self.parameters.len())
+    }
+
+    fn events_of<I: Iterator<Item = usize>>(&self, parameters: I) -> Vec<ControlEvent> {
+        let mut events: Vec<ControlEvent> = Vec::new();
+        for event in parameters.map(|idx| self.event(idx)) {
+            // the fields of a combined parameter are merged into its first event
+            let same = events.iter().position(|other| {
+                other.parameter().is_some() && other.parameter() == event.parameter()
+            });
+            match same {
+                Some(idx) => events[idx].merge(&event, event.fields()),
+                None => events.push(event),
+            }
+        }
+        events
+    }
+
+    /// Returns the event that applies the current value of a parameter. The values of
+    /// the menus are valid indices, since they are clamped to the ranges of the menus.
+    fn event(&self, idx: usize) -> ControlEvent {
+        let value = self.values[idx];
+        let parameter = &self.parameters[idx];
+        let osc = parameter.osc.unwrap_or(0);
+        let id = format!("OSC{}", osc + 1);
+        let all = |control: Control| {
+            (0..MAX_OSC_CNT)
+                .map(|osc| self.get(Some(osc), control))
+                .collect::<Vec<_>>()
+        };
+        let levels = |control: fn(usize) -> Control| {
+            (0..MAX_OSC_CNT)
+                .map(|modulator| self.get(Some(osc), control(modulator)))
+                .collect::<Vec<_>>()
+        };
+        let adsr = |envelope: Envelope| ControlEvent::ADSR {
+            id: envelope.id(osc),
+            attack: self.get(Some(osc), Control::Attack(envelope)) as Time,
+            decay: self.get(Some(osc), Control::Decay(envelope)) as Time,
+            sustain: self.get(Some(osc), Control::Sustain(envelope)),
+            release: self.get(Some(osc), Control::Release(envelope)) as Time,
+        };
+        let frequency = |coarse, fine, fixed, fixed_freq| ControlEvent::OscFrequency {
+            id: id.clone(),
+            coarse,
+            fine,
+            fixed,
+            fixed_freq,
+        };
+        let position = |position, envelope, lfo, lfo_rate| ControlEvent::WavetablePosition {
+            id: id.clone(),
+            position,
+            envelope,
+            lfo,
+            lfo_rate,
+        };
+        let pulse_width = |width, lfo, lfo_rate| ControlEvent::PulseWidth {
+            id: id.clone(),
+            width,
+            lfo,
+            lfo_rate,
+        };
+        let arpeggiator = ControlEvent::Arpeggiator {
+            enabled: None,
+            mode: None,
+            octaves: None,
+            division: None,
+            gate: None,
+            swing: None,
+            latch: None,
+        };
+        let selection = value as usize;
+        match parameter.control {
+            Control::Waveform => ControlEvent::Waveform {
+                id,
+                waveform: WAVEFORMS[selection],
+            },
+            Control::Transpose => ControlEvent::Transpose {
+                id,
+                transpose: value as i32,
+            },
+            Control::Detune => ControlEvent::Detune {
+                id,
+                detune: value as i32,
+            },
+            Control::Phase => ControlEvent::Phase { id, phase: value },
+            Control::Ratio => frequency(Some(value), None, None, None),
+            Control::Fine => frequency(None, Some(value), None, None),
+            Control::Fixed => frequency(None, None, Some(value > 0.5), None),
+            Control::FixedFreq => frequency(None, None, None, Some(value)),
+            Control::PulseWidth => pulse_width(Some(value), None, None),
+            Control::PwmDepth => pulse_width(None, Some(value), None),
+            Control::PwmRate => pulse_width(None, None, Some(value)),
+            Control::NoiseTone => ControlEvent::NoiseTone { id, tone: value },
+            Control::Volume => ControlEvent::Volume(all(Control::Volume)),
+            Control::Pan => ControlEvent::Pan(all(Control::Pan)),
+            Control::Sync => ControlEvent::Sync {
+                id,
+                source: match selection {
+                    0 => None,
+                    source => Some(format!("OSC{}", source)),
+                },
+            },
+            Control::FmLevel(_) => ControlEvent::FM {
+                id,
+                levels: levels(Control::FmLevel),
+            },
+            Control::RingModLevel(_) => ControlEvent::RingMod {
+                id,
+                levels: levels(Control::RingModLevel),
+            },
+            Control::Attack(envelope)
+            | Control::Decay(envelope)
+            | Control::Sustain(envelope)
+            | Control::Release(envelope) => adsr(envelope),
+            Control::VelocitySensitivity => ControlEvent::ADSRVelocity {
+                id: Envelope::Volume.id(osc),
+                sensitivity: Some(value),
+                time: None,
+            },
+            Control::VelocityTime => ControlEvent::ADSRVelocity {
+                id: Envelope::Volume.id(osc),
+                sensitivity: None,
+                time: Some(value),
+            },
+            Control::RateScaling => ControlEvent::ADSRRateScaling {
+                id: Envelope::Volume.id(osc),
+                rate: value,
+            },
+            Control::Position => position(Some(value), None, None, None),
+            Control::PositionEnvelope => position(None, Some(value), None, None),
+            Control::PositionLfo => position(None, None, Some(value), None),
+            Control::PositionLfoRate => position(None, None, None, Some(value)),
+            Control::FilterType => ControlEvent::Filter {
+                filter_type: Some(FILTER_TYPES[selection]),
+                freq: None,
+                q: None,
+            },
+            Control::FilterFreq => ControlEvent::Filter {
+                filter_type: None,
+                freq: Some(value),
+                q: None,
+            },
+            Control::FilterQ => ControlEvent::Filter {
+                filter_type: None,
+                freq: None,
+                q: Some(value),
+            },
+            Control::Interpolation => ControlEvent::Interpolation(INTERPOLATIONS[selection]),
+            Control::Algorithm => ControlEvent::FMAlgorithm(ALGORITHMS[selection].name.to_owned()),
+            Control::Operators => ControlEvent::OscillatorCount(selection),
+            Control::VoiceAllocation => ControlEvent::VoiceAllocation(VOICE_ALLOCATIONS[selection]),
+            Control::VelocityCurve => ControlEvent::VelocityResponse {
+                curve: Some(if selection == 0 {
+                    VelocityCurve::Linear
+                } else {
+                    VelocityCurve::Exponential
+                }),
+                range: None,
+            },
+            Control::VelocityRange => ControlEvent::VelocityResponse {
+                curve: None,
+                range: Some(value),
+            },
+            Control::ArpeggiatorOn
+            | Control::ArpeggiatorMode
+            | Control::ArpeggiatorOctaves
+            | Control::ArpeggiatorRate
+            | Control::ArpeggiatorGate
+            | Control::ArpeggiatorSwing
+            | Control::ArpeggiatorLatch => {
+                let mut event = arpeggiator;
+                if let ControlEvent::Arpeggiator {
+                    ref mut enabled,
+                    ref mut mode,
+                    ref mut octaves,
+                    ref mut division,
+                    ref mut gate,
+                    ref mut swing,
+                    ref mut latch,
+                } = event
+                {
+                    match parameter.control {
+                        Control::ArpeggiatorOn => *enabled = Some(value > 0.5),
+                        Control::ArpeggiatorMode => *mode = Some(ARPEGGIATOR_MODES[selection]),
+                        Control::ArpeggiatorOctaves => *octaves = Some(value.round() as usize),
+                        Control::ArpeggiatorRate => *division = Some(DIVISIONS[selection]),
+                        Control::ArpeggiatorGate => *gate = Some(value),
+                        Control::ArpeggiatorSwing => *swing = Some(value),
+                        _ => *latch = Some(value > 0.5),
+                    }
+                }
+                event
+            }
+            Control::Tempo => ControlEvent::Tempo(value),
+            Control::SequencerPattern => ControlEvent::Sequencer {
+                pattern: Some(selection),
+                division: None,
+            },
+            Control::SequencerRate => ControlEvent::Sequencer {
+                pattern: None,
+                division: Some(DIVISIONS[selection]),
+            },
+        }
+    }
+
+    /// Stores the values set by an event and returns the indices of the parameters
+    /// that it sets. Values outside of the ranges are kept, they can be set by patches.
+    pub fn update(&mut self, event: &ControlEvent) -> Vec<usize> {
+        let mut changes = Vec::new();
+        {
+            let mut change = |osc: Option<usize>, control: Control, value: Option<Float>| {
+                if let Some(value) = value {
+                    changes.push((osc, control, value));
+                }
+            };
+            let position = |selection: Option<usize>| selection.map(|idx| idx as Float);
+            match *event {
+                ControlEvent::Waveform { ref id, waveform } => change(
+                    oscillator(id),
+                    Control::Waveform,
+                    position(WAVEFORMS.iter().position(|w| *w == waveform)),
+                ),
+                ControlEvent::Transpose { ref id, transpose } => change(
+                    oscillator(id),
+                    Control::Transpose,
+                    Some(Float::from(transpose)),
+                ),
+                ControlEvent::Detune { ref id, detune } => {
+                    change(oscillator(id), Control::Detune, Some(Float::from(detune)))
+                }
+                ControlEvent::Phase { ref id, phase } => {
+                    change(oscillator(id), Control::Phase, Some(phase))
+                }
+                ControlEvent::OscFrequency {
+                    ref id,
+                    coarse,
+                    fine,
+                    fixed,
+                    fixed_freq,
+                } => {
+                    let osc = oscillator(id);
+                    change(osc, Control::Ratio, coarse);
+                    change(osc, Control::Fine, fine);
+                    change(osc, Control::Fixed, fixed.map(|fixed| fixed as u8 as Float));
+                    change(osc, Control::FixedFreq, fixed_freq);
+                }
+                ControlEvent::PulseWidth {
+                    ref id,
+                    width,
+                    lfo,
+                    lfo_rate,
+                } => {
+                    let osc = oscillator(id);
+                    change(osc, Control::PulseWidth, width);
+                    change(osc, Control::PwmDepth, lfo);
+                    change(osc, Control::PwmRate, lfo_rate);
+                }
+                ControlEvent::NoiseTone { ref id, tone } => {
+                    change(oscillator(id), Control::NoiseTone, Some(tone))
+                }
+                ControlEvent::Volume(ref volume) => {
+                    for (osc, db) in volume.iter().enumerate() {
+                        change(Some(osc), Control::Volume, Some(*db));
+                    }
+                }
+                ControlEvent::Pan(ref pan) => {
+                    for (osc, pan) in pan.iter().enumerate() {
+                        change(Some(osc), Control::Pan, Some(*pan));
+                    }
+                }
+                ControlEvent::Sync { ref id, ref source } => change(
+                    oscillator(id),
+                    Control::Sync,
+                    match *source {
+                        Some(ref source) => oscillator(source).map(|osc| (osc + 1) as Float),
+                        None => Some(0.0),
+                    },
+                ),
+                ControlEvent::FM { ref id, ref levels } => {
+                    for (modulator, level) in levels.iter().enumerate() {
+                        change(oscillator(id), Control::FmLevel(modulator), Some(*level));
+                    }
+                }
+                ControlEvent::RingMod { ref id, ref levels } => {
+                    for (modulator, level) in levels.iter().enumerate() {
+                        change(
+                            oscillator(id),
+                            Control::RingModLevel(modulator),
+                            Some(*level),
+                        );
+                    }
+                }
+                ControlEvent::ADSR {
+                    ref id,
+                    attack,
+                    decay,
+                    sustain,
+                    release,
+                } => {
+                    if let Some((osc, envelope)) = envelope_kind(id) {
+                        change(osc, Control::Attack(envelope), Some(Float::from(attack)));
+                        change(osc, Control::Decay(envelope), Some(Float::from(decay)));
+                        change(osc, Control::Sustain(envelope), Some(sustain));
+                        change(osc, Control::Release(envelope), Some(Float::from(release)));
+                    }
+                }
+                ControlEvent::ADSRVelocity {
+                    ref id,
+                    sensitivity,
+                    time,
+                } => {
+                    if let Some((osc, Envelope::Volume)) = envelope_kind(id) {
+                        change(osc, Control::VelocitySensitivity, sensitivity);
+                        change(osc, Control::VelocityTime, time);
+                    }
+                }
+                ControlEvent::ADSRRateScaling { ref id, rate } => {
+                    if let Some((osc, Envelope::Volume)) = envelope_kind(id) {
+                        change(osc, Control::RateScaling, Some(rate));
+                    }
+                }
+                ControlEvent::WavetablePosition {
+                    ref id,
+                    position,
+                    envelope,
+                    lfo,
+                    lfo_rate,
+                } => {
+                    let osc = oscillator(id);
+                    change(osc, Control::Position, position);
+                    change(osc, Control::PositionEnvelope, envelope);
+                    change(osc, Control::PositionLfo, lfo);
+                    change(osc, Control::PositionLfoRate, lfo_rate);
+                }
+                ControlEvent::Filter {
+                    filter_type,
+                    freq,
+                    q,
+                } => {
+                    change(
+                        None,
+                        Control::FilterType,
+                        position(
+                            filter_type
+                                .and_then(|t| FILTER_TYPES.iter().position(|other| *other == t)),
+                        ),
+                    );
+                    change(None, Control::FilterFreq, freq);
+                    change(None, Control::FilterQ, q);
+                }
+                ControlEvent::Interpolation(interpolation) => change(
+                    None,
+                    Control::Interpolation,
+                    position(INTERPOLATIONS.iter().position(|i| *i == interpolation)),
+                ),
+                ControlEvent::FMAlgorithm(ref name) => change(
+                    None,
+                    Control::Algorithm,
+                    position(ALGORITHMS.iter().position(|a| a.name == name)),
+                ),
+                ControlEvent::OscillatorCount(count) => {
+                    change(None, Control::Operators, Some(count as Float))
+                }
+                ControlEvent::VoiceAllocation(allocation) => change(
+                    None,
+                    Control::VoiceAllocation,
+                    position(VOICE_ALLOCATIONS.iter().position(|a| *a == allocation)),
+                ),
+                ControlEvent::VelocityResponse { ref curve, range } => {
+                    let curve = match *curve {
+                        Some(VelocityCurve::Linear) => Some(0.0),
+                        Some(VelocityCurve::Exponential) => Some(1.0),
+                        _ => None,
+                    };
+                    change(None, Control::VelocityCurve, curve);
+                    change(None, Control::VelocityRange, range);
+                }
+                ControlEvent::Arpeggiator {
+                    enabled,
+                    mode,
+                    octaves,
+                    division,
+                    gate,
+                    swing,
+                    latch,
+                } => {
+                    let switch = |on: Option<bool>| on.map(|on| on as u8 as Float);
+                    change(None, Control::ArpeggiatorOn, switch(enabled));
+                    change(
+                        None,
+                        Control::ArpeggiatorMode,
+                        position(mode.and_then(|m| ARPEGGIATOR_MODES.iter().position(|o| *o == m))),
+                    );
+                    change(
+                        None,
+                        Control::ArpeggiatorOctaves,
+                        octaves.map(|octaves| octaves as Float),
+                    );
+                    change(
+                        None,
+                        Control::ArpeggiatorRate,
+                        position(division.and_then(division_selection)),
+                    );
+                    change(None, Control::ArpeggiatorGate, gate);
+                    change(None, Control::ArpeggiatorSwing, swing);
+                    change(None, Control::ArpeggiatorLatch, switch(latch));
+                }
+                ControlEvent::Tempo(tempo) => change(None, Control::Tempo, Some(tempo)),
+                ControlEvent::Sequencer { pattern, division } => {
+                    change(
+                        None,
+                        Control::SequencerPattern,
+                        pattern.map(|pattern| pattern as Float),
+                    );
+                    change(
+                        None,
+                        Control::SequencerRate,
+                        position(division.and_then(division_selection)),
+                    );
+                }
+                _ => {}
+            }
+        }
+        changes
+            .into_iter()
+            .filter_map(|(osc, control, value)| {
+                // events of unknown oscillators are ignored
+                let idx = self.controls.get(&(osc, control)).cloned();
+                if let Some(idx) = idx {
+                    self.values[idx] = value;
+                }
+                idx
+            }).collect()
+    }
+}
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+/// Returns the index of the oscillator with the given id like `OSC1`.
+fn oscillator(id: &str) -> Option<usize> {
+    if !id.starts_with("OSC") {
+        return None;
+    }
+    match id["OSC".len()..].parse::<usize>() {
+        Ok(osc) if osc > 0 && osc <= MAX_OSC_CNT => Some(osc - 1),
+        _ => None,
+    }
+}
+
+/// Returns the oscillator and the kind of an envelope id like `ADSR-POSITION-OSC1`.
+fn envelope_kind(id: &str) -> Option<(Option<usize>, Envelope)> {
+    let mut parts = id.split('-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("ADSR"), Some("POSITION"), Some(osc)) => {
+            oscillator(osc).map(|osc| (Some(osc), Envelope::Position))
+        }
+        (Some("ADSR"), Some(osc), None) => oscillator(osc).map(|osc| (Some(osc), Envelope::Volume)),
+        _ => None,
+    }
+}
+
+/// Returns the menu entry of a step length.
+pub fn division_selection(division: Float) -> Option<usize> {
+    DIVISIONS
+        .iter()
+        .position(|selection| (selection - division).abs() < 1e-9)
+}
+
+#[test]
+fn test_registry() {
+    let mut registry = Registry::new();
+    assert_eq!(
+        registry.parameters().len(),
+        MAX_OSC_CNT * (OSC_PARAMETERS.len() + 2 * MAX_OSC_CNT) + GLOBAL_PARAMETERS.len()
+    );
+    assert_eq!(registry.addresses.len(), registry.parameters().len());
+    let attack = registry.find("/ytterbium/osc/2/adsr/attack").unwrap();
+    assert_relative_eq!(registry.value(attack), 0.02, epsilon = 1e-9);
+
+    // every parameter is restored from the event that applies it
+    for idx in 0..registry.parameters().len() {
+        let (min, max) = registry.parameters()[idx].range;
+        let value = (min + 0.75 * (max - min)).round();
+        let events = registry.set(&[(idx, value)]);
+        assert_eq!(events.len(), 1, "{}", registry.parameters()[idx].address);
+        let mut restored = Registry::new();
+        assert!(restored.update(&events[0]).contains(&idx));
+        assert_relative_eq!(restored.value(idx), registry.value(idx), epsilon = 1e-9);
+    }
+
+    // the envelope times share an event, out of range values are clamped
+    let decay = registry.find("/ytterbium/osc/2/adsr/decay").unwrap();
+    match registry.set(&[(attack, 0.5), (decay, 100.0)])[..] {
+        [ControlEvent::ADSR {
+            ref id,
+            attack,
+            decay,
+            ..
+        }] => {
+            assert_eq!(id, "ADSR-OSC2");
+            assert_relative_eq!(attack, 0.5);
+            assert_relative_eq!(decay, 60.0);
+        }
+        ref events => panic!("unexpected events {:?}", events),
+    }
+    assert!(registry.update(&ControlEvent::Phase {
+        id: "OSC9".to_owned(),
+        phase: 0.5,
+    }).is_empty());
+}
@@ -0,0 +1,383 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use errors::RunError;
+use io::{PitchConvert, CONCERT_A};
+use types::*;
+
+/// Reads a file into a string, Scala files may contain Latin-1 characters in comments.
+fn read_file<P: AsRef<Path>>(path: P) -> Result<String, RunError> {
+    let mut file = try!(File::open(path).map_err(RunError::IoError));
+    let mut bytes = Vec::new();
+    try!(file.read_to_end(&mut bytes).map_err(RunError::IoError));
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Returns the lines of a Scala file without comments.
+fn content_lines(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('!'))
+        .collect()
+}
+
+/// Returns the first word of a line, the rest of a Scala line is ignored.
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+fn parse_number<T: ::std::str::FromStr>(line: Option<&&str>, name: &str) -> Result<T, RunError> {
+    line.and_then(|line| first_word(line).parse::<T>().ok())
+        .ok_or_else(|| RunError::ScalaError(format!("missing or invalid {}", name)))
+}
+
+/// Division that rounds towards negative infinity, returns quotient and remainder.
+fn floor_div(lhs: isize, rhs: isize) -> (isize, isize) {
+    let remainder = ((lhs % rhs) + rhs) % rhs;
+    ((lhs - remainder) / rhs, remainder)
+}
+
+/// Builds the frequency table from a Scala scale and an optional keyboard mapping file.
+pub fn load_tuning<P: AsRef<Path>>(scale: P, mapping: Option<P>) -> Result<Vec<Float>, RunError> {
+    let scale = try!(Scale::load(scale));
+    let mapping = match mapping {
+        Some(path) => try!(KeyboardMapping::load(path)),
+        None => KeyboardMapping::default(),
+    };
+    scale.table(&mapping)
+}
+
+/// A scale in the Scala `.scl` format.
+/// See http://www.huygens-fokker.org/scala/scl_format.html
+#[derive(Debug, Clone)]
+pub struct Scale {
+    /// Pitches of the scale degrees in cents, without the implicit unison.
+    /// The last pitch is the period of the scale, usually the octave.
+    pitches: Vec<Float>,
+}
+impl Scale {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RunError> {
+        Scale::parse(&try!(read_file(path)))
+    }
+
+    /// Parses the content of a scale file, the first line is a description that is skipped.
+    pub fn parse(content: &str) -> Result<Self, RunError> {
+        let lines = content_lines(content);
+        let count = try!(parse_number::<usize>(lines.get(1), "note count"));
+        if count == 0 {
+            return Err(RunError::ScalaError("the scale has no notes".to_owned()));
+        }
+        let pitches = try!(
+            lines
+                .iter()
+                .skip(2)
+                .map(|line| first_word(line))
+                .filter(|pitch| !pitch.is_empty())
+                .map(Scale::parse_pitch)
+                .collect::<Result<Vec<Float>, RunError>>()
+        );
+        if pitches.len() != count {
+            return Err(RunError::ScalaError(format!(
+                "expected {} notes, found {}",
+                count,
+                pitches.len()
+            )));
+        }
+        Ok(Scale { pitches })
+    }
+
+    /// Parses a pitch in cents, if it contains a period, or as ratio otherwise.
+    fn parse_pitch(pitch: &str) -> Result<Float, RunError> {
+        let invalid = || RunError::ScalaError(format!("invalid pitch: {}", pitch));
+        if pitch.contains('.') {
+            return pitch.parse::<Float>().map_err(|_| invalid());
+        }
+        let mut parts = pitch.splitn(2, '/');
+        let numerator = try!(parts.next().unwrap_or("").parse::<Float>().map_err(|_| invalid()));
+        let denominator = match parts.next() {
+            Some(denominator) => try!(denominator.parse::<Float>().map_err(|_| invalid())),
+            None => 1.0,
+        };
+        if numerator <= 0.0 || denominator <= 0.0 {
+            return Err(invalid());
+        }
+        Ok(1200.0 * (numerator / denominator).log2())
+    }
+
+    /// Returns the pitch of a scale degree in cents, degrees outside of
+    /// the first period are transposed by multiples of the period.
+    fn degree_cents(&self, degree: isize) -> Float {
+        let (period, idx) = floor_div(degree, self.pitches.len() as isize);
+        let cents = if idx == 0 {
+            0.0
+        } else {
+            self.pitches[idx as usize - 1]
+        };
+        period as Float * self.pitches[self.pitches.len() - 1] + cents
+    }
+
+    /// Builds the frequency table for all 128 keys. Keys that are not mapped
+    /// keep their 12-TET frequency.
+    pub fn table(&self, mapping: &KeyboardMapping) -> Result<Vec<Float>, RunError> {
+        let reference = try!(
+            mapping
+                .degree(mapping.reference_key, self.pitches.len())
+                .map(|degree| self.degree_cents(degree))
+                .ok_or_else(|| RunError::ScalaError("the reference key is not mapped".to_owned()))
+        );
+        let equal_temperament = PitchConvert::default();
+        Ok((0..128)
+            .map(|key| {
+                if key < mapping.first_key || key > mapping.last_key {
+                    return equal_temperament.key_to_hz(key);
+                }
+                match mapping.degree(key, self.pitches.len()) {
+                    Some(degree) => {
+                        let cents = self.degree_cents(degree) - reference;
+                        mapping.reference_freq * Float::powf(2.0, cents / 1200.0)
+                    }
+                    None => equal_temperament.key_to_hz(key),
+                }
+            }).collect())
+    }
+}
+
+/// A keyboard mapping in the Scala `.kbm` format, assigns scale degrees to keys.
+/// See http://www.huygens-fokker.org/scala/help.htm#mappings
+#[derive(Debug, Clone)]
+pub struct KeyboardMapping {
+    first_key: u8,
+    last_key: u8,
+    /// The key that plays the first scale degree.
+    middle_key: u8,
+    reference_key: u8,
+    reference_freq: Float,
+    /// The degree that is the period of the mapping, `0` for the scale period.
+    octave_degree: usize,
+    /// Scale degrees of the keys starting at the middle key, `None` for unmapped keys.
+    /// An empty mapping maps consecutive keys to consecutive degrees.
+    mapping: Vec<Option<usize>>,
+}
+impl KeyboardMapping {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RunError> {
+        KeyboardMapping::parse(&try!(read_file(path)))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, RunError> {
+        let lines = content_lines(content)
+            .into_iter()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>();
+        let size = try!(parse_number::<usize>(lines.first(), "map size"));
+        let first_key = try!(parse_number::<u8>(lines.get(1), "first key"));
+        let last_key = try!(parse_number::<u8>(lines.get(2), "last key"));
+        let middle_key = try!(parse_number::<u8>(lines.get(3), "middle key"));
+        let reference_key = try!(parse_number::<u8>(lines.get(4), "reference key"));
+        let reference_freq = try!(parse_number::<Float>(lines.get(5), "reference frequency"));
+        let octave_degree = try!(parse_number::<usize>(lines.get(6), "octave degree"));
+        let entries = &lines[7..];
+        if entries.len() > size {
+            return Err(RunError::ScalaError(format!(
+                "expected {} keys, found {}",
+                size,
+                entries.len()
+            )));
+        }
+        let mut mapping = Vec::with_capacity(size);
+        for entry in entries {
+            mapping.push(match first_word(entry) {
+                "x" => None,
+                degree => Some(try!(degree.parse::<usize>().map_err(|_| {
+                    RunError::ScalaError(format!("invalid scale degree: {}", degree))
+                }))),
+            });
+        }
+        // missing entries at the end are unmapped
+        mapping.resize(size, None);
+        if reference_freq <= 0.0 {
+            return Err(RunError::ScalaError("invalid reference frequency".to_owned()));
+        }
+        Ok(KeyboardMapping {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_freq,
+            octave_degree,
+            mapping,
+        })
+    }
+
+    /// Returns the scale degree of a key relative to the middle key.
+    fn degree(&self, key: u8, scale_len: usize) -> Option<isize> {
+        let distance = key as isize - self.middle_key as isize;
+        if self.mapping.is_empty() {
+            return Some(distance);
+        }
+        let (period, idx) = floor_div(distance, self.mapping.len() as isize);
+        let octave_degree = if self.octave_degree == 0 {
+            scale_len
+        } else {
+            self.octave_degree
+        };
+        self.mapping[idx as usize].map(|degree| period * octave_degree as isize + degree as isize)
+    }
+}
+impl Default for KeyboardMapping {
+    /// Maps the first degree to the middle C and tunes A4 to 440 Hz.
+    fn default() -> Self {
+        KeyboardMapping {
+            first_key: 0,
+            last_key: 127,
+            middle_key: 60,
+            reference_key: 69,
+            reference_freq: 440.0,
+            octave_degree: 0,
+            mapping: Vec::new(),
+        }
+    }
+}
+
+/// Converts a MIDI Tuning Standard frequency, a key and a 14 bit fraction of a semitone.
+fn mts_freq(data: &[u8]) -> Option<Float> {
+    // 7F 7F 7F means no change
+    if data[0] > 0x7F || data[1] > 0x7F || data[2] > 0x7F || data.iter().all(|&b| b == 0x7F) {
+        return None;
+    }
+    let fraction = Float::from((u16::from(data[1]) << 7) | u16::from(data[2])) / 16384.0;
+    let semitones = Float::from(data[0]) + fraction - 69.0;
+    Some(CONCERT_A * Float::powf(2.0, semitones / 12.0))
+}
+
+/// Parses a MIDI Tuning Standard SysEx message into the new frequencies of the retuned keys.
+/// Bulk tuning dumps and single note tuning changes are supported, with and without bank.
+/// Tuning programs and banks are not distinguished, every message changes the current tuning.
+/// See https://www.midi.org/specifications-old/item/the-midi-1-0-specification
+pub fn parse_tuning_sysex(data: &[u8]) -> Option<Vec<(u8, Float)>> {
+    // F0 <7E|7F> <device> 08 <sub id> ... F7
+    if data.len() < 6 || data[0] != 0xF0 || data[data.len() - 1] != 0xF7 || data[3] != 0x08 {
+        return None;
+    }
+    let (realtime, body) = (data[1] == 0x7F, &data[4..data.len() - 1]);
+    if data[1] != 0x7E && !realtime {
+        return None;
+    }
+    // length of the sub id, bank and program number
+    let (header, bulk) = match (body[0], realtime) {
+        (0x01, false) => (2, true),
+        (0x04, false) => (3, true),
+        (0x02, _) => (2, false),
+        (0x07, _) => (3, false),
+        _ => return None,
+    };
+    // a bulk dump has at least a name, a single note change at least a count
+    if body.len() <= header {
+        return None;
+    }
+    let notes = &body[header..];
+    if bulk {
+        // 16 bytes name, 3 bytes for each key and a checksum, which is ignored
+        if notes.len() != 16 + 3 * 128 + 1 {
+            return None;
+        }
+        Some(
+            notes[16..16 + 3 * 128]
+                .chunks(3)
+                .enumerate()
+                .filter_map(|(key, freq)| mts_freq(freq).map(|freq| (key as u8, freq)))
+                .collect(),
+        )
+    } else {
+        // <count> [<key> <freq>]
+        let count = notes[0] as usize;
+        if notes.len() < 1 + 4 * count {
+            return None;
+        }
+        Some(
+            notes[1..1 + 4 * count]
+                .chunks(4)
+                .filter(|change| change[0] < 128)
+                .filter_map(|change| mts_freq(&change[1..]).map(|freq| (change[0], freq)))
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn test_equal_temperament_scale() {
+    let scale = Scale::parse(
+        "! 12-tet.scl\n!\n12-TET\n 12\n!\n100.0\n200.\n300.0\n400.0\n500.0\n600.0\n\
+         700.0\n800.0\n900.0\n1000.0\n1100.0\n2/1\n",
+    ).unwrap();
+    let table = scale.table(&KeyboardMapping::default()).unwrap();
+    let equal_temperament = PitchConvert::default();
+    for key in 0..128 {
+        assert_relative_eq!(table[key as usize], equal_temperament.key_to_hz(key), epsilon = 1e-6);
+    }
+}
+
+#[test]
+fn test_just_intonation_mapping() {
+    let scale = Scale::parse("Just major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2\n").unwrap();
+    // white keys only, C4 is tuned to 261.6 Hz
+    let mapping = KeyboardMapping::parse(
+        "! white keys\n12\n0\n127\n60\n60\n261.6\n7\n0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n",
+    ).unwrap();
+    let table = scale.table(&mapping).unwrap();
+    assert_relative_eq!(table[60], 261.6, epsilon = 1e-6);
+    assert_relative_eq!(table[64], 261.6 * 5.0 / 4.0, epsilon = 1e-6);
+    assert_relative_eq!(table[67], 261.6 * 3.0 / 2.0, epsilon = 1e-6);
+    assert_relative_eq!(table[72], 261.6 * 2.0, epsilon = 1e-6);
+    assert_relative_eq!(table[59], 261.6 * 15.0 / 16.0, epsilon = 1e-6);
+    // black keys keep their 12-TET frequency
+    assert_relative_eq!(table[61], PitchConvert::default().key_to_hz(61));
+    assert!(Scale::parse("broken\n2\n3/2\n").is_err());
+}
+
+#[test]
+fn test_tuning_sysex() {
+    // single note tuning change: key 60 to 61.5, key 69 to 440 Hz
+    let change = [
+        0xF0, 0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02, 60, 61, 0x40, 0x00, 69, 69, 0x00, 0x00, 0xF7,
+    ];
+    let tunings = parse_tuning_sysex(&change).unwrap();
+    assert_eq!(tunings.len(), 2);
+    assert_eq!(tunings[0].0, 60);
+    assert_relative_eq!(tunings[0].1, 440.0 * Float::powf(2.0, -7.5 / 12.0), epsilon = 1e-9);
+    assert_relative_eq!(tunings[1].1, 440.0);
+    // bulk dump that shifts every key up a semitone except the last one
+    let mut dump = vec![0xF0, 0x7E, 0x00, 0x08, 0x01, 0x00];
+    dump.extend_from_slice(b"shifted by one  ");
+    for key in 0..127 {
+        dump.extend_from_slice(&[key + 1, 0, 0]);
+    }
+    dump.extend_from_slice(&[0x7F, 0x7F, 0x7F, 0x00, 0xF7]);
+    let tunings = parse_tuning_sysex(&dump).unwrap();
+    assert_eq!(tunings.len(), 127);
+    assert_relative_eq!(tunings[68].1, 440.0, epsilon = 1e-9);
+    assert!(parse_tuning_sysex(&dump[..100]).is_none());
+}
+
+#[test]
+fn test_truncated_tuning_sysex() {
+    // sub ids and the length of their bank, program and name or count
+    for &(realtime, sub_id, header) in &[
+        (0x7E, 0x01, 17),
+        (0x7E, 0x04, 18),
+        (0x7F, 0x02, 2),
+        (0x7F, 0x07, 3),
+    ] {
+        for len in 0..header {
+            let mut message = vec![0xF0, realtime, 0x00, 0x08, sub_id];
+            message.extend(vec![0x00; len]);
+            message.push(0xF7);
+            assert!(parse_tuning_sysex(&message).is_none());
+        }
+    }
+    // the count announces more changes than the message contains
+    let change = [
+        0xF0, 0x7F, 0x7F, 0x08, 0x07, 0x00, 0x00, 0x02, 60, 61, 0x40, 0x00, 0xF7,
+    ];
+    assert!(parse_tuning_sysex(&change).is_none());
+}
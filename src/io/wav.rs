@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use errors::RunError;
+use types::*;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The audio data of a WAV file, all channels are mixed down to mono.
+#[derive(Debug)]
+pub struct WavFile {
+    pub sample_rate: u32,
+    pub samples: Vec<Float>,
+    /// Samples per wavetable frame as stored in the `clm ` chunk of wavetable editors.
+    pub frame_size: Option<usize>,
+}
+
+fn wav_error<S: Into<String>>(msg: S) -> RunError {
+    RunError::WavError(msg.into())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) | u16::from(data[offset + 1]) << 8
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(data, offset)) | u32::from(read_u16(data, offset + 2)) << 16
+}
+
+pub fn read_wav<P: AsRef<Path>>(path: P) -> Result<WavFile, RunError> {
+    let mut file = try!(File::open(path).map_err(RunError::IoError));
+    let mut data = Vec::new();
+    try!(file.read_to_end(&mut data).map_err(RunError::IoError));
+    parse_wav(&data)
+}
+
+/// Writes mono 32 bit float samples, the frame size of a wavetable is stored in a
+/// `clm ` chunk.
+pub fn write_wav<P: AsRef<Path>>(
+    path: P,
+    samples: &[Float],
+    sample_rate: u32,
+    frame_size: Option<usize>,
+) -> Result<(), RunError> {
+    let mut data = Vec::with_capacity(64 + 4 * samples.len());
+    data.extend_from_slice(b"RIFF\x00\x00\x00\x00WAVE");
+    data.extend_from_slice(b"fmt ");
+    push_u32(&mut data, 16);
+    push_u16(&mut data, FORMAT_FLOAT);
+    push_u16(&mut data, 1);
+    push_u32(&mut data, sample_rate);
+    push_u32(&mut data, 4 * sample_rate);
+    push_u16(&mut data, 4);
+    push_u16(&mut data, 32);
+    if let Some(frame_size) = frame_size {
+        let text = format!("<!>{} 00000000 ytterbium", frame_size);
+        data.extend_from_slice(b"clm ");
+        push_u32(&mut data, text.len() as u32);
+        data.extend_from_slice(text.as_bytes());
+        if text.len() % 2 == 1 {
+            data.push(0);
+        }
+    }
+    data.extend_from_slice(b"data");
+    push_u32(&mut data, 4 * samples.len() as u32);
+    for sample in samples {
+        push_u32(&mut data, (*sample as f32).to_bits());
+    }
+    let riff_size = data.len() as u32 - 8;
+    data[4..8].copy_from_slice(&[
+        riff_size as u8,
+        (riff_size >> 8) as u8,
+        (riff_size >> 16) as u8,
+        (riff_size >> 24) as u8,
+    ]);
+    let file = try!(File::create(path).map_err(RunError::IoError));
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&data).map_err(RunError::IoError)
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    push_u16(data, value as u16);
+    push_u16(data, (value >> 16) as u16);
+}
+
+/// Parses a RIFF WAVE file with integer PCM or floating point samples.
+pub fn parse_wav(data: &[u8]) -> Result<WavFile, RunError> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(wav_error("not a RIFF WAVE file"));
+    }
+    let mut format = None;
+    let mut samples = None;
+    let mut frame_size = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = read_u32(data, offset + 4) as usize;
+        let start = offset + 8;
+        let end = ::std::cmp::min(start + size, data.len());
+        let chunk = &data[start..end];
+        match id {
+            b"fmt " => {
+                if chunk.len() < 16 {
+                    return Err(wav_error("invalid fmt chunk"));
+                }
+                let mut tag = read_u16(chunk, 0);
+                if tag == FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+                    // the first two bytes of the sub format GUID are the format tag
+                    tag = read_u16(chunk, 24);
+                }
+                format = Some((
+                    tag,
+                    read_u16(chunk, 2),
+                    read_u32(chunk, 4),
+                    read_u16(chunk, 14),
+                ));
+            }
+            b"data" => samples = Some(chunk),
+            b"clm " => {
+                // e.g. "<!>2048 01000000 wavetable (www.xferrecords.com)"
+                let text = String::from_utf8_lossy(chunk);
+                frame_size = text
+                    .find("<!>")
+                    .and_then(|idx| text[idx + 3..].split_whitespace().next())
+                    .and_then(|size| size.parse::<usize>().ok())
+                    .filter(|&size| size > 0);
+            }
+            _ => {}
+        }
+        // chunks are padded to an even size
+        offset = start + size + size % 2;
+    }
+    let (tag, channels, sample_rate, bits) =
+        try!(format.ok_or_else(|| wav_error("missing fmt chunk")));
+    let samples = try!(samples.ok_or_else(|| wav_error("missing data chunk")));
+    if channels == 0 {
+        return Err(wav_error("no channels"));
+    }
+    let bytes = bits as usize / 8;
+    let decode: fn(&[u8]) -> Float = match (tag, bits) {
+        (FORMAT_PCM, 8) => |b| (Float::from(b[0]) - 128.0) / 128.0,
+        (FORMAT_PCM, 16) => |b| Float::from(read_u16(b, 0) as i16) / 32_768.0,
+        (FORMAT_PCM, 24) => |b| {
+            Float::from((u32::from(read_u16(b, 0)) << 8 | u32::from(b[2]) << 24) as i32)
+                / 2_147_483_648.0
+        },
+        (FORMAT_PCM, 32) => |b| Float::from(read_u32(b, 0) as i32) / 2_147_483_648.0,
+        (FORMAT_FLOAT, 32) => |b| Float::from(f32::from_bits(read_u32(b, 0))),
+        (FORMAT_FLOAT, 64) => {
+            |b| f64::from_bits(u64::from(read_u32(b, 0)) | u64::from(read_u32(b, 4)) << 32)
+        }
+        _ => {
+            return Err(wav_error(format!(
+                "unsupported sample format {} with {} bits",
+                tag, bits
+            )))
+        }
+    };
+    let block = bytes * channels as usize;
+    let samples = samples
+        .chunks(block)
+        .filter(|frame| frame.len() == block)
+        .map(|frame| frame.chunks(bytes).map(decode).sum::<Float>() / Float::from(channels))
+        .collect();
+    Ok(WavFile {
+        sample_rate,
+        samples,
+        frame_size,
+    })
+}
+
+#[test]
+fn test_parse_wav() {
+    // 16 bit stereo with a clm chunk and an odd sized chunk before the data
+    let mut data = Vec::new();
+    data.extend_from_slice(b"RIFF\x00\x00\x00\x00WAVE");
+    data.extend_from_slice(b"fmt \x10\x00\x00\x00");
+    data.extend_from_slice(&[1, 0, 2, 0, 0x80, 0xBB, 0, 0, 0, 0xEE, 2, 0, 4, 0, 16, 0]);
+    data.extend_from_slice(b"clm \x0B\x00\x00\x00<!>4 010000");
+    data.push(0);
+    data.extend_from_slice(b"data\x10\x00\x00\x00");
+    for &(left, right) in &[(0i16, 0i16), (16_384, 16_384), (-32_768, 0), (0, 32_767)] {
+        for sample in &[left, right] {
+            data.push(*sample as u8);
+            data.push((*sample >> 8) as u8);
+        }
+    }
+    let wav = parse_wav(&data).unwrap();
+    assert_eq!(wav.sample_rate, 48_000);
+    assert_eq!(wav.frame_size, Some(4));
+    assert_eq!(wav.samples.len(), 4);
+    for (sample, expected) in wav.samples.iter().zip(&[0.0, 0.5, -0.5, 0.5]) {
+        assert_relative_eq!(*sample, *expected, epsilon = 1e-4);
+    }
+    assert!(parse_wav(b"RIFF\x00\x00\x00\x00WAVE").is_err());
+}
+
+#[test]
+fn test_write_wav() {
+    let path = ::std::env::temp_dir().join(format!(
+        "ytterbium-{}-write-test.wav",
+        env!("CARGO_PKG_VERSION")
+    ));
+    let samples = [0.0, 0.25, -0.5, 1.0];
+    write_wav(&path, &samples, 44_100, Some(2)).unwrap();
+    let wav = read_wav(&path).unwrap();
+    assert_eq!(wav.sample_rate, 44_100);
+    assert_eq!(wav.frame_size, Some(2));
+    assert_eq!(wav.samples, samples.to_vec());
+}
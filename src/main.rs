@@ -10,8 +10,10 @@ extern crate clap;
 
 use std::cmp;
 use std::collections::HashMap;
+use std::fs;
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -26,11 +28,12 @@ mod event;
 mod dsp;
 mod errors;
 mod io;
+mod patch;
 mod types;
 use dsp::*;
 use errors::RunError;
-use event::Controllable;
-use io::{MidiReceiver, OscReceiver, Receiver};
+use event::{ControlEvent, Controllable};
+use io::{MidiReceiver, OscQueryServer, OscReceiver, OscSender, Receiver};
 use types::*;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -46,7 +49,17 @@ macro_rules! printerr(
 
 struct Args {
     socket_addr_in: SocketAddr,
+    port_out: u16,
+    feedback_addr: Option<SocketAddr>,
+    query_port: Option<u16>,
     sample_rate: usize,
+    patch_dir: PathBuf,
+    scale: Option<PathBuf>,
+    keyboard_map: Option<PathBuf>,
+    wavetable_dir: Option<PathBuf>,
+    wavetable_cache: Option<PathBuf>,
+    rebuild_wavetables: bool,
+    import_dx7: Option<PathBuf>,
 }
 
 /// Parses and validates the command line arguments.
@@ -64,11 +77,24 @@ fn get_args() -> Args {
     let ports_arg = clap::Arg::with_name("ports")
         .long("ports")
         .short("p")
-        .required(true)
+        .required_unless("import-dx7")
         .takes_value(true)
         .number_of_values(2)
         .value_names(&["in", "out"])
         .help("OSC listening and send port.");
+    let feedback_address_arg = clap::Arg::with_name("feedback-address")
+        .long("feedback-address")
+        .takes_value(true)
+        .value_name("ip-address:port")
+        .help(
+            "Address of the controller that receives the OSC feedback, defaults to the \
+             sender of the last OSC message and the send port.",
+        );
+    let query_port_arg = clap::Arg::with_name("query-port")
+        .long("query-port")
+        .takes_value(true)
+        .value_name("port")
+        .help("Port of the OSCQuery server that describes the OSC parameters.");
     let sample_rate_arg = clap::Arg::with_name("sample-rate")
         .long("sample-rate")
         .short("s")
@@ -77,12 +103,56 @@ fn get_args() -> Args {
         .default_value("48000")
         .possible_values(&["44100", "48000", "88200", "96000"])
         .help("Playback sample-rate");
+    let patch_dir_arg = clap::Arg::with_name("patch-dir")
+        .long("patch-dir")
+        .takes_value(true)
+        .value_name("directory")
+        .default_value(".")
+        .help("Directory where patches are stored.");
+    let scale_arg = clap::Arg::with_name("scale")
+        .long("scale")
+        .takes_value(true)
+        .value_name("scl-file")
+        .help("Scala scale file used for tuning.");
+    let keyboard_map_arg = clap::Arg::with_name("keyboard-map")
+        .long("keyboard-map")
+        .takes_value(true)
+        .value_name("kbm-file")
+        .requires("scale")
+        .help("Scala keyboard mapping for the scale.");
+    let wavetable_dir_arg = clap::Arg::with_name("wavetable-dir")
+        .long("wavetable-dir")
+        .takes_value(true)
+        .value_name("directory")
+        .help("Directory with WAV wavetables that are loaded on startup.");
+    let wavetable_cache_arg = clap::Arg::with_name("wavetable-cache")
+        .long("wavetable-cache")
+        .takes_value(true)
+        .value_name("directory")
+        .help("Directory for generated wavetables, defaults to the XDG cache directory.");
+    let rebuild_wavetables_arg = clap::Arg::with_name("rebuild-wavetables")
+        .long("rebuild-wavetables")
+        .help("Regenerates the cached wavetables.");
+    let import_dx7_arg = clap::Arg::with_name("import-dx7")
+        .long("import-dx7")
+        .takes_value(true)
+        .value_name("syx-file")
+        .help("Saves the voices of a DX7 SysEx dump as patches in the patch directory and exits.");
     let args = clap::App::new("ytterbium")
         .version(VERSION)
         .author("Andreas Linz <klingt.net@gmail.com>")
         .arg(address_arg)
         .arg(ports_arg)
+        .arg(feedback_address_arg)
+        .arg(query_port_arg)
         .arg(sample_rate_arg)
+        .arg(patch_dir_arg)
+        .arg(scale_arg)
+        .arg(keyboard_map_arg)
+        .arg(wavetable_dir_arg)
+        .arg(wavetable_cache_arg)
+        .arg(rebuild_wavetables_arg)
+        .arg(import_dx7_arg)
         .get_matches();
 
     let sample_rate = args
@@ -95,9 +165,11 @@ fn get_args() -> Args {
             process::exit(1)
         }
     };
+    // the ports are not needed for an import
     let ports = args
         .values_of("ports")
-        .unwrap()
+        .into_iter()
+        .flatten()
         .map(|port| match port.parse::<u16>() {
             Ok(val) => val,
             Err(err) => {
@@ -105,16 +177,63 @@ fn get_args() -> Args {
                 process::exit(1)
             }
         }).collect::<Vec<u16>>();
-    let socket_addr_in = SocketAddr::new(ip_addr, ports[0]);
+    let socket_addr_in = SocketAddr::new(ip_addr, ports.first().cloned().unwrap_or(0));
+    let port_out = ports.get(1).cloned().unwrap_or(0);
+    let feedback_addr = args
+        .value_of("feedback-address")
+        .map(|addr| match SocketAddr::from_str(addr) {
+            Ok(val) => val,
+            Err(err) => {
+                printerr!("Bad feedback address: {}", err);
+                process::exit(1)
+            }
+        });
+    let query_port = args
+        .value_of("query-port")
+        .map(|port| match port.parse::<u16>() {
+            Ok(val) => val,
+            Err(err) => {
+                printerr!("Bad query port, must be in range [0, 65535]: {}", err);
+                process::exit(1)
+            }
+        });
+    let patch_dir = PathBuf::from(args.value_of("patch-dir").unwrap());
+    let scale = args.value_of("scale").map(PathBuf::from);
+    let keyboard_map = args.value_of("keyboard-map").map(PathBuf::from);
+    let wavetable_dir = args.value_of("wavetable-dir").map(PathBuf::from);
+    let wavetable_cache = args
+        .value_of("wavetable-cache")
+        .map(PathBuf::from)
+        .or_else(WavetableCache::default_dir);
+    let rebuild_wavetables = args.is_present("rebuild-wavetables");
+    let import_dx7 = args.value_of("import-dx7").map(PathBuf::from);
 
     Args {
         socket_addr_in,
+        port_out,
+        feedback_addr,
+        query_port,
         sample_rate,
+        patch_dir,
+        scale,
+        keyboard_map,
+        wavetable_dir,
+        wavetable_cache,
+        rebuild_wavetables,
+        import_dx7,
     }
 }
 
 fn main() {
     let args = get_args();
+    if let Some(ref path) = args.import_dx7 {
+        import_dx7(path, &args.patch_dir)
+            .map_err(|err| {
+                printerr!("{:?}", err);
+                process::exit(1)
+            }).unwrap();
+        return;
+    }
     run(&args)
         .map_err(|err| {
             printerr!("{:?}", err);
@@ -126,21 +245,95 @@ fn run(args: &Args) -> Result<(), RunError> {
     let buf = rb::SpscRb::new(BUF_SIZE);
     let (producer, consumer) = (buf.producer(), buf.consumer());
     let (tx_receiver, rx_dsp) = mpsc::channel();
+    let (tx_feedback, rx_feedback) = mpsc::channel();
+    // the events that are not sent by the controller are also reported to it
+    let (tx_forward, rx_forward) = mpsc::channel();
+    let (tx_patches, rx_patches) = mpsc::channel();
     let audio_init = Arc::new(Barrier::new(1));
-    let mut handles = HashMap::with_capacity(5);
+    let mut handles = HashMap::with_capacity(9);
     let quit = Arc::new(AtomicBool::new(false));
 
+    if let Some(ref scale) = args.scale {
+        // the tuning is applied before any other event is handled
+        let table = try!(io::load_tuning(scale, args.keyboard_map.as_ref()));
+        tx_receiver
+            .send(ControlEvent::Tuning(Arc::new(table)))
+            .unwrap();
+    }
+    if let Some(ref dir) = args.wavetable_dir {
+        for event in try!(load_wavetable_dir(dir, args.sample_rate)) {
+            tx_receiver.send(event).unwrap();
+        }
+    }
+
+    // spectra are queued by the osc receiver and installed by the voices
+    let spectrum_renderer = SpectrumRenderer::new(LOWEST_FREQ, args.sample_rate);
+    let mut osc_receiver = try!(OscReceiver::new(
+        args.socket_addr_in,
+        args.sample_rate,
+        args.patch_dir.clone(),
+        spectrum_renderer.queue(),
+        tx_feedback.clone()
+    ));
+    if let Some(port) = args.query_port {
+        let addr = SocketAddr::new(args.socket_addr_in.ip(), port);
+        let mut query_server = try!(OscQueryServer::new(
+            addr,
+            args.socket_addr_in.port(),
+            osc_receiver.remote()
+        ));
+        handles.insert(
+            "oscquery",
+            thread::Builder::new()
+                .name("oscquery".to_owned())
+                .spawn({
+                    let tx = tx_forward.clone();
+                    move || query_server.receive_and_send(tx)
+                }).unwrap(),
+        );
+    }
+    handles.insert(
+        "feedback",
+        thread::Builder::new()
+            .name("feedback".to_owned())
+            .spawn({
+                let remote = osc_receiver.remote();
+                let feedback_addr = args.feedback_addr;
+                let port_out = args.port_out;
+                move || {
+                    let mut osc_sender = OscSender::new(feedback_addr, port_out, remote).unwrap();
+                    osc_sender.send_events(rx_feedback)
+                }
+            }).unwrap(),
+    );
+
+    handles.insert(
+        "forward",
+        thread::Builder::new()
+            .name("forward".to_owned())
+            .spawn({
+                let tx = tx_receiver.clone();
+                move || forward_events(&rx_forward, &tx, &tx_feedback)
+            }).unwrap(),
+    );
+
+    handles.insert(
+        "patches",
+        thread::Builder::new()
+            .name("patches".to_owned())
+            .spawn({
+                let patch_dir = args.patch_dir.clone();
+                move || save_patches(&patch_dir, &rx_patches)
+            }).unwrap(),
+    );
+
     handles.insert(
         "osc",
         thread::Builder::new()
             .name("osc".to_owned())
             .spawn({
                 let tx = tx_receiver.clone();
-                let socket_addr = args.socket_addr_in;
-                move || {
-                    let mut osc_receiver = OscReceiver::new(socket_addr).unwrap();
-                    osc_receiver.receive_and_send(tx)
-                }
+                move || osc_receiver.receive_and_send(tx)
             }).unwrap(),
     );
 
@@ -149,7 +342,7 @@ fn run(args: &Args) -> Result<(), RunError> {
         thread::Builder::new()
             .name("midi".to_owned())
             .spawn({
-                let tx = tx_receiver.clone();
+                let tx = tx_forward.clone();
                 move || {
                     let mut midi_receiver = MidiReceiver::new().unwrap();
                     midi_receiver.receive_and_send(tx)
@@ -165,10 +358,18 @@ fn run(args: &Args) -> Result<(), RunError> {
                 let init = audio_init.clone();
                 let quit = quit.clone();
                 let sample_rate = args.sample_rate;
+                let cache =
+                    WavetableCache::new(args.wavetable_cache.clone(), args.rebuild_wavetables);
+                // the locked parameters that are not stored in a patch return to their defaults
+                let mut defaults = patch::Patch::default();
+                for event in io::Registry::new().events() {
+                    defaults.update(&event);
+                }
                 move || {
-                    let source = VoiceManager::new(MAX_VOICES, sample_rate);
+                    let source =
+                        VoiceManager::new(MAX_VOICES, sample_rate, &cache, spectrum_renderer);
                     let sink = BufferSink::new(producer, 64);
-                    let mut flow = Flow::new(source, sink, sample_rate);
+                    let mut flow = Flow::new(source, sink, sample_rate, defaults, tx_patches);
                     init.wait();
                     loop {
                         if quit.load(Ordering::Relaxed) {
@@ -256,6 +457,76 @@ fn run(args: &Args) -> Result<(), RunError> {
     Ok(())
 }
 
+/// Loads all WAV files of the directory as wavetables that are named after the file.
+/// Files that can't be loaded are skipped.
+fn load_wavetable_dir(dir: &Path, sample_rate: usize) -> Result<Vec<ControlEvent>, RunError> {
+    let mut paths = try!(fs::read_dir(dir).map_err(RunError::IoError))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |ext| ext.to_string_lossy().to_lowercase() == "wav")
+        }).collect::<Vec<_>>();
+    // the order of the files determines the wavetable indices
+    paths.sort();
+    let mut events = Vec::with_capacity(paths.len());
+    for path in paths {
+        let name = match path.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        match load_wavetable(&path, LOWEST_FREQ, sample_rate) {
+            Ok(frames) => events.push(ControlEvent::AddWavetable {
+                name,
+                frames: Arc::new(frames),
+            }),
+            Err(err) => printerr!("Could not load wavetable {:?}: {:?}", path, err),
+        }
+    }
+    Ok(events)
+}
+
+/// Passes the events of the receivers to the synth and reports them to the controller,
+/// until all receivers are closed. The events are reported from here, so that the audio
+/// thread doesn't have to copy them.
+fn forward_events(
+    rx: &mpsc::Receiver<ControlEvent>,
+    tx: &mpsc::Sender<ControlEvent>,
+    feedback: &mpsc::Sender<ControlEvent>,
+) {
+    for event in rx.iter() {
+        if OscSender::reports(&event) {
+            // the synth keeps playing without a controller
+            feedback.send(event.clone()).ok();
+        }
+        tx.send(event).unwrap();
+    }
+}
+
+/// Saves the patches that are sent by the audio thread until the channel is closed.
+fn save_patches(patch_dir: &Path, rx: &mpsc::Receiver<(String, patch::Patch)>) {
+    for (name, patch) in rx.iter() {
+        let path = patch::Patch::path(patch_dir, &name);
+        if let Err(err) = patch.save(&path) {
+            println!("Could not save patch {:?}: {:?}", path, err);
+        }
+    }
+}
+
+/// Saves the voices of a DX7 SysEx dump as patches, which are named after the file,
+/// the number of the voice and its name.
+fn import_dx7(path: &Path, patch_dir: &Path) -> Result<(), RunError> {
+    let voices = try!(io::load_dx7_sysex(path));
+    let bank = path
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    for (idx, voice) in voices.iter().enumerate() {
+        let name = format!("{}-{:02} {}", bank, idx + 1, voice.name);
+        try!(voice.to_patch().save(patch::Patch::path(patch_dir, &name)));
+        println!("Imported {}", name);
+    }
+    Ok(())
+}
+
 fn read_eof() -> Result<(), RunError> {
     let mut buffer = String::new();
     let mut eof = false;
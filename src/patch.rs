@@ -0,0 +1,195 @@
+extern crate bincode;
+
+use self::bincode::rustc_serialize::{decode_from, encode_into};
+use self::bincode::SizeLimit;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use dsp::Pattern;
+use errors::RunError;
+use event::ControlEvent;
+
+/// The sound parameters and sequencer patterns that can be stored and recalled.
+/// Parameters are stored as the last `ControlEvent` that changed them, the fields of
+/// combined parameters like the filter are stored separately.
+#[derive(Debug, Clone, Default, RustcDecodable, RustcEncodable)]
+pub struct Patch {
+    parameters: Vec<ControlEvent>,
+    pub patterns: Vec<Pattern>,
+}
+impl Patch {
+    /// Reads a patch from the given file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RunError> {
+        let file = try!(File::open(path).map_err(RunError::IoError));
+        let mut reader = BufReader::new(file);
+        decode_from(&mut reader, SizeLimit::Infinite).map_err(RunError::PatchDecodingError)
+    }
+
+    /// Writes the patch into the given file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RunError> {
+        let file = try!(File::create(path).map_err(RunError::IoError));
+        let mut writer = BufWriter::new(file);
+        encode_into(self, &mut writer, SizeLimit::Infinite).map_err(RunError::PatchEncodingError)
+    }
+
+    /// Returns the file for the patch with the given name in `dir`.
+    /// Path separators are removed from the name.
+    pub fn path<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+        let name = name
+            .chars()
+            .filter(|c| *c != '/' && *c != '\\')
+            .collect::<String>();
+        dir.as_ref().join(format!("{}.ytterbium-patch", name))
+    }
+
+    /// Stores the fields of the event if it changes a parameter. Only new fields are
+    /// allocated, the stored fields are overwritten in place.
+    pub fn update(&mut self, msg: &ControlEvent) {
+        let parameter = match msg.parameter() {
+            Some(parameter) => parameter,
+            None => return,
+        };
+        let fields = msg.fields();
+        for field in (0..8)
+            .map(|idx| 1 << idx)
+            .filter(|field| fields & field != 0)
+        {
+            let stored = self
+                .parameters
+                .iter()
+                .position(|event| event.fields() == field && event.parameter() == Some(parameter));
+            match stored {
+                Some(idx) => self.parameters[idx].merge(msg, field),
+                None => {
+                    let mut event = msg.clone();
+                    event.retain_fields(field);
+                    self.parameters.push(event);
+                }
+            }
+        }
+    }
+
+    /// Returns the stored fields of a parameter merged into a single event, see
+    /// `ControlEvent::parameter` for the names.
+    pub fn get(&self, name: &str, id: &str) -> Option<ControlEvent> {
+        let mut merged: Option<ControlEvent> = None;
+        let stored = self.parameters.iter().filter(|event| {
+            event.parameter().map_or(false, |parameter| {
+                parameter.name == name && parameter.id == id
+            })
+        });
+        for event in stored {
+            match merged {
+                Some(ref mut merged) => merged.merge(event, event.fields()),
+                None => merged = Some(event.clone()),
+            }
+        }
+        merged
+    }
+
+    /// Returns the stored parameter events in the order they were first set, each event
+    /// sets a single field.
+    pub fn parameters(&self) -> &[ControlEvent] {
+        &self.parameters
+    }
+}
+
+#[test]
+fn test_patch_update() {
+    let mut patch = Patch::default();
+    for freq in &[100.0, 200.0] {
+        patch.update(&ControlEvent::Filter {
+            filter_type: None,
+            freq: Some(*freq),
+            q: None,
+        });
+    }
+    patch.update(&ControlEvent::NoteOn {
+        key: 60,
+        velocity: 1.0,
+    });
+    patch.update(&ControlEvent::Tempo(90.0));
+    assert_eq!(patch.parameters().len(), 2);
+    match patch.get("FILTER", "") {
+        Some(ControlEvent::Filter { freq, .. }) => assert_eq!(freq, Some(200.0)),
+        _ => panic!("filter frequency was not stored"),
+    }
+    // a combined event only overrides the fields that it sets
+    patch.update(&ControlEvent::Filter {
+        filter_type: None,
+        freq: Some(300.0),
+        q: Some(2.0),
+    });
+    patch.update(&ControlEvent::Filter {
+        filter_type: None,
+        freq: None,
+        q: Some(4.0),
+    });
+    assert_eq!(patch.parameters().len(), 3);
+    match patch.get("FILTER", "") {
+        Some(ControlEvent::Filter {
+            filter_type,
+            freq,
+            q,
+        }) => {
+            assert_eq!(filter_type, None);
+            assert_eq!(freq, Some(300.0));
+            assert_eq!(q, Some(4.0));
+        }
+        _ => panic!("filter was not stored"),
+    }
+    // the stored events don't contain outdated values
+    let freqs = patch
+        .parameters()
+        .iter()
+        .filter_map(|event| match *event {
+            ControlEvent::Filter { freq, .. } => freq,
+            _ => None,
+        }).collect::<Vec<_>>();
+    assert_eq!(freqs, vec![300.0]);
+}
+
+#[test]
+fn test_patch_save_load() {
+    use dsp::{Breakpoint, EnvelopeCurve};
+
+    let mut patch = Patch::default();
+    patch.update(&ControlEvent::ADSR {
+        id: "ADSR-OSC1".to_owned(),
+        attack: 0.1,
+        decay: 0.2,
+        sustain: 0.5,
+        release: 1.0,
+    });
+    patch.update(&ControlEvent::SegmentEnvelope {
+        id: "ADSR-OSC1".to_owned(),
+        breakpoints: vec![Breakpoint {
+            time: 0.5,
+            level: 1.0,
+            curve: EnvelopeCurve::Exponential(0.3),
+        }],
+        sustain: Some(0),
+        loop_points: None,
+    });
+    patch.patterns.push(Pattern::default());
+    let path = Patch::path(::std::env::temp_dir(), "ytterbium/test");
+    patch.save(&path).unwrap();
+    let loaded = Patch::load(&path).unwrap();
+    assert_eq!(loaded.patterns.len(), 1);
+    match loaded.get("ADSR", "ADSR-OSC1") {
+        Some(ControlEvent::ADSR { attack, .. }) => assert_relative_eq!(attack, 0.1),
+        _ => panic!("envelope was not loaded"),
+    }
+    match loaded.get("SEGMENTS", "ADSR-OSC1") {
+        Some(ControlEvent::SegmentEnvelope {
+            ref breakpoints,
+            sustain,
+            ..
+        }) => {
+            assert_eq!(breakpoints[0].curve, EnvelopeCurve::Exponential(0.3));
+            assert_eq!(sustain, Some(0));
+        }
+        _ => panic!("segments were not loaded"),
+    }
+}
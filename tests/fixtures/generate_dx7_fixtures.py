@@ -0,0 +1,342 @@
+#!/usr/bin/env python3
+"""Generates the DX7 SysEx fixtures of the tests in `src/io/dx7.rs`.
+
+    python3 tests/fixtures/generate_dx7_fixtures.py
+
+The voices are written out parameter by parameter in the ranges of the DX7, the
+values that the tests check are noted. Regenerate the fixtures instead of editing
+the binary files. See the SysEx section of the DX7 manual for the formats.
+"""
+
+import os
+
+# parameters of the operators of the initial voice
+INIT_OPERATOR = dict(
+    rates=(99, 99, 99, 99),
+    levels=(99, 99, 99, 0),
+    breakpoint=39,  # C3
+    left_depth=0,
+    right_depth=0,
+    left_curve=0,  # 0 to 3 are -LIN, -EXP, +EXP and +LIN
+    right_curve=0,
+    rate_scaling=0,
+    amp_mod_sensitivity=0,
+    velocity_sensitivity=0,
+    output_level=0,
+    fixed=0,
+    coarse=1,
+    fine=0,
+    detune=0,  # -7 to 7
+)
+
+# global parameters of the initial voice
+INIT_VOICE = dict(
+    pitch_rates=(99, 99, 99, 99),
+    pitch_levels=(50, 50, 50, 50),
+    algorithm=1,
+    feedback=0,
+    osc_key_sync=1,
+    lfo_speed=35,
+    lfo_delay=0,
+    lfo_pitch_depth=0,
+    lfo_amp_depth=0,
+    lfo_key_sync=1,
+    lfo_waveform=0,
+    pitch_mod_sensitivity=3,
+    transpose=0,  # in semitones
+)
+
+
+def operator(**params):
+    unknown = set(params) - set(INIT_OPERATOR)
+    assert not unknown, unknown
+    return dict(INIT_OPERATOR, **params)
+
+
+def voice(name, operators, **params):
+    """The operators start with operator 1, missing operators are silent."""
+    unknown = set(params) - set(INIT_VOICE)
+    assert not unknown, unknown
+    operators = list(operators) + [operator()] * (6 - len(operators))
+    return dict(INIT_VOICE, name=name, operators=operators, **params)
+
+
+INIT = voice("INIT VOICE", [operator(output_level=99)])
+
+# the operator rates, levels and scaling are checked by the tests
+E_PIANO = voice(
+    "E.PIANO",
+    [
+        operator(
+            rates=(96, 25, 25, 67),
+            levels=(99, 75, 0, 0),
+            breakpoint=48,  # A3, key 69
+            left_depth=10,
+            right_depth=20,
+            left_curve=3,
+            right_curve=1,
+            rate_scaling=3,
+            velocity_sensitivity=2,
+            output_level=99,
+        ),
+        operator(
+            rates=(95, 50, 35, 78),
+            levels=(99, 75, 0, 0),
+            velocity_sensitivity=7,
+            output_level=58,
+            coarse=14,
+        ),
+        operator(
+            rates=(95, 20, 20, 50),
+            levels=(99, 95, 0, 0),
+            output_level=99,
+            detune=3,
+        ),
+        operator(rates=(95, 29, 20, 50), levels=(99, 95, 0, 0), output_level=89),
+        operator(
+            rates=(95, 20, 20, 50),
+            levels=(99, 95, 0, 0),
+            output_level=99,
+            detune=-3,
+        ),
+        operator(
+            rates=(95, 29, 20, 50),
+            levels=(99, 95, 0, 0),
+            amp_mod_sensitivity=1,
+            output_level=79,
+        ),
+    ],
+    algorithm=5,
+    feedback=6,
+    lfo_speed=34,
+    lfo_amp_depth=12,
+    pitch_mod_sensitivity=0,
+)
+
+# transposed an octave down, operator 1 has a coarse ratio of 0
+SYN_BASS = voice(
+    "SYN BASS",
+    [
+        operator(
+            rates=(99, 60, 35, 60),
+            levels=(99, 80, 70, 0),
+            output_level=99,
+            coarse=0,
+        ),
+        operator(rates=(99, 50, 30, 60), levels=(99, 70, 0, 0), output_level=82, coarse=0),
+        operator(rates=(99, 40, 30, 60), levels=(99, 60, 0, 0), output_level=75),
+        operator(rates=(99, 45, 30, 60), levels=(99, 65, 0, 0), output_level=70, coarse=2),
+        operator(
+            rates=(99, 50, 30, 60),
+            levels=(99, 60, 0, 0),
+            output_level=65,
+            coarse=3,
+            fine=50,
+        ),
+        operator(rates=(99, 55, 30, 60), levels=(99, 55, 0, 0), output_level=60),
+    ],
+    algorithm=16,
+    feedback=7,
+    transpose=-12,
+)
+
+# operator 5 plays a fixed frequency of 10^2.46 Hz
+TUB_BELLS = voice(
+    "TUB BELLS",
+    [
+        operator(rates=(95, 33, 71, 25), levels=(99, 0, 32, 0), output_level=95),
+        operator(
+            rates=(98, 12, 71, 28),
+            levels=(99, 0, 32, 0),
+            output_level=78,
+            coarse=3,
+            fine=50,
+        ),
+        operator(
+            rates=(95, 33, 71, 25),
+            levels=(99, 0, 32, 0),
+            output_level=95,
+            detune=3,
+        ),
+        operator(
+            rates=(98, 12, 71, 28),
+            levels=(99, 0, 32, 0),
+            output_level=78,
+            coarse=3,
+            fine=50,
+            detune=-4,
+        ),
+        operator(
+            rates=(76, 78, 71, 70),
+            levels=(99, 0, 0, 0),
+            output_level=80,
+            fixed=1,
+            coarse=2,
+            fine=46,
+        ),
+        operator(rates=(98, 91, 0, 28), levels=(99, 0, 0, 0), output_level=65, coarse=2),
+    ],
+    algorithm=5,
+    feedback=7,
+)
+
+STRINGS = voice(
+    "STRINGS",
+    [
+        operator(
+            rates=(45, 24, 20, 41),
+            levels=(99, 85, 70, 0),
+            output_level=99,
+            detune=1,
+        ),
+        operator(
+            rates=(75, 71, 17, 49),
+            levels=(82, 92, 62, 0),
+            amp_mod_sensitivity=2,
+            output_level=73,
+        ),
+        operator(
+            rates=(44, 45, 20, 54),
+            levels=(99, 85, 82, 0),
+            output_level=99,
+            detune=-1,
+        ),
+        operator(rates=(96, 19, 20, 54), levels=(99, 92, 86, 0), output_level=86),
+        operator(rates=(53, 19, 20, 54), levels=(99, 92, 86, 0), output_level=82, coarse=2),
+        operator(rates=(53, 19, 20, 54), levels=(99, 92, 86, 0), output_level=78, coarse=4),
+    ],
+    algorithm=2,
+    feedback=7,
+    lfo_speed=30,
+    lfo_delay=30,
+    lfo_pitch_depth=8,
+    lfo_amp_depth=20,
+    pitch_mod_sensitivity=4,
+)
+
+BANK = [E_PIANO, SYN_BASS, TUB_BELLS, STRINGS] + [INIT] * 28
+
+# the single voice dump
+BRASS = voice(
+    "BRASS",
+    [
+        operator(rates=(72, 76, 99, 71), levels=(99, 88, 96, 0), output_level=98),
+        operator(rates=(62, 51, 29, 71), levels=(82, 95, 96, 0), output_level=86),
+        operator(
+            rates=(77, 76, 82, 71),
+            levels=(99, 98, 98, 0),
+            output_level=99,
+            detune=2,
+        ),
+        operator(
+            rates=(77, 36, 41, 71),
+            levels=(99, 98, 98, 0),
+            output_level=99,
+            detune=-2,
+        ),
+        operator(
+            rates=(77, 36, 41, 71),
+            levels=(99, 98, 98, 0),
+            output_level=98,
+            detune=-1,
+        ),
+        operator(rates=(49, 99, 28, 68), levels=(98, 98, 91, 0), output_level=82, detune=1),
+    ],
+    algorithm=22,
+    feedback=7,
+    lfo_speed=37,
+    lfo_pitch_depth=5,
+)
+
+
+def name_bytes(voice):
+    return voice["name"].ljust(10).encode("ascii")
+
+
+def unpacked(voice):
+    """The 155 bytes of a single voice dump, the parameters of operator 6 come first."""
+    data = []
+    for op in reversed(voice["operators"]):
+        data += op["rates"] + op["levels"]
+        data += [
+            op["breakpoint"],
+            op["left_depth"],
+            op["right_depth"],
+            op["left_curve"],
+            op["right_curve"],
+            op["rate_scaling"],
+            op["amp_mod_sensitivity"],
+            op["velocity_sensitivity"],
+            op["output_level"],
+            op["fixed"],
+            op["coarse"],
+            op["fine"],
+            op["detune"] + 7,
+        ]
+    data += voice["pitch_rates"] + voice["pitch_levels"]
+    data += [
+        voice["algorithm"] - 1,
+        voice["feedback"],
+        voice["osc_key_sync"],
+        voice["lfo_speed"],
+        voice["lfo_delay"],
+        voice["lfo_pitch_depth"],
+        voice["lfo_amp_depth"],
+        voice["lfo_key_sync"],
+        voice["lfo_waveform"],
+        voice["pitch_mod_sensitivity"],
+        voice["transpose"] + 24,
+    ]
+    return bytes(data) + name_bytes(voice)
+
+
+def packed(voice):
+    """The 128 bytes of a voice of a bulk dump."""
+    data = []
+    for op in reversed(voice["operators"]):
+        data += op["rates"] + op["levels"]
+        data += [
+            op["breakpoint"],
+            op["left_depth"],
+            op["right_depth"],
+            op["left_curve"] | op["right_curve"] << 2,
+            op["rate_scaling"] | (op["detune"] + 7) << 3,
+            op["amp_mod_sensitivity"] | op["velocity_sensitivity"] << 2,
+            op["output_level"],
+            op["fixed"] | op["coarse"] << 1,
+            op["fine"],
+        ]
+    data += voice["pitch_rates"] + voice["pitch_levels"]
+    data += [
+        voice["algorithm"] - 1,
+        voice["feedback"] | voice["osc_key_sync"] << 3,
+        voice["lfo_speed"],
+        voice["lfo_delay"],
+        voice["lfo_pitch_depth"],
+        voice["lfo_amp_depth"],
+        voice["lfo_key_sync"]
+        | voice["lfo_waveform"] << 1
+        | voice["pitch_mod_sensitivity"] << 4,
+        voice["transpose"] + 24,
+    ]
+    return bytes(data) + name_bytes(voice)
+
+
+def sysex(format, data):
+    """F0 43 <channel> <format> <byte count> <byte count> ... <checksum> F7"""
+    checksum = -sum(data) & 0x7F
+    header = bytes([0xF0, 0x43, 0x00, format, len(data) >> 7, len(data) & 0x7F])
+    return header + data + bytes([checksum, 0xF7])
+
+
+def main():
+    fixtures = os.path.dirname(os.path.abspath(__file__))
+    bank = b"".join(packed(voice) for voice in BANK)
+    with open(os.path.join(fixtures, "dx7_bank.syx"), "wb") as f:
+        f.write(sysex(0x09, bank))
+    with open(os.path.join(fixtures, "dx7_voice.syx"), "wb") as f:
+        f.write(sysex(0x00, unpacked(BRASS)))
+
+
+if __name__ == "__main__":
+    main()